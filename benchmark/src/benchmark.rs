@@ -112,12 +112,13 @@ impl<'a> Benchmark<'a> {
                 input_audio: None,
                 input_audio_format: None,
                 input_video: None,
+                input_file: None,
             };
             let messages = vec![message];
 
             // Run the model to get the answer
             let model_response = match polytheus.run(&model, messages, None).await {
-                Ok(resp) => resp,
+                Ok(completion) => completion.text,
                 Err(e) => {
                     eprintln!("Error running model {}: {}", model, e);
                     continue;
@@ -145,6 +146,7 @@ impl<'a> Benchmark<'a> {
                 input_audio: None,
                 input_audio_format: None,
                 input_video: None,
+                input_file: None,
             };
             let messages = vec![message];
 
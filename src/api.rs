@@ -1,12 +1,743 @@
+pub mod anthropic;
+pub mod multipart;
+pub mod ollama;
 pub mod open_ai;
+pub mod response_store;
+pub mod threads;
+
+use crate::polytheus::{
+    thread_store_from_env, Completion, Message, Polytheus, RunOptions, ThreadStore, WebhookHeaders,
+};
+use response_store::ResponseStore;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tracing::{info_span, Instrument};
+
+/// State shared by every API handler for the lifetime of the process.
+///
+/// Hosting methods build it once and hand it to `router` on every request, so warm
+/// invocations reuse the model catalog and its HTTP connections.
+#[derive(Debug)]
+pub struct AppState {
+    /// Catalog and runtime used to answer every request.
+    pub polytheus: Polytheus,
+
+    /// Responses API results, for `previous_response_id` chaining.
+    pub responses: Box<dyn ResponseStore>,
+
+    /// Conversations of the threads API.
+    pub threads: Box<dyn ThreadStore>,
+}
+
+impl AppState {
+    /// Build the state around an existing `Polytheus`.
+    pub fn new(polytheus: Polytheus) -> AppState {
+        AppState {
+            polytheus,
+            responses: response_store::store_from_env(),
+            threads: thread_store_from_env(),
+        }
+    }
+
+    /// Process-wide state, built on first use.
+    pub fn shared() -> Arc<AppState> {
+        static STATE: OnceLock<Arc<AppState>> = OnceLock::new();
+        STATE
+            .get_or_init(|| Arc::new(AppState::new(Polytheus::fast_fill())))
+            .clone()
+    }
+}
+
+/// What a handler may need from an incoming request besides its JSON body.
+///
+/// Kept independent of the hosting method so every handler can be called the same way.
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    /// Request headers, names lowercased.
+    headers: HashMap<String, String>,
+
+    /// HTTP method, uppercased.
+    method: Option<String>,
+}
+
+impl RequestContext {
+    /// Context of a request carrying `headers`.
+    pub fn new<'a>(headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> RequestContext {
+        RequestContext {
+            headers: headers
+                .into_iter()
+                .map(|(name, value)| (name.to_ascii_lowercase(), value.to_string()))
+                .collect(),
+            method: None,
+        }
+    }
+
+    /// The same context, for a request made with `method`.
+    pub fn with_method(mut self, method: &str) -> RequestContext {
+        self.method = Some(method.to_ascii_uppercase());
+        self
+    }
+
+    /// HTTP method of the request; POST when unknown, as every route but the threads
+    /// API only takes POST.
+    pub fn method(&self) -> &str {
+        self.method.as_deref().unwrap_or("POST")
+    }
+
+    /// Value of the header `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// Content type of the request body.
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("content-type")
+    }
+
+    /// Whether the client opted out of the response cache, with `Cache-Control: no-cache`
+    /// (or `no-store`) or `X-Polytheus-Cache: bypass`.
+    pub fn no_cache(&self) -> bool {
+        let cache_control = self.header("cache-control").unwrap_or_default();
+        cache_control
+            .split(',')
+            .map(str::trim)
+            .any(|directive| directive == "no-cache" || directive == "no-store")
+            || self
+                .header("x-polytheus-cache")
+                .is_some_and(|v| v.eq_ignore_ascii_case("bypass"))
+    }
+}
+
+/// Response of a handler.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiResponse {
+    /// A JSON document.
+    Json(serde_json::Value),
+
+    /// Raw content, such as generated audio or plain text.
+    Binary {
+        /// Content type of `data` (e.g. "audio/mpeg").
+        content_type: String,
+        data: Vec<u8>,
+    },
+
+    /// Server-sent events, buffered and sent at once as a `text/event-stream` body.
+    EventStream(Vec<ServerEvent>),
+
+    /// A JSON error document in the shape of the API called, sent with a 400 status
+    /// instead of the default `{"error": ...}` one.
+    Error(serde_json::Value),
+}
+
+/// One server-sent event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerEvent {
+    /// Event name, for protocols that name their events.
+    pub event: Option<String>,
+
+    /// Event payload, usually a JSON document.
+    pub data: String,
+}
+
+impl ServerEvent {
+    /// Event `event` carrying the JSON document `data`.
+    pub fn json(event: &str, data: &serde_json::Value) -> ServerEvent {
+        ServerEvent {
+            event: Some(event.to_string()),
+            data: data.to_string(),
+        }
+    }
+}
+
+/// `text/event-stream` encoding of `events`.
+pub fn encode_events(events: &[ServerEvent]) -> String {
+    let mut body = String::new();
+    for event in events {
+        if let Some(name) = &event.event {
+            body.push_str(&format!("event: {}\n", name));
+        }
+        for line in event.data.lines() {
+            body.push_str(&format!("data: {}\n", line));
+        }
+        body.push('\n');
+    }
+    body
+}
+
+/// Prompt and completion tokens of `completion`: the provider's counts, or word counts
+/// as a lightweight approximation.
+pub(crate) fn token_counts(messages: &[Message], completion: &Completion) -> (u64, u64) {
+    let prompt_tokens = completion.usage.prompt_tokens.unwrap_or_else(|| {
+        messages
+            .iter()
+            .map(|m| m.input_text.split_whitespace().count() as u64)
+            .sum()
+    });
+    let completion_tokens = completion
+        .usage
+        .completion_tokens
+        .unwrap_or_else(|| completion.text.split_whitespace().count() as u64);
+    (prompt_tokens, completion_tokens)
+}
+
+/// Message of `role` made of `input_text` only.
+pub(crate) fn text_message(role: &str, input_text: &str) -> Message {
+    Message {
+        role: role.to_string(),
+        input_text: input_text.to_string(),
+        input_image: None,
+        input_audio: None,
+        input_audio_format: None,
+        input_video: None,
+        input_file: None,
+    }
+}
+
+impl RunOptions {
+    /// Options of a run requested with `context`: the cache opt-out, the
+    /// `X-Polytheus-Tenant` header and the non-standard `timeout` (in seconds) of `body`.
+    ///
+    /// Sampling parameters are left to the handlers, as every API spells them its own way.
+    pub(crate) fn from_request(context: &RequestContext, body: &serde_json::Value) -> RunOptions {
+        RunOptions {
+            timeout: body["timeout"]
+                .as_f64()
+                .filter(|secs| *secs > 0.0)
+                .map(Duration::from_secs_f64),
+            no_cache: context.no_cache(),
+            tenant: context.header("x-polytheus-tenant").map(str::to_string),
+            ..RunOptions::default()
+        }
+    }
+}
+
+/// Error returned to the client when a Polytheus run fails with `e`.
+pub(crate) fn run_error(e: String) -> String {
+    "Something go wrong with the Polytheus run. Polytheus error: ".to_string() + &e
+}
+
+/// Parse a JSON request body.
+fn json_body(body: &[u8]) -> Result<serde_json::Value, String> {
+    serde_json::from_slice(body).map_err(|e| {
+        "Error to parsing the body of the request. deserialize error: ".to_string() + &e.to_string()
+    })
+}
 
 /// This function routes the incoming API requests to the appropriate handler based on the path.
+///
+/// `body` is the raw request body: JSON for most routes, multipart for uploads.
 pub async fn router(
+    state: &AppState,
     path: &str,
-    structBody: serde_json::Value,
-) -> Result<serde_json::Value, String> {
-    match path {
-        "/v1/chat/completions" => open_ai::ChatCompletions(structBody).await,
-        _ => Err(format!("Unknown API path: {}", path)),
+    context: &RequestContext,
+    body: &[u8],
+) -> Result<ApiResponse, String> {
+    let span = info_span!("api.router", path);
+    async move {
+        match path {
+            "/v1/chat/completions" => open_ai::ChatCompletions(state, context, json_body(body)?)
+                .await
+                .map(ApiResponse::Json),
+            "/v1/completions" => open_ai::Completions(state, context, json_body(body)?)
+                .await
+                .map(ApiResponse::Json),
+            "/v1/responses" => open_ai::Responses(state, context, json_body(body)?).await,
+            "/v1/messages" => {
+                let response = match json_body(body) {
+                    Ok(body) => anthropic::Messages(state, context, body).await,
+                    Err(e) => Err(e),
+                };
+                Ok(response.unwrap_or_else(|e| anthropic::error("invalid_request_error", &e)))
+            }
+            "/v1/embeddings" => open_ai::Embeddings(state, context, json_body(body)?)
+                .await
+                .map(ApiResponse::Json),
+            "/v1/images/generations" => {
+                open_ai::ImagesGenerations(state, context, json_body(body)?)
+                    .await
+                    .map(ApiResponse::Json)
+            }
+            "/v1/images/edits" => open_ai::ImagesEdits(state, context, json_body(body)?)
+                .await
+                .map(ApiResponse::Json),
+            "/v1/audio/transcriptions" => open_ai::AudioTranscriptions(state, context, body).await,
+            "/v1/audio/speech" => open_ai::AudioSpeech(state, context, json_body(body)?).await,
+            "/api/chat" => ollama::Chat(state, context, json_body(body)?).await,
+            "/api/generate" => ollama::Generate(state, context, json_body(body)?).await,
+            "/api/tags" => ollama::Tags(state).map(ApiResponse::Json),
+            "/v1/threads" => threads::Threads(state, context, body).map(ApiResponse::Json),
+            thread if thread.starts_with("/v1/threads/") => {
+                threads::Thread(state, context, &thread["/v1/threads/".len()..], body)
+                    .await
+                    .map(ApiResponse::Json)
+            }
+            "/v1/webhooks/replicate" => {
+                let header = |name: &str| {
+                    context
+                        .header(name)
+                        .ok_or(format!("Replicate webhook is missing the {} header", name))
+                };
+                let headers = WebhookHeaders {
+                    id: header("webhook-id")?,
+                    timestamp: header("webhook-timestamp")?,
+                    signature: header("webhook-signature")?,
+                };
+                state.polytheus.receive_replicate_webhook(&headers, body)?;
+                Ok(ApiResponse::Json(serde_json::json!({ "received": true })))
+            }
+            _ => Err(format!("Unknown API path: {}", path)),
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod api_tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Instant;
+
+    /// Route a JSON `body` to `path`, expecting a JSON response; API-shaped error
+    /// documents are returned as errors.
+    async fn call(path: &str, body: serde_json::Value) -> Result<serde_json::Value, String> {
+        let state = AppState::shared();
+        let body = serde_json::to_vec(&body).unwrap();
+        match router(&state, path, &RequestContext::default(), &body).await? {
+            ApiResponse::Json(value) => Ok(value),
+            ApiResponse::Error(document) => Err(document.to_string()),
+            other => Err(format!("unexpected response: {:?}", other)),
+        }
+    }
+
+    #[test]
+    fn test_shared_state_is_built_once() {
+        let start = Instant::now();
+
+        assert!(Arc::ptr_eq(&AppState::shared(), &AppState::shared()));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_shared_state_is_built_once took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_router_unknown_path() {
+        let start = Instant::now();
+
+        let result = call("/v1/unknown", json!({})).await;
+        assert_eq!(result, Err("Unknown API path: /v1/unknown".to_string()));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_router_unknown_path took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_request_context_cache_opt_out() {
+        let start = Instant::now();
+
+        assert!(!RequestContext::default().no_cache());
+        assert!(RequestContext::new([("Cache-Control", "max-age=0, no-cache")]).no_cache());
+        assert!(RequestContext::new([("X-Polytheus-Cache", "BYPASS")]).no_cache());
+        assert_eq!(
+            RequestContext::new([("X-Request-Id", "abc")]).header("x-request-id"),
+            Some("abc")
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_request_context_cache_opt_out took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_chat_generation_params() {
+        let start = Instant::now();
+
+        let messages = json!([{ "role": "user", "content": "hi" }]);
+        let unsupported = call(
+            "/v1/chat/completions",
+            json!({ "model": "claude-4-sonnet", "messages": messages, "seed": 1 }),
+        )
+        .await;
+        assert!(unsupported
+            .unwrap_err()
+            .ends_with("Parameters not supported by model 'claude-4-sonnet': seed"));
+
+        let out_of_range = call(
+            "/v1/chat/completions",
+            json!({ "model": "gpt-4o", "messages": messages, "top_p": 1.5 }),
+        )
+        .await;
+        assert!(out_of_range
+            .unwrap_err()
+            .ends_with("top_p must be between 0 and 1, got 1.5"));
+
+        let bad_stop = call(
+            "/v1/chat/completions",
+            json!({ "model": "gpt-4o", "messages": messages, "stop": 3 }),
+        )
+        .await;
+        assert_eq!(
+            bad_stop,
+            Err("stop must be a string or an array of strings".to_string())
+        );
+
+        let too_many = call(
+            "/v1/chat/completions",
+            json!({ "model": "gpt-4o", "messages": messages, "n": 11 }),
+        )
+        .await;
+        assert!(too_many
+            .unwrap_err()
+            .ends_with("n must be between 1 and 10, got 11"));
+
+        let no_logprobs = call(
+            "/v1/chat/completions",
+            json!({ "model": "gpt-5-codex", "messages": messages, "logprobs": true }),
+        )
+        .await;
+        assert!(no_logprobs
+            .unwrap_err()
+            .ends_with("Parameters not supported by model 'gpt-5-codex': logprobs"));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_chat_generation_params took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_responses_validation() {
+        let start = Instant::now();
+
+        let unknown_previous = call(
+            "/v1/responses",
+            json!({ "model": "gpt-4o", "input": "hi", "previous_response_id": "resp_nope" }),
+        )
+        .await;
+        assert!(unknown_previous
+            .unwrap_err()
+            .starts_with("Previous response 'resp_nope' not found: responses expire"));
+
+        let tool_output = call(
+            "/v1/responses",
+            json!({
+                "model": "gpt-4o",
+                "input": [{ "type": "function_call_output", "call_id": "c", "output": "42" }]
+            }),
+        )
+        .await;
+        assert_eq!(
+            tool_output,
+            Err("Unsupported input item type: function_call_output".to_string())
+        );
+
+        let missing_input = call("/v1/responses", json!({ "model": "gpt-4o" })).await;
+        assert_eq!(missing_input, Err("you are missing the input".to_string()));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_responses_validation took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_messages_validation() {
+        let start = Instant::now();
+
+        let anthropic_error = |kind: &str, message: &str| {
+            Err(
+                json!({ "type": "error", "error": { "type": kind, "message": message } })
+                    .to_string(),
+            )
+        };
+
+        let missing_max_tokens = call(
+            "/v1/messages",
+            json!({ "model": "gpt-4o", "messages": [{ "role": "user", "content": "hi" }] }),
+        )
+        .await;
+        assert_eq!(
+            missing_max_tokens,
+            anthropic_error(
+                "invalid_request_error",
+                "max_tokens must be a positive integer"
+            )
+        );
+
+        let no_thinking = call(
+            "/v1/messages",
+            json!({
+                "model": "gpt-4o",
+                "max_tokens": 1024,
+                "thinking": { "type": "enabled", "budget_tokens": 2048 },
+                "messages": [{ "role": "user", "content": "hi" }]
+            }),
+        )
+        .await;
+        assert_eq!(
+            no_thinking,
+            anthropic_error(
+                "api_error",
+                "Something go wrong with the Polytheus run. Polytheus error: Model 'gpt-4o' doesn't support thinking"
+            )
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_messages_validation took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_completions_and_ollama_validation() {
+        let start = Instant::now();
+
+        let missing_prompt = call("/v1/completions", json!({ "model": "gpt-4o" })).await;
+        assert_eq!(
+            missing_prompt,
+            Err("you are missing the prompt".to_string())
+        );
+
+        let tags = call("/api/tags", json!({})).await.unwrap();
+        let names: Vec<&str> = tags["models"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|model| model["name"].as_str())
+            .collect();
+        assert!(names.contains(&"gpt-4o"));
+        assert!(!names.contains(&"flux-schnell"));
+
+        let unknown_format = call(
+            "/api/generate",
+            json!({ "model": "gpt-4o:latest", "prompt": "hi", "format": "yaml" }),
+        )
+        .await;
+        assert_eq!(
+            unknown_format,
+            Err("Unsupported format: \"yaml\"".to_string())
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_completions_and_ollama_validation took: {:?}",
+            duration
+        );
+    }
+
+    #[tokio::test]
+    async fn test_threads_flow() {
+        let start = Instant::now();
+
+        let state = AppState::new(Polytheus::fast_fill());
+        let request_as = |tenant: &str, method: &str, path: &str, body: serde_json::Value| {
+            let context = RequestContext::new([("X-Polytheus-Tenant", tenant)]).with_method(method);
+            let body = match body {
+                serde_json::Value::Null => vec![],
+                body => serde_json::to_vec(&body).unwrap(),
+            };
+            let state = &state;
+            let path = path.to_string();
+            async move {
+                match router(state, &path, &context, &body).await? {
+                    ApiResponse::Json(value) => Ok(value),
+                    other => Err(format!("unexpected response: {:?}", other)),
+                }
+            }
+        };
+        let request = |method: &str, path: &str, body: serde_json::Value| {
+            request_as("acme", method, path, body)
+        };
+
+        let thread = request(
+            "POST",
+            "/v1/threads",
+            json!({ "metadata": { "flow": "research" }, "messages": [
+                { "role": "system", "content": "You are a researcher." }
+            ]}),
+        )
+        .await
+        .unwrap();
+        let id = thread["id"].as_str().unwrap();
+        let messages_path = format!("/v1/threads/{}/messages", id);
+
+        let appended = request(
+            "POST",
+            &messages_path,
+            json!({ "messages": [{ "role": "user", "content": "Find sources" }] }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(appended["data"][0]["content"][0]["text"], "Find sources");
+
+        let unknown_model = request(
+            "POST",
+            &messages_path,
+            json!({ "model": "nope", "messages": [{ "role": "user", "content": "More" }] }),
+        )
+        .await;
+        assert!(unknown_model
+            .unwrap_err()
+            .ends_with("Model 'nope' not found"));
+
+        let messages = request("GET", &messages_path, json!(null)).await.unwrap();
+        assert_eq!(messages["data"].as_array().unwrap().len(), 2);
+        let listed = request("GET", "/v1/threads", json!(null)).await.unwrap();
+        assert_eq!(listed["data"][0]["message_count"], 2);
+        assert_eq!(listed["data"][0]["metadata"], json!({ "flow": "research" }));
+
+        let thread_path = format!("/v1/threads/{}", id);
+        let other_tenant = request_as("globex", "GET", &thread_path, json!(null)).await;
+        assert_eq!(other_tenant, Err(format!("Thread '{}' not found", id)));
+        let other_listing = request_as("globex", "GET", "/v1/threads", json!(null)).await;
+        assert_eq!(other_listing.unwrap()["data"], json!([]));
+        let anonymous_listing = router(
+            &state,
+            "/v1/threads",
+            &RequestContext::default().with_method("GET"),
+            &[],
+        )
+        .await;
+        assert_eq!(
+            anonymous_listing,
+            Err("Listing threads needs the X-Polytheus-Tenant header".to_string())
+        );
+
+        let deleted = request("DELETE", &thread_path, json!(null)).await.unwrap();
+        assert_eq!(deleted["deleted"], true);
+        assert_eq!(
+            request("GET", &thread_path, json!(null)).await,
+            Err(format!("Thread '{}' not found", id))
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_threads_flow took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_encode_events() {
+        let start = Instant::now();
+
+        let events = [
+            ServerEvent::json("message_stop", &json!({ "type": "message_stop" })),
+            ServerEvent {
+                event: None,
+                data: "[DONE]".to_string(),
+            },
+        ];
+        assert_eq!(
+            encode_events(&events),
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\ndata: [DONE]\n\n"
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_encode_events took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_embeddings_validation() {
+        let start = Instant::now();
+
+        let not_embedding = call(
+            "/v1/embeddings",
+            json!({ "model": "gpt-4o", "input": "hi" }),
+        )
+        .await;
+        assert!(not_embedding
+            .unwrap_err()
+            .contains("is not an embedding model"));
+
+        let bad_format = call(
+            "/v1/embeddings",
+            json!({
+                "model": "text-embedding-3-small",
+                "input": ["a", "b"],
+                "encoding_format": "int8"
+            }),
+        )
+        .await;
+        assert_eq!(
+            bad_format,
+            Err("Unsupported encoding_format: int8".to_string())
+        );
+
+        let too_large = call(
+            "/v1/embeddings",
+            json!({
+                "model": "text-embedding-3-small",
+                "input": "hi",
+                "dimensions": 4096
+            }),
+        )
+        .await;
+        assert!(too_large
+            .unwrap_err()
+            .contains("dimensions must be between 1 and 1536"));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_embeddings_validation took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_images_validation() {
+        let start = Instant::now();
+
+        let text_model = call(
+            "/v1/images/generations",
+            json!({ "model": "gpt-4o", "prompt": "a cat" }),
+        )
+        .await;
+        assert!(text_model.unwrap_err().contains("doesn't generate images"));
+
+        let cannot_edit = call(
+            "/v1/images/edits",
+            json!({
+                "model": "flux-schnell",
+                "prompt": "make it blue",
+                "image": "https://example.com/cat.png"
+            }),
+        )
+        .await;
+        assert!(cannot_edit.unwrap_err().contains("can't edit images"));
+
+        let missing_image = call("/v1/images/edits", json!({ "prompt": "make it blue" })).await;
+        assert_eq!(missing_image, Err("you are missing the image".to_string()));
+
+        let wrapped_n = call(
+            "/v1/images/generations",
+            json!({ "model": "flux-schnell", "prompt": "a cat", "n": 4294967297u64 }),
+        )
+        .await;
+        assert_eq!(wrapped_n, Err("n must be a positive integer".to_string()));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_images_validation took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_audio_validation() {
+        let start = Instant::now();
+
+        let state = AppState::shared();
+        let context = RequestContext::new([("Content-Type", "multipart/form-data; boundary=b")]);
+        let body =
+            b"--b\r\nContent-Disposition: form-data; name=\"model\"\r\n\r\ngpt-4o\r\n--b--\r\n";
+        let missing_file = router(&state, "/v1/audio/transcriptions", &context, body).await;
+        assert_eq!(missing_file, Err("you are missing the file".to_string()));
+
+        let not_multipart = router(
+            &state,
+            "/v1/audio/transcriptions",
+            &RequestContext::default(),
+            b"{}",
+        )
+        .await;
+        assert!(not_multipart.unwrap_err().contains("multipart/form-data"));
+
+        let text_model = call(
+            "/v1/audio/speech",
+            json!({ "model": "gpt-4o", "input": "hello", "voice": "alloy" }),
+        )
+        .await;
+        assert!(text_model.unwrap_err().contains("doesn't generate audio"));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_audio_validation took: {:?}", duration);
     }
 }
@@ -0,0 +1,506 @@
+//! Anthropic Messages API on top of the Polytheus backend, so clients built on the
+//! Anthropic SDK can use any model of the catalog.
+//!
+//! Streaming answers are buffered: the whole event sequence is produced once the run
+//! is over and sent as a single `text/event-stream` body.
+//!
+//! Errors have the Anthropic shape (`{"type": "error", "error": {...}}`, see `error`).
+use crate::api::{
+    run_error, text_message, token_counts, ApiResponse, AppState, RequestContext, ServerEvent,
+};
+use crate::polytheus::{
+    Completion, FinishReason, GenerationParams, Message, RunOptions, ThinkingLevel,
+};
+use crate::telemetry::otel;
+use serde_json::{json, Value};
+
+/// Handles Anthropic API that use messages endpoint.
+///
+/// `max_tokens` is required, as upstream. `top_k` has no equivalent and is rejected.
+/// `thinking.budget_tokens` is sent as is to models taking a token budget, and as the
+/// closest effort level to the others (see `ThinkingLevel::honored_by`).
+pub async fn Messages(
+    state: &AppState,
+    context: &RequestContext,
+    structBody: serde_json::Value,
+) -> Result<ApiResponse, String> {
+    let polytheus = &state.polytheus;
+    let model_name = structBody["model"]
+        .as_str()
+        .ok_or("you are missing the model name".to_string())?;
+    let max_tokens = structBody["max_tokens"]
+        .as_u64()
+        .and_then(|max_tokens| u32::try_from(max_tokens).ok())
+        .filter(|max_tokens| *max_tokens > 0)
+        .ok_or("max_tokens must be a positive integer".to_string())?;
+    let stop = match &structBody["stop_sequences"] {
+        Value::Null => None,
+        Value::Array(items) => Some(
+            items
+                .iter()
+                .map(|item| item.as_str().map(|s| s.to_string()))
+                .collect::<Option<_>>()
+                .ok_or("stop_sequences must be an array of strings".to_string())?,
+        ),
+        _ => return Err("stop_sequences must be an array of strings".to_string()),
+    };
+    if !structBody["top_k"].is_null() {
+        return Err("top_k is not supported".to_string());
+    }
+    let params = GenerationParams {
+        max_tokens: Some(max_tokens),
+        stop,
+        temperature: structBody["temperature"].as_f64(),
+        top_p: structBody["top_p"].as_f64(),
+        ..GenerationParams::default()
+    };
+    let thinking_level = match structBody["thinking"]["type"].as_str() {
+        None | Some("disabled") => None,
+        Some("enabled") => Some(match structBody["thinking"]["budget_tokens"].as_u64() {
+            Some(budget) => ThinkingLevel::Budget(
+                u32::try_from(budget).map_err(|_| "budget_tokens is too large".to_string())?,
+            ),
+            None => ThinkingLevel::Medium,
+        }),
+        Some(other) => return Err(format!("Unsupported thinking type: {}", other)),
+    };
+    // models without a token budget think at the level holding `budget_tokens`
+    let thinking_level =
+        thinking_level.map(|level| match polytheus.get_model_by_name(model_name) {
+            Some(model) => level.honored_by(model),
+            None => level,
+        });
+    let options = RunOptions {
+        params,
+        ..RunOptions::from_request(context, &structBody)
+    };
+
+    let mut messages = vec![];
+    match &structBody["system"] {
+        Value::Null => {}
+        system => messages.push(text_message("system", &block_text(system)?)),
+    }
+    for message_json in structBody["messages"]
+        .as_array()
+        .ok_or("you are missing the messages".to_string())?
+    {
+        messages.push(message(message_json)?);
+    }
+
+    let completion = match polytheus
+        .run_with_options(model_name, messages.clone(), thinking_level, &options)
+        .await
+    {
+        Ok(completion) => completion,
+        Err(e) => return Ok(error("api_error", &run_error(e))),
+    };
+
+    let stream = structBody["stream"].as_bool().unwrap_or(false);
+    Ok(response(
+        model_name,
+        &messages,
+        &completion,
+        thinking_level.is_some(),
+        stream,
+    ))
+}
+
+/// Anthropic error response of `kind` (e.g. "invalid_request_error", "api_error").
+pub fn error(kind: &str, message: &str) -> ApiResponse {
+    ApiResponse::Error(error_document(kind, message))
+}
+
+/// Anthropic error document, the body of an error response or of an `error` event.
+fn error_document(kind: &str, message: &str) -> Value {
+    json!({ "type": "error", "error": { "type": kind, "message": message } })
+}
+
+/// Anthropic response to `messages`, a JSON message or its `stream` of events.
+///
+/// With `thinking`, the reasoning of the model comes first as a `thinking` block. It is
+/// sent with an empty signature, as no upstream signature is available.
+///
+/// A run that stopped on an upstream error is an `api_error`: an error response, or,
+/// when streaming, an `error` event in place of the end of the message.
+fn response(
+    model_name: &str,
+    messages: &[Message],
+    completion: &Completion,
+    thinking: bool,
+    stream: bool,
+) -> ApiResponse {
+    let (input_tokens, output_tokens) = token_counts(messages, completion);
+    let cache_read_input_tokens = if completion.cached { input_tokens } else { 0 };
+    let text = &completion.text;
+    let reasoning = completion.reasoning.as_deref().filter(|_| thinking);
+    let stop_reason = stop_reason(completion);
+    let failure = "The model stopped on an upstream error";
+    let stop_sequence = &completion.stop_sequence;
+    let usage = json!({
+        "input_tokens": input_tokens,
+        "output_tokens": output_tokens,
+        "cache_creation_input_tokens": 0,
+        "cache_read_input_tokens": cache_read_input_tokens,
+        "cost": completion.usage.cost
+    });
+    let id = format!("msg_{}", otel::random_id(12));
+
+    if !stream {
+        if stop_reason.is_none() {
+            return error("api_error", failure);
+        }
+        let mut content = vec![];
+        if let Some(reasoning) = reasoning {
+            content.push(json!({ "type": "thinking", "thinking": reasoning, "signature": "" }));
+        }
+        content.push(json!({ "type": "text", "text": text }));
+        return ApiResponse::Json(json!({
+            "id": id,
+            "type": "message",
+            "role": "assistant",
+            "model": completion.model(model_name),
+            "content": content,
+            "stop_reason": stop_reason,
+            "stop_sequence": stop_sequence,
+            "usage": usage
+        }));
+    }
+
+    let mut events = vec![ServerEvent::json(
+        "message_start",
+        &json!({
+            "type": "message_start",
+            "message": {
+                "id": id,
+                "type": "message",
+                "role": "assistant",
+                "model": completion.model(model_name),
+                "content": [],
+                "stop_reason": Value::Null,
+                "stop_sequence": Value::Null,
+                "usage": {
+                    "input_tokens": input_tokens,
+                    "output_tokens": 0,
+                    "cache_creation_input_tokens": 0,
+                    "cache_read_input_tokens": cache_read_input_tokens
+                }
+            }
+        }),
+    )];
+    let mut blocks = vec![];
+    if let Some(reasoning) = reasoning {
+        blocks.push((
+            json!({ "type": "thinking", "thinking": "", "signature": "" }),
+            json!({ "type": "thinking_delta", "thinking": reasoning }),
+        ));
+    }
+    blocks.push((
+        json!({ "type": "text", "text": "" }),
+        json!({ "type": "text_delta", "text": text }),
+    ));
+    for (index, (block, delta)) in blocks.into_iter().enumerate() {
+        events.push(ServerEvent::json(
+            "content_block_start",
+            &json!({ "type": "content_block_start", "index": index, "content_block": block }),
+        ));
+        events.push(ServerEvent::json(
+            "content_block_delta",
+            &json!({ "type": "content_block_delta", "index": index, "delta": delta }),
+        ));
+        events.push(ServerEvent::json(
+            "content_block_stop",
+            &json!({ "type": "content_block_stop", "index": index }),
+        ));
+    }
+    if stop_reason.is_none() {
+        events.push(ServerEvent::json(
+            "error",
+            &error_document("api_error", failure),
+        ));
+        return ApiResponse::EventStream(events);
+    }
+    events.push(ServerEvent::json(
+        "message_delta",
+        &json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": stop_reason, "stop_sequence": stop_sequence },
+            "usage": usage
+        }),
+    ));
+    events.push(ServerEvent::json(
+        "message_stop",
+        &json!({ "type": "message_stop" }),
+    ));
+    ApiResponse::EventStream(events)
+}
+
+/// Anthropic `stop_reason` of `completion`.
+///
+/// The matched stop sequence isn't reported by every provider, so runs ending on one
+/// are only reported as `stop_sequence` when it is known, and as `end_turn` otherwise.
+fn stop_reason(completion: &Completion) -> Option<&'static str> {
+    match completion.finish_reason {
+        FinishReason::Stop if completion.stop_sequence.is_some() => Some("stop_sequence"),
+        FinishReason::Stop => Some("end_turn"),
+        FinishReason::Length => Some("max_tokens"),
+        FinishReason::ContentFilter => Some("refusal"),
+        FinishReason::ToolCalls => Some("tool_use"),
+        FinishReason::Error => None,
+    }
+}
+
+/// Text of a `system` prompt or of a message content: a string or a list of blocks.
+///
+/// `thinking` blocks echoed back by clients are dropped; other blocks are rejected.
+fn block_text(content: &Value) -> Result<String, String> {
+    match content {
+        Value::String(text) => Ok(text.clone()),
+        Value::Array(blocks) => {
+            let mut texts: Vec<&str> = vec![];
+            for block in blocks {
+                match block["type"].as_str() {
+                    Some("text") => texts.push(
+                        block["text"]
+                            .as_str()
+                            .ok_or("you are missing the text of a text block")?,
+                    ),
+                    Some("thinking" | "redacted_thinking") => {}
+                    other => {
+                        return Err(format!(
+                            "Unsupported content block type: {}",
+                            other.unwrap_or("none")
+                        ))
+                    }
+                }
+            }
+            Ok(texts.join(" "))
+        }
+        _ => Err("you are missing the content".to_string()),
+    }
+}
+
+/// Polytheus message of an Anthropic message, whose content may hold one image block.
+fn message(message_json: &Value) -> Result<Message, String> {
+    let role = message_json["role"]
+        .as_str()
+        .ok_or("you are missing the role")?;
+    let content = &message_json["content"];
+    let Value::Array(blocks) = content else {
+        return Ok(text_message(role, &block_text(content)?));
+    };
+    let (images, others): (Vec<&Value>, Vec<&Value>) = blocks
+        .iter()
+        .partition(|block| block["type"].as_str() == Some("image"));
+    if images.len() > 1 {
+        return Err("only one image per message is supported".to_string());
+    }
+    let mut message = text_message(
+        role,
+        &block_text(&Value::Array(others.into_iter().cloned().collect()))?,
+    );
+    if let Some(image) = images.first() {
+        message.input_image = Some(image_source(&image["source"])?);
+    }
+    Ok(message)
+}
+
+/// URL, or data URL, of the `source` of an image block.
+fn image_source(source: &Value) -> Result<String, String> {
+    match source["type"].as_str() {
+        Some("base64") => {
+            let media_type = source["media_type"]
+                .as_str()
+                .ok_or("you are missing the media_type of the image")?;
+            let data = source["data"]
+                .as_str()
+                .ok_or("you are missing the data of the image")?;
+            Ok(format!("data:{};base64,{}", media_type, data))
+        }
+        Some("url") => source["url"]
+            .as_str()
+            .map(|url| url.to_string())
+            .ok_or("you are missing the url of the image".to_string()),
+        other => Err(format!(
+            "Unsupported image source type: {}",
+            other.unwrap_or("none")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod anthropic_tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_message_blocks() {
+        let start = Instant::now();
+
+        let message = message(&json!({
+            "role": "user",
+            "content": [
+                { "type": "text", "text": "What is this?" },
+                { "type": "image", "source": {
+                    "type": "base64", "media_type": "image/png", "data": "AAAA"
+                }}
+            ]
+        }))
+        .unwrap();
+        assert_eq!(message.input_text, "What is this?");
+        assert_eq!(
+            message.input_image.as_deref(),
+            Some("data:image/png;base64,AAAA")
+        );
+
+        let tool_use = block_text(&json!([{ "type": "tool_use", "id": "t", "name": "f" }]));
+        assert_eq!(
+            tool_use,
+            Err("Unsupported content block type: tool_use".to_string())
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_message_blocks took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_stop_reason() {
+        let start = Instant::now();
+
+        let finished = |finish_reason: FinishReason, stop_sequence: Option<&str>| Completion {
+            finish_reason,
+            stop_sequence: stop_sequence.map(str::to_string),
+            ..Completion::default()
+        };
+        assert_eq!(
+            stop_reason(&finished(FinishReason::Stop, None)),
+            Some("end_turn")
+        );
+        assert_eq!(
+            stop_reason(&finished(FinishReason::Stop, Some("END"))),
+            Some("stop_sequence")
+        );
+        assert_eq!(
+            stop_reason(&finished(FinishReason::Length, None)),
+            Some("max_tokens")
+        );
+        assert_eq!(
+            stop_reason(&finished(FinishReason::ContentFilter, None)),
+            Some("refusal")
+        );
+        assert_eq!(stop_reason(&finished(FinishReason::Error, None)), None);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_stop_reason took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_errors_have_the_anthropic_shape() {
+        let start = Instant::now();
+
+        let messages = vec![text_message("user", "Count to 3")];
+        let failed = Completion {
+            text: "1, 2".to_string(),
+            finish_reason: FinishReason::Error,
+            ..Completion::default()
+        };
+        let upstream_error = json!({
+            "type": "error",
+            "error": { "type": "api_error", "message": "The model stopped on an upstream error" }
+        });
+        assert_eq!(
+            response("claude-4-sonnet", &messages, &failed, false, false),
+            ApiResponse::Error(upstream_error.clone())
+        );
+        let ApiResponse::EventStream(events) =
+            response("claude-4-sonnet", &messages, &failed, false, true)
+        else {
+            panic!("expected events");
+        };
+        assert_eq!(
+            events.last(),
+            Some(&ServerEvent::json("error", &upstream_error))
+        );
+        assert!(!events
+            .iter()
+            .any(|event| event.event.as_deref() == Some("message_stop")));
+
+        let state = crate::api::AppState::shared();
+        let invalid = crate::api::router(
+            &state,
+            "/v1/messages",
+            &RequestContext::default(),
+            br#"{ "max_tokens": 16 }"#,
+        )
+        .await;
+        assert_eq!(
+            invalid,
+            Ok(ApiResponse::Error(json!({
+                "type": "error",
+                "error": {
+                    "type": "invalid_request_error",
+                    "message": "you are missing the model name"
+                }
+            })))
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_errors_have_the_anthropic_shape took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_thinking_and_stop_sequence_are_returned() {
+        let start = Instant::now();
+
+        let messages = vec![text_message("user", "Count to 3")];
+        let completion = Completion {
+            text: "1, 2, 3".to_string(),
+            reasoning: Some("Counting is easy.".to_string()),
+            stop_sequence: Some("4".to_string()),
+            ..Completion::default()
+        };
+        let ApiResponse::Json(message) =
+            response("claude-4-sonnet", &messages, &completion, true, false)
+        else {
+            panic!("expected a JSON message");
+        };
+        assert_eq!(
+            message["content"],
+            json!([
+                { "type": "thinking", "thinking": "Counting is easy.", "signature": "" },
+                { "type": "text", "text": "1, 2, 3" }
+            ])
+        );
+        assert_eq!(message["stop_reason"], "stop_sequence");
+        assert_eq!(message["stop_sequence"], "4");
+
+        let ApiResponse::Json(without_thinking) =
+            response("claude-4-sonnet", &messages, &completion, false, false)
+        else {
+            panic!("expected a JSON message");
+        };
+        assert_eq!(without_thinking["content"][0]["type"], "text");
+
+        let ApiResponse::EventStream(events) =
+            response("claude-4-sonnet", &messages, &completion, true, true)
+        else {
+            panic!("expected events");
+        };
+        let data: Vec<Value> = events
+            .iter()
+            .map(|event| serde_json::from_str(&event.data).unwrap())
+            .collect();
+        assert_eq!(
+            data[2]["delta"],
+            json!({ "type": "thinking_delta", "thinking": "Counting is easy." })
+        );
+        assert_eq!(data[5]["index"], 1);
+        assert_eq!(data[5]["delta"]["text"], "1, 2, 3");
+        assert_eq!(data[7]["delta"]["stop_sequence"], "4");
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_thinking_and_stop_sequence_are_returned took: {:?}",
+            duration
+        );
+    }
+}
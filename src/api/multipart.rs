@@ -0,0 +1,160 @@
+//! Minimal `multipart/form-data` parser (RFC 7578), enough for OpenAI-style uploads.
+//!
+//! The whole body is already in memory (Lambda delivers it at once), so parts are
+//! sliced out of it rather than streamed.
+
+/// One field of a multipart body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    /// Name of the form field.
+    pub name: String,
+
+    /// File name, for file fields.
+    pub filename: Option<String>,
+
+    /// Content type of the part, when given.
+    pub content_type: Option<String>,
+
+    /// Raw content of the part.
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    /// Content of a text field.
+    pub fn text(&self) -> Result<&str, String> {
+        std::str::from_utf8(&self.data)
+            .map(str::trim)
+            .map_err(|_| format!("field '{}' is not valid UTF-8", self.name))
+    }
+}
+
+/// Boundary declared in a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<&str> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// Split `body` into its parts, using the boundary declared in `content_type`.
+pub fn parse(content_type: &str, body: &[u8]) -> Result<Vec<Part>, String> {
+    let boundary = boundary(content_type)
+        .ok_or_else(|| "expected a multipart/form-data body with a boundary".to_string())?;
+    let delimiter = format!("--{}", boundary).into_bytes();
+
+    let mut parts = vec![];
+    let mut rest = match find(body, &delimiter) {
+        Some(at) => &body[at + delimiter.len()..],
+        None => return Err("multipart body without boundary".to_string()),
+    };
+    loop {
+        // "--" right after a delimiter closes the body
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        rest = rest
+            .strip_prefix(b"\r\n")
+            .ok_or_else(|| "malformed multipart delimiter".to_string())?;
+        let end =
+            find(rest, &delimiter).ok_or_else(|| "multipart body is not terminated".to_string())?;
+        // the CRLF before the next delimiter belongs to the delimiter
+        let part = rest[..end]
+            .strip_suffix(b"\r\n")
+            .ok_or_else(|| "malformed multipart part".to_string())?;
+        parts.push(parse_part(part)?);
+        rest = &rest[end + delimiter.len()..];
+    }
+}
+
+/// Parse the headers and content of a single part.
+fn parse_part(part: &[u8]) -> Result<Part, String> {
+    let split =
+        find(part, b"\r\n\r\n").ok_or_else(|| "multipart part without headers".to_string())?;
+    let headers = std::str::from_utf8(&part[..split])
+        .map_err(|_| "multipart headers are not valid UTF-8".to_string())?;
+    let data = part[split + 4..].to_vec();
+
+    let mut name = None;
+    let mut filename = None;
+    let mut content_type = None;
+    for line in headers.split("\r\n") {
+        let Some((header, value)) = line.split_once(':') else {
+            continue;
+        };
+        if header.trim().eq_ignore_ascii_case("content-disposition") {
+            for param in value.split(';').skip(1) {
+                if let Some((key, v)) = param.split_once('=') {
+                    let v = v.trim().trim_matches('"').to_string();
+                    match key.trim() {
+                        "name" => name = Some(v),
+                        "filename" => filename = Some(v),
+                        _ => {}
+                    }
+                }
+            }
+        } else if header.trim().eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+    Ok(Part {
+        name: name.ok_or_else(|| "multipart part without name".to_string())?,
+        filename,
+        content_type,
+        data,
+    })
+}
+
+/// Position of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod multipart_tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_parse_fields_and_file() {
+        let start = Instant::now();
+
+        let body = b"--XyZ\r\n\
+Content-Disposition: form-data; name=\"model\"\r\n\r\n\
+gpt-4o-transcribe\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"a.wav\"\r\n\
+Content-Type: audio/wav\r\n\r\n\
+RIFF\r\n\0\x01\r\n\
+--XyZ--\r\n";
+        let parts = parse("multipart/form-data; boundary=\"XyZ\"", body).unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "model");
+        assert_eq!(parts[0].text().unwrap(), "gpt-4o-transcribe");
+        assert_eq!(parts[1].filename.as_deref(), Some("a.wav"));
+        assert_eq!(parts[1].content_type.as_deref(), Some("audio/wav"));
+        assert_eq!(parts[1].data, b"RIFF\r\n\0\x01");
+
+        let duration = Instant::now() - start;
+        eprintln!("test_parse_fields_and_file took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_parse_rejects_other_bodies() {
+        let start = Instant::now();
+
+        assert!(parse("application/json", b"{}").is_err());
+        assert!(parse("multipart/form-data; boundary=a", b"--a\r\nunterminated").is_err());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_parse_rejects_other_bodies took: {:?}", duration);
+    }
+}
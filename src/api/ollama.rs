@@ -0,0 +1,362 @@
+//! Ollama API on top of the Polytheus backend, so tools that only speak to a local
+//! Ollama server (IDE plugins, desktop chat apps) can use the models of the catalog.
+//!
+//! Ollama streams by default: streamed answers are buffered and sent at once as
+//! newline-delimited JSON, the last line carrying `done: true` and the statistics.
+use crate::api::{run_error, text_message, token_counts, ApiResponse, AppState, RequestContext};
+use crate::polytheus::{
+    Completion, FinishReason, GenerationParams, Message, RunOptions, ThinkingLevel,
+};
+use serde_json::{json, Value};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Handles Ollama API that use chat endpoint.
+pub async fn Chat(
+    state: &AppState,
+    context: &RequestContext,
+    structBody: serde_json::Value,
+) -> Result<ApiResponse, String> {
+    let model_name = model_name(&structBody)?;
+    let mut messages = vec![];
+    for message_json in structBody["messages"]
+        .as_array()
+        .ok_or("you are missing the messages".to_string())?
+    {
+        let role = message_json["role"]
+            .as_str()
+            .ok_or("you are missing the role")?;
+        let content = message_json["content"]
+            .as_str()
+            .ok_or("you are missing the content")?;
+        let mut message = text_message(role, content);
+        message.input_image = image(&message_json["images"])?;
+        messages.push(message);
+    }
+
+    let start = Instant::now();
+    let completion = run(state, context, &structBody, model_name, &messages).await?;
+    let fields = json!({
+        "message": { "role": "assistant", "content": completion.text }
+    });
+    let last = json!({
+        "message": { "role": "assistant", "content": "" }
+    });
+    respond(
+        &structBody,
+        model_name,
+        &messages,
+        &completion,
+        start,
+        fields,
+        last,
+    )
+}
+
+/// Handles Ollama API that use generate endpoint.
+pub async fn Generate(
+    state: &AppState,
+    context: &RequestContext,
+    structBody: serde_json::Value,
+) -> Result<ApiResponse, String> {
+    let model_name = model_name(&structBody)?;
+    let mut messages = vec![];
+    if let Some(system) = structBody["system"].as_str() {
+        messages.push(text_message("system", system));
+    }
+    let prompt = structBody["prompt"]
+        .as_str()
+        .ok_or("you are missing the prompt".to_string())?;
+    let mut message = text_message("user", prompt);
+    message.input_image = image(&structBody["images"])?;
+    messages.push(message);
+
+    let start = Instant::now();
+    let completion = run(state, context, &structBody, model_name, &messages).await?;
+    let fields = json!({ "response": completion.text });
+    let last = json!({ "response": "" });
+    respond(
+        &structBody,
+        model_name,
+        &messages,
+        &completion,
+        start,
+        fields,
+        last,
+    )
+}
+
+/// Handles Ollama API that use tags endpoint: the text models of the catalog.
+pub fn Tags(state: &AppState) -> Result<serde_json::Value, String> {
+    let modified_at = rfc3339(SystemTime::now());
+    let models: Vec<Value> = state
+        .polytheus
+        .text_models()
+        .into_iter()
+        .map(|name| {
+            json!({
+                "name": name,
+                "model": name,
+                "modified_at": modified_at,
+                "size": 0,
+                "digest": "",
+                "details": {
+                    "format": "remote",
+                    "family": "",
+                    "families": Value::Null,
+                    "parameter_size": "",
+                    "quantization_level": ""
+                }
+            })
+        })
+        .collect();
+    Ok(json!({ "models": models }))
+}
+
+/// Model of a request, without the ":latest" tag Ollama clients add.
+fn model_name(body: &Value) -> Result<&str, String> {
+    let name = body["model"]
+        .as_str()
+        .ok_or("you are missing the model name".to_string())?;
+    Ok(name.strip_suffix(":latest").unwrap_or(name))
+}
+
+/// Data URL of the single image of `images`, a list of raw base64 images.
+fn image(images: &Value) -> Result<Option<String>, String> {
+    let images = match images {
+        Value::Null => return Ok(None),
+        Value::Array(images) => images,
+        _ => return Err("images must be an array of base64 strings".to_string()),
+    };
+    if images.len() > 1 {
+        return Err("only one image per message is supported".to_string());
+    }
+    images
+        .first()
+        .map(|image| {
+            let data = image
+                .as_str()
+                .ok_or("images must be an array of base64 strings".to_string())?;
+            Ok(format!("data:{};base64,{}", media_type(data), data))
+        })
+        .transpose()
+}
+
+/// Media type of a base64 image, from the encoding of its magic bytes.
+fn media_type(base64: &str) -> &'static str {
+    if base64.starts_with("/9j/") {
+        "image/jpeg"
+    } else if base64.starts_with("R0lGOD") {
+        "image/gif"
+    } else if base64.starts_with("UklGR") {
+        "image/webp"
+    } else {
+        "image/png"
+    }
+}
+
+/// Run `messages`, applying the `think` and `format` options of the request.
+async fn run(
+    state: &AppState,
+    context: &RequestContext,
+    body: &Value,
+    model_name: &str,
+    messages: &[Message],
+) -> Result<Completion, String> {
+    let thinking_level = match &body["think"] {
+        Value::Null | Value::Bool(false) => None,
+        Value::Bool(true) => Some(ThinkingLevel::Medium),
+        Value::String(level) => Some(level.parse()?),
+        _ => return Err("think must be a boolean or a level".to_string()),
+    };
+    let mut messages = messages.to_vec();
+    match &body["format"] {
+        Value::Null => {}
+        Value::String(format) if format == "json" => messages.insert(
+            0,
+            text_message(
+                "system",
+                "You must respond with a single valid JSON object (no surrounding text).",
+            ),
+        ),
+        Value::Object(_) => messages.insert(
+            0,
+            text_message(
+                "system",
+                &format!(
+                    "You must respond with a single JSON object that conforms to this JSON Schema:\n{}",
+                    body["format"]
+                ),
+            ),
+        ),
+        other => return Err(format!("Unsupported format: {}", other)),
+    }
+    let options = RunOptions {
+        params: generation_params(&body["options"])?,
+        ..RunOptions::from_request(context, body)
+    };
+    state
+        .polytheus
+        .run_with_options(model_name, messages, thinking_level, &options)
+        .await
+        .map_err(run_error)
+}
+
+/// Sampling parameters of the Ollama `options`; runtime options of a local server
+/// (`num_ctx`, `num_gpu`...) are meaningless here and ignored.
+fn generation_params(options: &Value) -> Result<GenerationParams, String> {
+    let max_tokens = match options["num_predict"].as_i64() {
+        // -1 (the default) generates until the model stops
+        None | Some(-1) => None,
+        Some(max) => Some(
+            u32::try_from(max)
+                .map_err(|_| "num_predict must be -1 or a positive integer".to_string())?,
+        ),
+    };
+    let stop = match &options["stop"] {
+        Value::Null => None,
+        Value::Array(items) => Some(
+            items
+                .iter()
+                .map(|item| item.as_str().map(|s| s.to_string()))
+                .collect::<Option<_>>()
+                .ok_or("stop must be an array of strings".to_string())?,
+        ),
+        _ => return Err("stop must be an array of strings".to_string()),
+    };
+    Ok(GenerationParams {
+        temperature: options["temperature"].as_f64(),
+        top_p: options["top_p"].as_f64(),
+        max_tokens,
+        stop,
+        seed: options["seed"].as_i64(),
+        presence_penalty: options["presence_penalty"].as_f64(),
+        frequency_penalty: options["frequency_penalty"].as_f64(),
+        ..GenerationParams::default()
+    })
+}
+
+/// Answer of a chat or generate request: `fields` and the statistics in a single
+/// document, or, when streaming, `fields` then `last` with the statistics.
+fn respond(
+    body: &Value,
+    model_name: &str,
+    messages: &[Message],
+    completion: &Completion,
+    start: Instant,
+    mut fields: Value,
+    mut last: Value,
+) -> Result<ApiResponse, String> {
+    let created_at = rfc3339(SystemTime::now());
+    let (prompt_eval_count, eval_count) = token_counts(messages, completion);
+    let total_duration = start.elapsed().as_nanos() as u64;
+    let statistics = json!({
+        "done_reason": done_reason(completion.finish_reason),
+        "total_duration": total_duration,
+        "load_duration": 0,
+        "prompt_eval_count": prompt_eval_count,
+        "prompt_eval_duration": 0,
+        "eval_count": eval_count,
+        "eval_duration": total_duration
+    });
+    for document in [&mut fields, &mut last] {
+        document["model"] = json!(completion.model(model_name));
+        document["created_at"] = json!(created_at);
+    }
+    let merge = |document: &mut Value| {
+        for (key, value) in statistics.as_object().into_iter().flatten() {
+            document[key] = value.clone();
+        }
+    };
+
+    if !body["stream"].as_bool().unwrap_or(true) {
+        fields["done"] = json!(true);
+        merge(&mut fields);
+        return Ok(ApiResponse::Json(fields));
+    }
+    fields["done"] = json!(false);
+    last["done"] = json!(true);
+    merge(&mut last);
+    Ok(ApiResponse::Binary {
+        content_type: "application/x-ndjson".to_string(),
+        data: format!("{}\n{}\n", fields, last).into_bytes(),
+    })
+}
+
+/// Ollama `done_reason` of a finish reason: Ollama only knows "stop" and "length".
+fn done_reason(finish_reason: FinishReason) -> &'static str {
+    match finish_reason {
+        FinishReason::Length => "length",
+        _ => "stop",
+    }
+}
+
+/// `time` as an RFC 3339 UTC timestamp (e.g. "2024-05-01T12:00:00.000Z").
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+    // civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod ollama_tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rfc3339() {
+        let start = Instant::now();
+
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_millis(1_709_210_096_789)),
+            "2024-02-29T12:34:56.789Z"
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_rfc3339 took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_model_name_and_images() {
+        let start = Instant::now();
+
+        assert_eq!(
+            model_name(&json!({ "model": "gpt-4o:latest" })).unwrap(),
+            "gpt-4o"
+        );
+        assert_eq!(
+            image(&json!(["/9j/4AAQ"])).unwrap().as_deref(),
+            Some("data:image/jpeg;base64,/9j/4AAQ")
+        );
+        assert_eq!(image(&Value::Null).unwrap(), None);
+        assert!(image(&json!(["iVBO", "iVBO"])).is_err());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_model_name_and_images took: {:?}", duration);
+    }
+}
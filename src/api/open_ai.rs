@@ -1,12 +1,27 @@
 /// This file simulates an OpenAI-compatible API using the Polytheus backend.
 /// It translates OpenAI API requests into Polytheus calls and formats the responses accordingly.
-use crate::polytheus::{Message, Polytheus};
+use crate::api::response_store::StoredResponse;
+use crate::api::{
+    multipart, run_error, text_message, token_counts, ApiResponse, AppState, RequestContext,
+    ServerEvent,
+};
+use crate::polytheus::codec;
+use crate::polytheus::{
+    AudioFile, Completion, FinishReason, GenerationParams, ImageRequest, Message, RunOptions,
+    SpeechRequest, ThinkingLevel, TokenLogprob, TranscriptionRequest,
+};
+use crate::telemetry::otel;
+use base64::prelude::*;
 use serde_json::{json, Value};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Handles Open AI API that use chat completions endpoint.
-pub async fn ChatCompletions(structBody: serde_json::Value) -> Result<serde_json::Value, String> {
-    let polytheus = Polytheus::fast_fill();
+pub async fn ChatCompletions(
+    state: &AppState,
+    context: &RequestContext,
+    structBody: serde_json::Value,
+) -> Result<serde_json::Value, String> {
+    let polytheus = &state.polytheus;
     let model_name = structBody["model"]
         .as_str()
         .ok_or("you are missing the model name".to_string())?;
@@ -15,71 +30,26 @@ pub async fn ChatCompletions(structBody: serde_json::Value) -> Result<serde_json
         .ok_or("you are missing the messages".to_string())?;
     let reasoning_effort = structBody["reasoning_effort"]
         .as_str()
-        .map(|s| s.to_string());
-    let mut messages: Vec<Message> = vec![];
-    for message_json in messages_json {
-        let role = message_json["role"]
-            .as_str()
-            .ok_or("you are missing the role")?
-            .to_string();
-        let input_text = if message_json.get("content").is_none() {
-            return Err("you are missing the content".to_string());
-        } else if let Some(s) = message_json["content"].as_str() {
-            s.to_string()
-        } else if let Some(arr) = message_json["content"].as_array() {
-            let mut parts: Vec<String> = Vec::new();
-            for el in arr {
-                // element might be plain string
-                if let Some(t) = el.as_str() {
-                    parts.push(t.to_string());
-                    continue;
-                }
-                // element might be an object like { "type": "text", "text": "..." }
-                if el.is_object() {
-                    if let Some(t) = el.get("text").and_then(|v| v.as_str()) {
-                        parts.push(t.to_string());
-                        continue;
-                    }
-                    // some variants use "content" or nested structures:
-                    if let Some(t) = el.get("content").and_then(|v| v.as_str()) {
-                        parts.push(t.to_string());
-                        continue;
-                    }
-                    // handle arrays-of-arrays if necessary (flatten one level)
-                    if let Some(inner_arr) = el.get("content").and_then(|v| v.as_array()) {
-                        for inner in inner_arr {
-                            if let Some(t) = inner.get("text").and_then(|v| v.as_str()) {
-                                parts.push(t.to_string());
-                            } else if let Some(t) = inner.as_str() {
-                                parts.push(t.to_string());
-                            }
-                        }
-                    }
-                }
-            }
-            if parts.is_empty() {
-                return Err("you are missing the content".to_string());
-            }
-            parts.join(" ")
-        } else {
-            return Err("you are missing the content".to_string());
-        };
-        let input_image = message_json["input_image"].as_str().map(|s| s.to_string());
-        let input_audio = message_json["input_audio"].as_str().map(|s| s.to_string());
-        let input_audio_format = message_json["input_audio_format"]
-            .as_str()
-            .map(|s| s.to_string());
-        let input_video = message_json["input_video"].as_str().map(|s| s.to_string());
-
-        messages.push(Message {
-            role,
-            input_text,
-            input_image,
-            input_audio,
-            input_audio_format,
-            input_video,
-        });
-    }
+        .map(str::parse::<ThinkingLevel>)
+        .transpose()?;
+    // Non-standard extension (as on OpenRouter): return the reasoning of the model.
+    let include_reasoning = structBody["include_reasoning"].as_bool().unwrap_or(false);
+    let n = match &structBody["n"] {
+        Value::Null => 1,
+        n => n
+            .as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or("n must be a positive integer".to_string())?,
+    };
+    // Non-standard extension (sent through `extra_body`): per-request timeout in seconds.
+    let options = RunOptions {
+        params: generation_params(&structBody)?,
+        ..RunOptions::from_request(context, &structBody)
+    };
+    let mut messages = messages_json
+        .iter()
+        .map(codec::decode_message)
+        .collect::<Result<Vec<Message>, String>>()?;
 
     // Handle response_format option (Structured Outputs / JSON modes)
     // Note: the OpenAI Python SDK typically sends JSON Schema nested like:
@@ -151,20 +121,17 @@ pub async fn ChatCompletions(structBody: serde_json::Value) -> Result<serde_json
                         input_audio: None,
                         input_audio_format: None,
                         input_video: None,
+                        input_file: None,
                     },
                 );
             }
         }
     }
 
-    println!("model_name: {}", model_name);
-    let result_text = polytheus
-        .run(model_name, messages.clone(), reasoning_effort)
+    let completions = polytheus
+        .run_choices(model_name, messages.clone(), reasoning_effort, &options, n)
         .await
-        .map_err(|e| {
-            "Something go wrong with the Polytheus run. Polytheus error: ".to_string()
-                + &e.to_string()
-        })?;
+        .map_err(run_error)?;
 
     // Build OpenAI-like response
     let created = SystemTime::now()
@@ -175,39 +142,61 @@ pub async fn ChatCompletions(structBody: serde_json::Value) -> Result<serde_json
     // simple id using timestamp (replace with stronger id if desired)
     let id = format!("chatcmpl-{}", created);
 
-    // compute naive token counts by word counts as a lightweight approximation
-    let prompt_tokens: usize = messages
-        .iter()
-        .map(|m| m.input_text.split_whitespace().count())
-        .sum();
-    let completion_tokens: usize = result_text.split_whitespace().count();
-    let total_tokens = prompt_tokens + completion_tokens;
+    // the prompt is counted once, as upstream, but every choice is paid for
+    let mut prompt_tokens = 0;
+    let mut completion_tokens = 0;
+    let mut reasoning_tokens = 0;
+    let mut cost = Some(0.0);
+    let mut cached = false;
+    let mut choices = vec![];
+    let model = completions
+        .first()
+        .map_or(model_name, |completion| completion.model(model_name))
+        .to_string();
+    for (index, completion) in completions.into_iter().enumerate() {
+        let (prompt_count, completion_count) = token_counts(&messages, &completion);
+        prompt_tokens = prompt_count;
+        completion_tokens += completion_count;
+        reasoning_tokens += completion.usage.reasoning_tokens.unwrap_or(0);
+        cost = cost.zip(completion.usage.cost).map(|(a, b)| a + b);
+        cached = completion.cached;
+        let mut choice = json!({
+            "index": index,
+            "message": {
+                "role": "assistant",
+                // IMPORTANT: keep `content` as a string for OpenAI SDK compatibility.
+                // The OpenAI Python `.parse()` helper expects `message.content` to be a JSON *string*
+                // (the SDK parses/validates it client-side into `.message.parsed`).
+                "content": completion.text,
+                "refusal": completion.refusal,
+                "annotations": []
+            },
+            "logprobs": completion
+                .logprobs
+                .map(|content| json!({ "content": content, "refusal": Value::Null })),
+            "finish_reason": completion.finish_reason.as_str()
+        });
+        if include_reasoning {
+            choice["message"]["reasoning_content"] = json!(completion.reasoning);
+        }
+        choices.push(choice);
+    }
 
-    let choice = json!({
-        "index": 0,
-        "message": {
-            "role": "assistant",
-            // IMPORTANT: keep `content` as a string for OpenAI SDK compatibility.
-            // The OpenAI Python `.parse()` helper expects `message.content` to be a JSON *string*
-            // (the SDK parses/validates it client-side into `.message.parsed`).
-            "content": result_text,
-            "refusal": Value::Null,
-            "annotations": []
-        },
-        "logprobs": Value::Null,
-        "finish_reason": "stop"
-    });
+    let total_tokens = prompt_tokens + completion_tokens;
+    let cached_tokens = if cached { prompt_tokens } else { 0 };
 
     let usage = json!({
         "prompt_tokens": prompt_tokens,
         "completion_tokens": completion_tokens,
         "total_tokens": total_tokens,
+        // OpenRouter extension: cost in USD, zero when served from the cache
+        "cost": cost,
         "prompt_tokens_details": {
-            "cached_tokens": 0,
+            "cached_tokens": cached_tokens,
             "audio_tokens": 0
         },
         "completion_tokens_details": {
-            "reasoning_tokens": 0,
+            "reasoning_tokens": reasoning_tokens,
             "audio_tokens": 0,
             "accepted_prediction_tokens": 0,
             "rejected_prediction_tokens": 0
@@ -218,11 +207,950 @@ pub async fn ChatCompletions(structBody: serde_json::Value) -> Result<serde_json
         "id": id,
         "object": "chat.completion",
         "created": created,
-        "model": model_name,
-        "choices": [ choice ],
+        "model": model,
+        "choices": choices,
         "usage": usage,
         "service_tier": "default"
     });
 
     Ok(response)
 }
+
+/// Sampling parameters of an OpenAI request: `max_completion_tokens`, `max_tokens` and
+/// `max_output_tokens` (Responses API) all set the maximum number of tokens.
+fn generation_params(body: &Value) -> Result<GenerationParams, String> {
+    let number = |name: &str| match &body[name] {
+        Value::Null => Ok(None),
+        value => value
+            .as_f64()
+            .map(Some)
+            .ok_or_else(|| format!("{} must be a number", name)),
+    };
+    let max_tokens = ["max_completion_tokens", "max_tokens", "max_output_tokens"]
+        .iter()
+        .find(|name| !body[**name].is_null())
+        .map(|name| {
+            body[*name]
+                .as_u64()
+                .and_then(|max| u32::try_from(max).ok())
+                .ok_or_else(|| format!("{} must be a positive integer", name))
+        })
+        .transpose()?;
+    let stop = match &body["stop"] {
+        Value::Null => None,
+        Value::String(stop) => Some(vec![stop.clone()]),
+        Value::Array(items) => Some(
+            items
+                .iter()
+                .map(|item| item.as_str().map(|s| s.to_string()))
+                .collect::<Option<_>>()
+                .ok_or("stop must be a string or an array of strings".to_string())?,
+        ),
+        _ => return Err("stop must be a string or an array of strings".to_string()),
+    };
+    let logit_bias = match &body["logit_bias"] {
+        Value::Null => None,
+        Value::Object(biases) => Some(
+            biases
+                .iter()
+                .map(|(token, bias)| Some((token.clone(), bias.as_f64()?)))
+                .collect::<Option<_>>()
+                .ok_or("logit_bias must map token ids to numbers".to_string())?,
+        ),
+        _ => return Err("logit_bias must map token ids to numbers".to_string()),
+    };
+    // a boolean, or in the legacy completions API the number of alternatives per token
+    let (logprobs, legacy_top_logprobs) = match &body["logprobs"] {
+        Value::Null => (None, None),
+        Value::Bool(logprobs) => (Some(*logprobs), None),
+        top => {
+            let top = top
+                .as_u64()
+                .and_then(|top| u32::try_from(top).ok())
+                .ok_or("logprobs must be a boolean or a number of alternatives".to_string())?;
+            (Some(true), Some(top).filter(|top| *top > 0))
+        }
+    };
+    let top_logprobs = match &body["top_logprobs"] {
+        Value::Null => legacy_top_logprobs,
+        top => Some(
+            top.as_u64()
+                .and_then(|top| u32::try_from(top).ok())
+                .ok_or("top_logprobs must be a positive integer".to_string())?,
+        ),
+    };
+    let seed = match &body["seed"] {
+        Value::Null => None,
+        seed => Some(seed.as_i64().ok_or("seed must be an integer".to_string())?),
+    };
+    Ok(GenerationParams {
+        temperature: number("temperature")?,
+        top_p: number("top_p")?,
+        max_tokens,
+        stop,
+        seed,
+        presence_penalty: number("presence_penalty")?,
+        frequency_penalty: number("frequency_penalty")?,
+        logit_bias,
+        logprobs,
+        top_logprobs,
+    })
+}
+
+/// Messages of a Responses API `input`: a string (one user message) or a list of
+/// message items, whose content is a string or a list of `input_text`, `output_text`,
+/// `input_image` and `input_file` parts.
+fn response_input(input: &Value) -> Result<Vec<Message>, String> {
+    let items = match input {
+        Value::String(text) => return Ok(vec![text_message("user", text)]),
+        Value::Array(items) => items,
+        _ => return Err("you are missing the input".to_string()),
+    };
+    let mut messages = vec![];
+    for item in items {
+        match item["type"].as_str() {
+            None | Some("message") => {}
+            Some(other) => return Err(format!("Unsupported input item type: {}", other)),
+        }
+        let role = item["role"].as_str().ok_or("you are missing the role")?;
+        let mut message = text_message(role, "");
+        let mut texts: Vec<&str> = vec![];
+        match &item["content"] {
+            Value::String(text) => texts.push(text),
+            Value::Array(parts) => {
+                for part in parts {
+                    match part["type"].as_str() {
+                        Some("input_text" | "output_text") => texts.push(
+                            part["text"]
+                                .as_str()
+                                .ok_or("you are missing the text of a content part")?,
+                        ),
+                        Some("input_image") => {
+                            if message.input_image.is_some() {
+                                return Err(
+                                    "only one input_image per message is supported".to_string()
+                                );
+                            }
+                            let url = image_url(part)
+                                .ok_or("input_image must have an image_url".to_string())?;
+                            message.input_image = Some(url);
+                        }
+                        Some("input_file") => {
+                            if message.input_file.is_some() {
+                                return Err(
+                                    "only one input_file per message is supported".to_string()
+                                );
+                            }
+                            let file = part["file_data"]
+                                .as_str()
+                                .or_else(|| part["file_url"].as_str())
+                                .ok_or("input_file must have a file_data or file_url")?;
+                            message.input_file = Some(file.to_string());
+                        }
+                        other => {
+                            return Err(format!(
+                                "Unsupported content part type: {}",
+                                other.unwrap_or("none")
+                            ))
+                        }
+                    }
+                }
+            }
+            _ => return Err("you are missing the content".to_string()),
+        }
+        message.input_text = texts.join(" ");
+        messages.push(message);
+    }
+    if messages.is_empty() {
+        return Err("you are missing the input".to_string());
+    }
+    Ok(messages)
+}
+
+/// Handles Open AI API that use responses endpoint.
+///
+/// Unless `store` is false, the turn is kept so a later request of the same tenant can
+/// continue the conversation with `previous_response_id`. `instructions` only apply to
+/// the current response.
+///
+/// The reasoning of the model, when it returns one, comes first as a `reasoning` item
+/// holding it as its summary. With `stream`, the response is sent as its sequence of
+/// `response.*` events, buffered as every stream of this API (see `response_events`).
+pub async fn Responses(
+    state: &AppState,
+    context: &RequestContext,
+    structBody: serde_json::Value,
+) -> Result<ApiResponse, String> {
+    let model_name = structBody["model"]
+        .as_str()
+        .ok_or("you are missing the model name".to_string())?;
+    let previous_response_id = structBody["previous_response_id"].as_str();
+    let instructions = structBody["instructions"].as_str();
+    let reasoning_effort = structBody["reasoning"]["effort"].as_str();
+    let thinking_level = reasoning_effort
+        .map(str::parse::<ThinkingLevel>)
+        .transpose()?;
+    let store = structBody["store"].as_bool().unwrap_or(true);
+    let options = RunOptions {
+        params: generation_params(&structBody)?,
+        ..RunOptions::from_request(context, &structBody)
+    };
+
+    let owner = context.header("x-polytheus-tenant");
+    let mut messages = match previous_response_id {
+        Some(id) => state.responses.conversation(id, owner)?.ok_or_else(|| {
+            format!(
+                "Previous response '{}' not found: responses expire, and unless \
+                 POLYTHEUS_RESPONSES=sqlite shares them, are only kept by the server \
+                 instance that answered",
+                id
+            )
+        })?,
+        None => vec![],
+    };
+    let mut turn = response_input(&structBody["input"])?;
+    messages.extend(turn.iter().cloned());
+    if let Some(instructions) = instructions {
+        messages.insert(0, text_message("system", instructions));
+    }
+
+    let completion = state
+        .polytheus
+        .run_with_options(model_name, messages.clone(), thinking_level, &options)
+        .await
+        .map_err(run_error)?;
+
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| format!("time error: {}", e))?
+        .as_secs();
+    let id = format!("resp_{}", otel::random_id(24));
+    if store {
+        turn.push(text_message("assistant", &completion.text));
+        state.responses.put(
+            &id,
+            StoredResponse {
+                model: model_name.to_string(),
+                owner: owner.map(str::to_string),
+                previous_response_id: previous_response_id.map(str::to_string),
+                messages: turn,
+            },
+        )?;
+    }
+
+    let response = response_object(
+        &id,
+        created_at,
+        &structBody,
+        model_name,
+        &messages,
+        &completion,
+    );
+    match structBody["stream"].as_bool().unwrap_or(false) {
+        true => Ok(ApiResponse::EventStream(response_events(&response))),
+        false => Ok(ApiResponse::Json(response)),
+    }
+}
+
+/// Events of the Responses API streaming `response`: `response.created`, then each
+/// output item added, its content streamed as a single delta, and done, then
+/// `response.completed` (or `response.incomplete`).
+fn response_events(response: &Value) -> Vec<ServerEvent> {
+    let mut events = vec![];
+    let mut push = |kind: &str, mut data: Value| {
+        data["type"] = json!(kind);
+        data["sequence_number"] = json!(events.len());
+        events.push(ServerEvent::json(kind, &data));
+    };
+    let mut in_progress = response.clone();
+    in_progress["status"] = json!("in_progress");
+    in_progress["output"] = json!([]);
+    in_progress["usage"] = Value::Null;
+    in_progress["incomplete_details"] = Value::Null;
+    push("response.created", json!({ "response": in_progress }));
+    push("response.in_progress", json!({ "response": in_progress }));
+
+    // `fields` of an event, plus `key`
+    let with = |mut fields: Value, key: &str, value: Value| {
+        fields[key] = value;
+        fields
+    };
+    let output = response["output"].as_array().cloned().unwrap_or_default();
+    for (output_index, item) in output.iter().enumerate() {
+        let item_id = &item["id"];
+        let mut added = item.clone();
+        added["status"] = json!("in_progress");
+        if item["type"] == "reasoning" {
+            added["summary"] = json!([]);
+        } else {
+            added["content"] = json!([]);
+        }
+        push(
+            "response.output_item.added",
+            json!({ "output_index": output_index, "item": added }),
+        );
+        if item["type"] == "reasoning" {
+            for (summary_index, part) in
+                item["summary"].as_array().into_iter().flatten().enumerate()
+            {
+                let at = json!({
+                    "item_id": item_id,
+                    "output_index": output_index,
+                    "summary_index": summary_index
+                });
+                push(
+                    "response.reasoning_summary_part.added",
+                    with(
+                        at.clone(),
+                        "part",
+                        json!({ "type": "summary_text", "text": "" }),
+                    ),
+                );
+                push(
+                    "response.reasoning_summary_text.delta",
+                    with(at.clone(), "delta", part["text"].clone()),
+                );
+                push(
+                    "response.reasoning_summary_text.done",
+                    with(at.clone(), "text", part["text"].clone()),
+                );
+                push(
+                    "response.reasoning_summary_part.done",
+                    with(at, "part", part.clone()),
+                );
+            }
+        }
+        for (content_index, part) in item["content"].as_array().into_iter().flatten().enumerate() {
+            let at = json!({
+                "item_id": item_id,
+                "output_index": output_index,
+                "content_index": content_index
+            });
+            let (kind, key) = match part["type"].as_str() {
+                Some("refusal") => ("refusal", "refusal"),
+                _ => ("output_text", "text"),
+            };
+            let mut empty = part.clone();
+            empty[key] = json!("");
+            push(
+                "response.content_part.added",
+                with(at.clone(), "part", empty),
+            );
+            push(
+                &format!("response.{}.delta", kind),
+                with(at.clone(), "delta", part[key].clone()),
+            );
+            push(
+                &format!("response.{}.done", kind),
+                with(at.clone(), key, part[key].clone()),
+            );
+            push("response.content_part.done", with(at, "part", part.clone()));
+        }
+        push(
+            "response.output_item.done",
+            json!({ "output_index": output_index, "item": item }),
+        );
+    }
+
+    let done = match response["status"].as_str() {
+        Some("incomplete") => "response.incomplete",
+        _ => "response.completed",
+    };
+    push(done, json!({ "response": response }));
+    events
+}
+
+/// Responses API object `id` answering `body` with `completion`, `messages` being the
+/// whole conversation sent to `model_name`.
+fn response_object(
+    id: &str,
+    created_at: u64,
+    body: &Value,
+    model_name: &str,
+    messages: &[Message],
+    completion: &Completion,
+) -> Value {
+    let instructions = body["instructions"].as_str();
+    let previous_response_id = body["previous_response_id"].as_str();
+    let reasoning_effort = body["reasoning"]["effort"].as_str();
+    let store = body["store"].as_bool().unwrap_or(true);
+    let (input_tokens, output_tokens) = token_counts(messages, completion);
+    let cached_tokens = if completion.cached { input_tokens } else { 0 };
+    let content = match &completion.refusal {
+        Some(refusal) => json!({ "type": "refusal", "refusal": refusal }),
+        None => json!({
+            "type": "output_text",
+            "text": completion.text,
+            "annotations": []
+        }),
+    };
+    let incomplete_reason = match completion.finish_reason {
+        FinishReason::Length => Some("max_output_tokens"),
+        FinishReason::ContentFilter => Some("content_filter"),
+        _ => None,
+    };
+    let status = if incomplete_reason.is_some() {
+        "incomplete"
+    } else {
+        "completed"
+    };
+    let mut output = vec![];
+    if let Some(reasoning) = &completion.reasoning {
+        output.push(json!({
+            "type": "reasoning",
+            "id": format!("rs_{}", otel::random_id(24)),
+            "summary": [{ "type": "summary_text", "text": reasoning }]
+        }));
+    }
+    output.push(json!({
+        "type": "message",
+        "id": format!("msg_{}", otel::random_id(24)),
+        "status": status,
+        "role": "assistant",
+        "content": [content]
+    }));
+
+    json!({
+        "id": id,
+        "object": "response",
+        "created_at": created_at,
+        "status": status,
+        "error": Value::Null,
+        "incomplete_details": incomplete_reason.map(|reason| json!({ "reason": reason })),
+        "instructions": instructions,
+        "model": completion.model(model_name),
+        "output": output,
+        "previous_response_id": previous_response_id,
+        "reasoning": { "effort": reasoning_effort, "summary": Value::Null },
+        "store": store,
+        "text": { "format": { "type": "text" } },
+        "tools": [],
+        "metadata": body.get("metadata").cloned().unwrap_or_else(|| json!({})),
+        "usage": {
+            "input_tokens": input_tokens,
+            "input_tokens_details": { "cached_tokens": cached_tokens },
+            "output_tokens": output_tokens,
+            "output_tokens_details": {
+                "reasoning_tokens": completion.usage.reasoning_tokens.unwrap_or(0)
+            },
+            "total_tokens": input_tokens + output_tokens,
+            "cost": completion.usage.cost
+        }
+    })
+}
+
+/// Handles Open AI API that use the legacy completions endpoint.
+///
+/// Each prompt of `prompt` (a string or an array of strings) is sent as a user message
+/// and gives one choice; `echo` repeats the prompt.
+pub async fn Completions(
+    state: &AppState,
+    context: &RequestContext,
+    structBody: serde_json::Value,
+) -> Result<serde_json::Value, String> {
+    let model_name = structBody["model"]
+        .as_str()
+        .ok_or("you are missing the model name".to_string())?;
+    let prompts: Vec<&str> = match &structBody["prompt"] {
+        Value::String(prompt) => vec![prompt],
+        Value::Array(items) if !items.is_empty() => items
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .ok_or("prompt must be a string or an array of strings".to_string())
+            })
+            .collect::<Result<_, _>>()?,
+        _ => return Err("you are missing the prompt".to_string()),
+    };
+    let echo = structBody["echo"].as_bool().unwrap_or(false);
+    let options = RunOptions {
+        params: generation_params(&structBody)?,
+        ..RunOptions::from_request(context, &structBody)
+    };
+
+    let n = match &structBody["n"] {
+        Value::Null => 1,
+        n => n
+            .as_u64()
+            .and_then(|n| u32::try_from(n).ok())
+            .ok_or("n must be a positive integer".to_string())?,
+    };
+
+    let mut choices = vec![];
+    let (mut prompt_tokens, mut completion_tokens) = (0, 0);
+    let mut cost = Some(0.0);
+    for prompt in &prompts {
+        let messages = vec![text_message("user", prompt)];
+        let completions = state
+            .polytheus
+            .run_choices(model_name, messages.clone(), None, &options, n)
+            .await
+            .map_err(run_error)?;
+        // each prompt is counted once, as upstream, but every choice is paid for
+        let mut prompt_count = 0;
+        for completion in completions {
+            let (prompt_counted, completion_count) = token_counts(&messages, &completion);
+            prompt_count = prompt_counted;
+            completion_tokens += completion_count;
+            cost = cost.zip(completion.usage.cost).map(|(a, b)| a + b);
+
+            let offset = if echo { prompt.len() } else { 0 };
+            let text = if echo {
+                format!("{}{}", prompt, completion.text)
+            } else {
+                completion.text
+            };
+            choices.push(json!({
+                "text": text,
+                "index": choices.len(),
+                "logprobs": completion
+                    .logprobs
+                    .map(|logprobs| legacy_logprobs(&logprobs, offset)),
+                "finish_reason": completion.finish_reason.as_str()
+            }));
+        }
+        prompt_tokens += prompt_count;
+    }
+
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| format!("time error: {}", e))?
+        .as_secs();
+    Ok(json!({
+        "id": format!("cmpl-{}", otel::random_id(12)),
+        "object": "text_completion",
+        "created": created,
+        "model": model_name,
+        "choices": choices,
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens,
+            "cost": cost
+        }
+    }))
+}
+
+/// Log probabilities in the shape of the legacy completions API, the generated text
+/// starting at `offset` (after the prompt when it is echoed).
+fn legacy_logprobs(logprobs: &[TokenLogprob], offset: usize) -> Value {
+    let mut text_offset = Vec::with_capacity(logprobs.len());
+    let mut position = offset;
+    for token in logprobs {
+        text_offset.push(position);
+        position += token.token.len();
+    }
+    let top_logprobs: Vec<Value> = logprobs
+        .iter()
+        .map(|token| {
+            let alternatives: serde_json::Map<String, Value> = token
+                .top_logprobs
+                .iter()
+                .map(|top| (top.token.clone(), json!(top.logprob)))
+                .collect();
+            json!(alternatives)
+        })
+        .collect();
+    let any_top = logprobs.iter().any(|token| !token.top_logprobs.is_empty());
+    json!({
+        "tokens": logprobs.iter().map(|token| &token.token).collect::<Vec<_>>(),
+        "token_logprobs": logprobs.iter().map(|token| token.logprob).collect::<Vec<_>>(),
+        "top_logprobs": if any_top { json!(top_logprobs) } else { Value::Null },
+        "text_offset": text_offset
+    })
+}
+
+/// Handles Open AI API that use embeddings endpoint.
+///
+/// `input` is a string or an array of strings (token arrays are not supported);
+/// `encoding_format` "base64" returns each vector as little-endian `f32` bytes.
+pub async fn Embeddings(
+    state: &AppState,
+    _context: &RequestContext,
+    structBody: serde_json::Value,
+) -> Result<serde_json::Value, String> {
+    let model_name = structBody["model"]
+        .as_str()
+        .ok_or("you are missing the model name".to_string())?;
+    let inputs: Vec<String> = match &structBody["input"] {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(items) => items
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .map(|s| s.to_string())
+                    .ok_or("input must be a string or an array of strings".to_string())
+            })
+            .collect::<Result<_, _>>()?,
+        _ => return Err("you are missing the input".to_string()),
+    };
+    let dimensions = match structBody.get("dimensions") {
+        None | Some(Value::Null) => None,
+        Some(d) => Some(
+            d.as_u64()
+                .ok_or("dimensions must be a positive integer".to_string())? as usize,
+        ),
+    };
+    let base64 = match structBody["encoding_format"].as_str() {
+        None | Some("float") => false,
+        Some("base64") => true,
+        Some(other) => return Err(format!("Unsupported encoding_format: {}", other)),
+    };
+
+    let embeddings = state
+        .polytheus
+        .embed(model_name, inputs, dimensions)
+        .await
+        .map_err(|e| {
+            "Something go wrong with the Polytheus embed. Polytheus error: ".to_string() + &e
+        })?;
+
+    let data: Vec<Value> = embeddings
+        .vectors
+        .iter()
+        .enumerate()
+        .map(|(index, vector)| {
+            let embedding = if base64 {
+                let bytes: Vec<u8> = vector.iter().flat_map(|v| v.to_le_bytes()).collect();
+                json!(BASE64_STANDARD.encode(bytes))
+            } else {
+                json!(vector)
+            };
+            json!({ "object": "embedding", "index": index, "embedding": embedding })
+        })
+        .collect();
+    let prompt_tokens = embeddings.usage.prompt_tokens.unwrap_or(0);
+
+    Ok(json!({
+        "object": "list",
+        "data": data,
+        "model": model_name,
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "total_tokens": prompt_tokens,
+            "cost": embeddings.usage.cost
+        }
+    }))
+}
+
+/// Handles Open AI API that use images generations endpoint.
+pub async fn ImagesGenerations(
+    state: &AppState,
+    context: &RequestContext,
+    structBody: serde_json::Value,
+) -> Result<serde_json::Value, String> {
+    images(state, context, structBody, false).await
+}
+
+/// Handles Open AI API that use images edits endpoint.
+///
+/// The image (and mask) are sent as URLs or data URLs, either as `image` or as the
+/// first entry of `images: [{ "image_url": ... }]`.
+pub async fn ImagesEdits(
+    state: &AppState,
+    context: &RequestContext,
+    structBody: serde_json::Value,
+) -> Result<serde_json::Value, String> {
+    images(state, context, structBody, true).await
+}
+
+/// URL of an image given as a string or as `{ "image_url": ... }`.
+fn image_url(value: &Value) -> Option<String> {
+    value
+        .as_str()
+        .or_else(|| value["image_url"].as_str())
+        .or_else(|| value["image_url"]["url"].as_str())
+        .map(|s| s.to_string())
+}
+
+/// Shared body of `ImagesGenerations` and `ImagesEdits`.
+async fn images(
+    state: &AppState,
+    context: &RequestContext,
+    structBody: serde_json::Value,
+    edit: bool,
+) -> Result<serde_json::Value, String> {
+    let polytheus = &state.polytheus;
+    let model_name = match structBody["model"].as_str() {
+        Some(name) => name.to_string(),
+        None => polytheus
+            .default_image_model(edit)
+            .ok_or("no image model available".to_string())?
+            .to_string(),
+    };
+    let image = if edit {
+        let image = image_url(&structBody["image"])
+            .or_else(|| image_url(&structBody["images"][0]))
+            .ok_or("you are missing the image".to_string())?;
+        Some(image)
+    } else {
+        None
+    };
+    let request = ImageRequest {
+        prompt: structBody["prompt"]
+            .as_str()
+            .ok_or("you are missing the prompt".to_string())?
+            .to_string(),
+        image,
+        mask: image_url(&structBody["mask"]),
+        n: match &structBody["n"] {
+            Value::Null => 1,
+            n => n
+                .as_u64()
+                .and_then(|n| u32::try_from(n).ok())
+                .ok_or("n must be a positive integer".to_string())?,
+        },
+        size: structBody["size"]
+            .as_str()
+            .filter(|size| *size != "auto")
+            .map(|s| s.to_string()),
+    };
+    let b64_json = match structBody["response_format"].as_str() {
+        None | Some("url") => false,
+        Some("b64_json") => true,
+        Some(other) => return Err(format!("Unsupported response_format: {}", other)),
+    };
+    let options = RunOptions::from_request(context, &structBody);
+
+    let images = polytheus
+        .generate_images(&model_name, &request, &options)
+        .await
+        .map_err(run_error)?;
+
+    let data: Vec<Value> = if b64_json {
+        polytheus
+            .images_base64(&images.urls)
+            .await?
+            .into_iter()
+            .map(|b64| json!({ "b64_json": b64 }))
+            .collect()
+    } else {
+        images
+            .urls
+            .iter()
+            .map(|url| json!({ "url": url }))
+            .collect()
+    };
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| format!("time error: {}", e))?
+        .as_secs();
+
+    Ok(json!({
+        "created": created,
+        "data": data,
+        "model": model_name,
+        "usage": { "cost": images.usage.cost }
+    }))
+}
+
+/// Handles Open AI API that use audio transcriptions endpoint.
+///
+/// The body is a `multipart/form-data` upload with a `file` field, and optional `model`,
+/// `language`, `prompt` and `response_format` ("json" or "text") fields.
+pub async fn AudioTranscriptions(
+    state: &AppState,
+    context: &RequestContext,
+    body: &[u8],
+) -> Result<ApiResponse, String> {
+    let polytheus = &state.polytheus;
+    let parts = multipart::parse(context.content_type().unwrap_or_default(), body)?;
+    let field = |name: &str| -> Result<Option<String>, String> {
+        parts
+            .iter()
+            .find(|part| part.name == name)
+            .map(|part| part.text().map(|text| text.to_string()))
+            .transpose()
+    };
+    let file = parts
+        .iter()
+        .find(|part| part.name == "file")
+        .ok_or("you are missing the file".to_string())?;
+
+    let model_name = match field("model")? {
+        Some(name) => name,
+        None => polytheus
+            .default_model_with("transcription")
+            .ok_or("no transcription model available".to_string())?
+            .to_string(),
+    };
+    let as_text = match field("response_format")?.as_deref() {
+        None | Some("json") => false,
+        Some("text") => true,
+        Some(other) => return Err(format!("Unsupported response_format: {}", other)),
+    };
+    let request = TranscriptionRequest {
+        file: AudioFile::new(
+            file.data.clone(),
+            file.filename.as_deref().unwrap_or("audio"),
+            file.content_type.as_deref(),
+        ),
+        language: field("language")?,
+        prompt: field("prompt")?,
+    };
+    let options = RunOptions::from_request(context, &Value::Null);
+
+    let transcription = polytheus
+        .transcribe(&model_name, &request, &options)
+        .await
+        .map_err(run_error)?;
+
+    if as_text {
+        return Ok(ApiResponse::Binary {
+            content_type: "text/plain; charset=utf-8".to_string(),
+            data: transcription.text.into_bytes(),
+        });
+    }
+    let usage = &transcription.usage;
+    let mut response = json!({ "text": transcription.text });
+    if let (Some(input), Some(output)) = (usage.prompt_tokens, usage.completion_tokens) {
+        response["usage"] = json!({
+            "type": "tokens",
+            "input_tokens": input,
+            "output_tokens": output,
+            "total_tokens": input + output,
+            "cost": usage.cost
+        });
+    }
+    Ok(ApiResponse::Json(response))
+}
+
+/// Handles Open AI API that use audio speech endpoint.
+///
+/// Voices are model-specific: OpenAI voice names use the model's default voice. The
+/// `response_format` can be mp3, wav, flac or pcm (see `audio::SPEECH_FORMATS`).
+pub async fn AudioSpeech(
+    state: &AppState,
+    context: &RequestContext,
+    structBody: serde_json::Value,
+) -> Result<ApiResponse, String> {
+    let polytheus = &state.polytheus;
+    let model_name = match structBody["model"].as_str() {
+        Some(name) => name.to_string(),
+        None => polytheus
+            .default_model_with("speech")
+            .ok_or("no speech model available".to_string())?
+            .to_string(),
+    };
+    let request = SpeechRequest {
+        input: structBody["input"]
+            .as_str()
+            .ok_or("you are missing the input".to_string())?
+            .to_string(),
+        voice: structBody["voice"].as_str().map(|s| s.to_string()),
+        speed: structBody["speed"].as_f64(),
+        response_format: structBody["response_format"]
+            .as_str()
+            .map(|s| s.to_string()),
+    };
+    let options = RunOptions::from_request(context, &structBody);
+
+    let speech = polytheus
+        .speak(&model_name, &request, &options)
+        .await
+        .map_err(run_error)?;
+
+    Ok(ApiResponse::Binary {
+        content_type: speech.content_type,
+        data: speech.audio,
+    })
+}
+
+#[cfg(test)]
+mod open_ai_tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_reasoning_is_a_response_output_item() {
+        let start = Instant::now();
+
+        let messages = vec![text_message("user", "Count to 3")];
+        let completion = Completion {
+            text: "1, 2, 3".to_string(),
+            reasoning: Some("Counting is easy.".to_string()),
+            ..Completion::default()
+        };
+        let body = json!({ "model": "gemini-3-pro", "reasoning": { "effort": "low" } });
+        let response = response_object("resp_1", 0, &body, "gemini-3-pro", &messages, &completion);
+        let output = response["output"].as_array().unwrap();
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0]["type"], "reasoning");
+        assert_eq!(
+            output[0]["summary"],
+            json!([{ "type": "summary_text", "text": "Counting is easy." }])
+        );
+        assert_eq!(output[1]["type"], "message");
+        assert_eq!(output[1]["content"][0]["text"], "1, 2, 3");
+
+        let without_reasoning = Completion {
+            reasoning: None,
+            ..completion
+        };
+        let response = response_object(
+            "resp_2",
+            0,
+            &body,
+            "gemini-3-pro",
+            &messages,
+            &without_reasoning,
+        );
+        assert_eq!(response["output"].as_array().unwrap().len(), 1);
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_reasoning_is_a_response_output_item took: {:?}",
+            duration
+        );
+    }
+
+    #[test]
+    fn test_streamed_response_events() {
+        let start = Instant::now();
+
+        let messages = vec![text_message("user", "Count to 3")];
+        let completion = Completion {
+            text: "1, 2, 3".to_string(),
+            reasoning: Some("Counting is easy.".to_string()),
+            ..Completion::default()
+        };
+        let body = json!({ "model": "gemini-3-pro", "stream": true });
+        let response = response_object("resp_1", 0, &body, "gemini-3-pro", &messages, &completion);
+        let events = response_events(&response);
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|event| event.event.as_deref().unwrap())
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "response.created",
+                "response.in_progress",
+                "response.output_item.added",
+                "response.reasoning_summary_part.added",
+                "response.reasoning_summary_text.delta",
+                "response.reasoning_summary_text.done",
+                "response.reasoning_summary_part.done",
+                "response.output_item.done",
+                "response.output_item.added",
+                "response.content_part.added",
+                "response.output_text.delta",
+                "response.output_text.done",
+                "response.content_part.done",
+                "response.output_item.done",
+                "response.completed"
+            ]
+        );
+        let data: Vec<Value> = events
+            .iter()
+            .map(|event| serde_json::from_str(&event.data).unwrap())
+            .collect();
+        assert_eq!(data[0]["response"]["status"], "in_progress");
+        assert_eq!(data[10]["delta"], "1, 2, 3");
+        assert_eq!(data[14]["sequence_number"], 14);
+        assert_eq!(data[14]["response"], response);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_streamed_response_events took: {:?}", duration);
+    }
+}
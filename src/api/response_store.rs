@@ -0,0 +1,392 @@
+//! Store of Responses API results, so a request can continue a conversation with
+//! `previous_response_id` instead of resending it.
+//!
+//! Responses live in the memory of the process unless `POLYTHEUS_RESPONSES` is
+//! "sqlite": on Lambda, a chain then only survives as long as requests reach the same
+//! warm instance, and only a database shared by the instances (e.g. on EFS) keeps it
+//! across them.
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::warn;
+
+use crate::polytheus::Message;
+
+/// Database of the SQLite store when `POLYTHEUS_RESPONSES_DB` is not set.
+const DEFAULT_DB_PATH: &str = "/tmp/polytheus-responses.db";
+
+/// Responses kept in memory when `POLYTHEUS_RESPONSES_CAPACITY` is not set.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Bytes of messages (text and attachments) kept in memory when
+/// `POLYTHEUS_RESPONSES_MAX_BYTES` is not set.
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// A stored response: the turn it added to its conversation.
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    /// Model that produced the response.
+    pub model: String,
+
+    /// Tenant the response was made for, the only one allowed to continue it.
+    pub owner: Option<String>,
+
+    /// Response this one continues, whose conversation comes first.
+    pub previous_response_id: Option<String>,
+
+    /// Input messages of the response, followed by the assistant answer
+    /// (`instructions` excluded: they are not carried over to the next response).
+    pub messages: Vec<Message>,
+}
+
+impl StoredResponse {
+    /// Bytes of text and attachments held by the response.
+    fn size(&self) -> usize {
+        self.messages
+            .iter()
+            .map(|message| {
+                message.input_text.len()
+                    + [
+                        &message.input_image,
+                        &message.input_audio,
+                        &message.input_video,
+                        &message.input_file,
+                    ]
+                    .iter()
+                    .map(|attachment| attachment.as_ref().map_or(0, String::len))
+                    .sum::<usize>()
+            })
+            .sum()
+    }
+}
+
+/// Where Responses API results are kept.
+pub trait ResponseStore: Send + Sync + Debug {
+    /// Response stored under `id` for `owner`.
+    fn get(&self, id: &str, owner: Option<&str>) -> Result<Option<StoredResponse>, String>;
+
+    /// Store `response` under `id`.
+    fn put(&self, id: &str, response: StoredResponse) -> Result<(), String>;
+
+    /// Every message of the conversation ending with the response `id` of `owner`,
+    /// oldest first; `None` when a response of the chain is missing.
+    fn conversation(&self, id: &str, owner: Option<&str>) -> Result<Option<Vec<Message>>, String> {
+        let mut turns = vec![];
+        let mut next = Some(id.to_string());
+        while let Some(id) = next {
+            let Some(response) = self.get(&id, owner)? else {
+                return Ok(None);
+            };
+            next = response.previous_response_id;
+            turns.push(response.messages);
+            // ids are never reused, but a corrupted chain mustn't loop forever
+            if turns.len() > MAX_CHAIN_LEN {
+                return Err(format!("Response '{}' continues too long a chain", id));
+            }
+        }
+        Ok(Some(turns.into_iter().rev().flatten().collect()))
+    }
+}
+
+/// Responses of a chain followed at most by `conversation`.
+const MAX_CHAIN_LEN: usize = 10_000;
+
+/// Build the store described by `POLYTHEUS_RESPONSES` ("sqlite", or unset for memory)
+/// and `POLYTHEUS_RESPONSES_DB`; a database that can't be opened falls back to memory.
+///
+/// The memory store is bounded by `POLYTHEUS_RESPONSES_CAPACITY` responses and
+/// `POLYTHEUS_RESPONSES_MAX_BYTES` bytes.
+pub fn store_from_env() -> Box<dyn ResponseStore> {
+    let memory = || {
+        let limit = |name: &str, default: usize| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Box::new(MemoryResponseStore::new(
+            limit("POLYTHEUS_RESPONSES_CAPACITY", DEFAULT_CAPACITY),
+            limit("POLYTHEUS_RESPONSES_MAX_BYTES", DEFAULT_MAX_BYTES),
+        ))
+    };
+    if env::var("POLYTHEUS_RESPONSES").as_deref() != Ok("sqlite") {
+        return memory();
+    }
+    let path = env::var("POLYTHEUS_RESPONSES_DB").unwrap_or_else(|_| DEFAULT_DB_PATH.to_string());
+    match SqliteResponseStore::open(&path) {
+        Ok(store) => Box::new(store),
+        Err(e) => {
+            warn!(error = %e, path, "response database unavailable, keeping responses in memory");
+            memory()
+        }
+    }
+}
+
+/// Stored responses by id, oldest evicted first past `capacity` responses or
+/// `max_bytes` bytes.
+#[derive(Debug)]
+pub struct MemoryResponseStore {
+    capacity: usize,
+    max_bytes: usize,
+    state: Mutex<StoreState>,
+}
+
+#[derive(Debug, Default)]
+struct StoreState {
+    order: VecDeque<String>,
+    responses: HashMap<String, StoredResponse>,
+
+    /// Sum of the sizes of `responses`.
+    bytes: usize,
+}
+
+impl MemoryResponseStore {
+    /// Store keeping at most `capacity` responses, holding at most `max_bytes` bytes.
+    pub fn new(capacity: usize, max_bytes: usize) -> MemoryResponseStore {
+        MemoryResponseStore {
+            capacity: capacity.max(1),
+            max_bytes,
+            state: Mutex::new(StoreState::default()),
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, StoreState>, String> {
+        self.state
+            .lock()
+            .map_err(|_| "the response store is poisoned".to_string())
+    }
+}
+
+impl Default for MemoryResponseStore {
+    fn default() -> MemoryResponseStore {
+        MemoryResponseStore::new(DEFAULT_CAPACITY, DEFAULT_MAX_BYTES)
+    }
+}
+
+impl ResponseStore for MemoryResponseStore {
+    fn get(&self, id: &str, owner: Option<&str>) -> Result<Option<StoredResponse>, String> {
+        Ok(self
+            .lock()?
+            .responses
+            .get(id)
+            .filter(|response| response.owner.as_deref() == owner)
+            .cloned())
+    }
+
+    fn put(&self, id: &str, response: StoredResponse) -> Result<(), String> {
+        let mut state = self.lock()?;
+        state.bytes += response.size();
+        match state.responses.insert(id.to_string(), response) {
+            Some(replaced) => state.bytes -= replaced.size(),
+            None => state.order.push_back(id.to_string()),
+        }
+        while state.order.len() > self.capacity || state.bytes > self.max_bytes {
+            let Some(oldest) = state.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = state.responses.remove(&oldest) {
+                state.bytes -= evicted.size();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Responses in a SQLite database, their messages as a JSON array.
+#[derive(Debug)]
+pub struct SqliteResponseStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteResponseStore {
+    /// Store in the database at `path`, created if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<SqliteResponseStore, String> {
+        let connection = Connection::open(path.as_ref()).map_err(|e| {
+            format!(
+                "Failed to open response database {:?}: {}",
+                path.as_ref(),
+                e
+            )
+        })?;
+        SqliteResponseStore::with_connection(connection)
+    }
+
+    /// Store in a private in-memory database.
+    pub fn in_memory() -> Result<SqliteResponseStore, String> {
+        let connection = Connection::open_in_memory()
+            .map_err(|e| format!("Failed to open response database: {}", e))?;
+        SqliteResponseStore::with_connection(connection)
+    }
+
+    fn with_connection(connection: Connection) -> Result<SqliteResponseStore, String> {
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS responses (
+                     id TEXT PRIMARY KEY,
+                     owner TEXT,
+                     model TEXT NOT NULL,
+                     previous_response_id TEXT,
+                     messages TEXT NOT NULL
+                 );",
+            )
+            .map_err(|e| format!("Failed to create the response table: {}", e))?;
+        Ok(SqliteResponseStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>, String> {
+        self.connection
+            .lock()
+            .map_err(|_| "the response store is poisoned".to_string())
+    }
+}
+
+impl ResponseStore for SqliteResponseStore {
+    fn get(&self, id: &str, owner: Option<&str>) -> Result<Option<StoredResponse>, String> {
+        let row = self
+            .lock()?
+            .query_row(
+                "SELECT model, previous_response_id, messages FROM responses
+                 WHERE id = ?1 AND owner IS ?2",
+                params![id, owner],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, String>(2)?,
+                    ))
+                },
+            )
+            .optional()
+            .map_err(|e| format!("Failed to read the response: {}", e))?;
+        let Some((model, previous_response_id, messages)) = row else {
+            return Ok(None);
+        };
+        Ok(Some(StoredResponse {
+            model,
+            owner: owner.map(str::to_string),
+            previous_response_id,
+            messages: serde_json::from_str(&messages)
+                .map_err(|e| format!("Invalid messages in response '{}': {}", id, e))?,
+        }))
+    }
+
+    fn put(&self, id: &str, response: StoredResponse) -> Result<(), String> {
+        let messages = serde_json::to_string(&response.messages)
+            .map_err(|e| format!("Failed to store the response: {}", e))?;
+        self.lock()?
+            .execute(
+                "INSERT OR REPLACE INTO responses (id, owner, model, previous_response_id, messages)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    id,
+                    response.owner,
+                    response.model,
+                    response.previous_response_id,
+                    messages
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store the response: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod response_store_tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn stored(text: &str, previous_response_id: Option<&str>) -> StoredResponse {
+        StoredResponse {
+            model: "gpt-4o".to_string(),
+            owner: Some("acme".to_string()),
+            previous_response_id: previous_response_id.map(str::to_string),
+            messages: vec![Message {
+                role: "user".to_string(),
+                input_text: text.to_string(),
+                input_image: None,
+                input_audio: None,
+                input_audio_format: None,
+                input_video: None,
+                input_file: None,
+            }],
+        }
+    }
+
+    fn texts(messages: Option<Vec<Message>>) -> Option<Vec<String>> {
+        messages.map(|messages| messages.into_iter().map(|m| m.input_text).collect())
+    }
+
+    #[test]
+    fn test_oldest_response_is_evicted() {
+        let start = Instant::now();
+
+        let store = MemoryResponseStore::new(2, DEFAULT_MAX_BYTES);
+        for (id, text) in [("resp_1", "one"), ("resp_2", "two"), ("resp_3", "three")] {
+            store.put(id, stored(text, None)).unwrap();
+        }
+        assert!(store.get("resp_1", Some("acme")).unwrap().is_none());
+        let two = store.get("resp_2", Some("acme")).unwrap().unwrap();
+        assert_eq!(two.messages[0].input_text, "two");
+        let three = store.get("resp_3", Some("acme")).unwrap().unwrap();
+        assert_eq!(three.messages[0].input_text, "three");
+
+        let bounded = MemoryResponseStore::new(10, 6);
+        for (id, text) in [("resp_1", "one"), ("resp_2", "two"), ("resp_3", "three")] {
+            bounded.put(id, stored(text, None)).unwrap();
+        }
+        assert!(bounded.get("resp_1", Some("acme")).unwrap().is_none());
+        assert!(bounded.get("resp_2", Some("acme")).unwrap().is_none());
+        assert!(bounded.get("resp_3", Some("acme")).unwrap().is_some());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_oldest_response_is_evicted took: {:?}", duration);
+    }
+
+    /// Check `store` chains the conversations of their owner only.
+    fn exercise(store: &dyn ResponseStore) {
+        store.put("resp_1", stored("one", None)).unwrap();
+        store.put("resp_2", stored("two", Some("resp_1"))).unwrap();
+        store
+            .put("resp_3", stored("three", Some("resp_2")))
+            .unwrap();
+        assert_eq!(
+            texts(store.conversation("resp_3", Some("acme")).unwrap()),
+            Some(vec![
+                "one".to_string(),
+                "two".to_string(),
+                "three".to_string()
+            ])
+        );
+        assert_eq!(store.conversation("resp_3", Some("globex")), Ok(None));
+        assert_eq!(store.conversation("resp_3", None), Ok(None));
+        assert_eq!(store.conversation("resp_nope", Some("acme")), Ok(None));
+    }
+
+    #[test]
+    fn test_conversation_follows_the_chain_of_its_owner() {
+        let start = Instant::now();
+
+        exercise(&MemoryResponseStore::default());
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_conversation_follows_the_chain_of_its_owner took: {:?}",
+            duration
+        );
+    }
+
+    #[test]
+    fn test_sqlite_response_store() {
+        let start = Instant::now();
+
+        exercise(&SqliteResponseStore::in_memory().unwrap());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_sqlite_response_store took: {:?}", duration);
+    }
+}
@@ -0,0 +1,195 @@
+//! Threads API: conversations kept server-side, so clients only send the new turns and
+//! several models can answer the same history.
+//!
+//! Threads belong to the tenant of the `X-Polytheus-Tenant` header they were created
+//! with, and are not found by anyone else.
+//!
+//! - `POST /v1/threads` creates a thread, with optional `messages` and `metadata`;
+//! - `GET /v1/threads` lists the threads of the tenant, the most recent first;
+//! - `GET /v1/threads/{id}` returns a thread and `DELETE` deletes it;
+//! - `GET /v1/threads/{id}/messages` returns its messages;
+//! - `POST /v1/threads/{id}/messages` appends `messages`; given a `model`, the model
+//!   then answers the whole thread and its answer is appended as well.
+use crate::api::{json_body, run_error, text_message, token_counts, AppState, RequestContext};
+use crate::polytheus::codec::{self, Dialect};
+use crate::polytheus::{Message, RunOptions, ThinkingLevel, Thread as StoredThread};
+use serde_json::{json, Value};
+
+/// Threads returned by a listing.
+const LIST_LIMIT: usize = 100;
+
+/// Handles `/v1/threads`: create (POST) or list (GET) threads.
+pub fn Threads(state: &AppState, context: &RequestContext, body: &[u8]) -> Result<Value, String> {
+    let owner = owner(context);
+    match context.method() {
+        "POST" => {
+            let request = optional_body(body)?;
+            let messages = match &request["messages"] {
+                Value::Null => vec![],
+                messages => decode_messages(messages)?,
+            };
+            let metadata = match &request["metadata"] {
+                Value::Null => json!({}),
+                metadata if metadata.is_object() => metadata.clone(),
+                _ => return Err("metadata must be an object".to_string()),
+            };
+            let thread = state
+                .threads
+                .create(owner.map(str::to_string), metadata, messages)?;
+            thread_json(&thread)
+        }
+        "GET" => {
+            // anonymous threads are only reachable by their id
+            if owner.is_none() {
+                return Err("Listing threads needs the X-Polytheus-Tenant header".to_string());
+            }
+            let threads = state.threads.list(owner, LIST_LIMIT)?;
+            let data: Vec<Value> = threads
+                .iter()
+                .map(|thread| {
+                    json!({
+                        "id": thread.id,
+                        "object": "thread",
+                        "created_at": thread.created_at,
+                        "metadata": thread.metadata,
+                        "message_count": thread.message_count
+                    })
+                })
+                .collect();
+            Ok(json!({ "object": "list", "data": data }))
+        }
+        other => Err(format!("Unsupported method {} on /v1/threads", other)),
+    }
+}
+
+/// Handles `/v1/threads/{path}`, where `path` is a thread id, optionally followed by
+/// `/messages`.
+pub async fn Thread(
+    state: &AppState,
+    context: &RequestContext,
+    path: &str,
+    body: &[u8],
+) -> Result<Value, String> {
+    let (id, messages_route) = match path.split_once('/') {
+        None => (path, false),
+        Some((id, "messages")) => (id, true),
+        Some(_) => return Err(format!("Unknown API path: /v1/threads/{}", path)),
+    };
+    let owner = owner(context);
+    let thread = state
+        .threads
+        .get(id, owner)?
+        .ok_or_else(|| format!("Thread '{}' not found", id))?;
+
+    match (context.method(), messages_route) {
+        ("GET", false) => thread_json(&thread),
+        ("DELETE", false) => {
+            let deleted = state.threads.delete(id, owner)?;
+            Ok(json!({ "id": id, "object": "thread.deleted", "deleted": deleted }))
+        }
+        ("GET", true) => Ok(json!({
+            "object": "list",
+            "thread_id": id,
+            "data": encode_messages(&thread.messages)?
+        })),
+        ("POST", true) => append(state, context, thread, optional_body(body)?).await,
+        (other, _) => Err(format!(
+            "Unsupported method {} on /v1/threads/{}",
+            other, path
+        )),
+    }
+}
+
+/// Append the `messages` of `request` to `thread`, then the answer of its `model`.
+///
+/// The turn is only stored if the thread didn't change while the model answered.
+async fn append(
+    state: &AppState,
+    context: &RequestContext,
+    thread: StoredThread,
+    request: Value,
+) -> Result<Value, String> {
+    let owner = thread.owner.as_deref();
+    let mut appended = decode_messages(&request["messages"])?;
+    let Some(model_name) = request["model"].as_str() else {
+        state.threads.append(&thread.id, owner, None, &appended)?;
+        return Ok(json!({
+            "object": "list",
+            "thread_id": thread.id,
+            "data": encode_messages(&appended)?
+        }));
+    };
+
+    let thinking_level = request["reasoning_effort"]
+        .as_str()
+        .map(str::parse::<ThinkingLevel>)
+        .transpose()?;
+    let options = RunOptions::from_request(context, &request);
+    let answered_len = thread.messages.len();
+    let mut history = thread.messages;
+    history.extend(appended.iter().cloned());
+    let completion = state
+        .polytheus
+        .run_with_options(model_name, history.clone(), thinking_level, &options)
+        .await
+        .map_err(run_error)?;
+
+    // the turn is only stored once answered
+    appended.push(text_message("assistant", &completion.text));
+    state
+        .threads
+        .append(&thread.id, owner, Some(answered_len), &appended)?;
+
+    let (prompt_tokens, completion_tokens) = token_counts(&history, &completion);
+    Ok(json!({
+        "object": "list",
+        "thread_id": thread.id,
+        "data": encode_messages(&appended)?,
+        "model": completion.model(model_name),
+        "finish_reason": completion.finish_reason.as_str(),
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens
+        }
+    }))
+}
+
+/// JSON body of a request, `null` when empty.
+fn optional_body(body: &[u8]) -> Result<Value, String> {
+    match body.iter().all(u8::is_ascii_whitespace) {
+        true => Ok(Value::Null),
+        false => json_body(body),
+    }
+}
+
+/// Messages of an OpenAI-like `messages` array.
+fn decode_messages(messages: &Value) -> Result<Vec<Message>, String> {
+    messages
+        .as_array()
+        .ok_or("you are missing the messages".to_string())?
+        .iter()
+        .map(codec::decode_message)
+        .collect()
+}
+
+/// Tenant the request is made for, owning the threads it creates.
+fn owner(context: &RequestContext) -> Option<&str> {
+    context.header("x-polytheus-tenant")
+}
+
+/// OpenAI-like `messages` array of `messages`.
+fn encode_messages(messages: &[Message]) -> Result<Vec<Value>, String> {
+    codec::encode_messages(messages, Dialect::OpenRouter)
+}
+
+/// JSON object of `thread`, with its messages.
+fn thread_json(thread: &StoredThread) -> Result<Value, String> {
+    Ok(json!({
+        "id": thread.id,
+        "object": "thread",
+        "created_at": thread.created_at,
+        "metadata": thread.metadata,
+        "messages": encode_messages(&thread.messages)?
+    }))
+}
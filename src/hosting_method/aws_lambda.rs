@@ -1,49 +1,111 @@
 use std::any::Any;
 use std::fmt;
 use std::ops::DerefMut;
+use std::sync::Arc;
 use std::{borrow, result};
 
 use lambda_http::aws_lambda_events::query_map::QueryMap;
 use lambda_http::http::header;
-use lambda_http::{service_fn, tracing, Body, Error, Request, RequestExt, Response};
+use lambda_http::{service_fn, Body, Error, Request, RequestExt, Response};
 
 use crate::polytheus::Message;
 
 use serde_json::{ser, Value};
 
 use crate::api;
+use crate::api::{ApiResponse, AppState};
 use crate::polytheus::Polytheus;
+use crate::telemetry::otel::{self, OtelConfig};
+use crate::telemetry::{self, LogConfig};
+use tracing::{info, info_span, warn, Instrument};
 
 pub async fn run() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    tracing::init_default_subscriber();
+    telemetry::init(&LogConfig::from_env(), OtelConfig::from_env());
 
-    lambda_http::run(service_fn(function_handler)).await
+    // Built once per execution environment and reused by every warm invocation.
+    let state = AppState::shared();
+
+    lambda_http::run(service_fn(move |event| {
+        function_handler(event, state.clone())
+    }))
+    .await
 }
 
 /// This is the main body for the function.
 /// Write your code inside it.
 /// There are some code example in the following URLs:
 /// - https://github.com/awslabs/aws-lambda-rust-runtime/tree/main/examples
-pub(crate) async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+pub(crate) async fn function_handler(
+    event: Request,
+    state: Arc<AppState>,
+) -> Result<Response<Body>, Error> {
     let path = event.uri().path(); // ex: "/users/42/posts/7"
+    let request_id = event
+        .lambda_context_ref()
+        .map(|context| context.request_id.clone())
+        .unwrap_or_default();
+    let span = info_span!(
+        "request",
+        otel.kind = "server",
+        request_id = %request_id,
+        path = %path,
+        http.status_code = tracing::field::Empty,
+    );
+    let start = std::time::Instant::now();
 
     let body = event.body();
 
-    println!("{:?}", body);
-
-    let struct_body = serde_json::from_slice::<Value>(body.as_ref()).map_err(|e| {
-        "Error to parsing the body of the request. deserialize error: ".to_string() + &e.to_string()
-    })?;
-
-    let result = api::router(path, struct_body).await;
+    let context = api::RequestContext::new(
+        event
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?))),
+    )
+    .with_method(event.method().as_str());
+
+    let result = api::router(&state, path, &context, body.as_ref())
+        .instrument(span.clone())
+        .await;
+
+    let status = match &result {
+        Ok(ApiResponse::Error(_)) | Err(_) => 400,
+        Ok(_) => 200,
+    };
+    span.record("http.status_code", status);
+    span.in_scope(|| {
+        info!(
+            status,
+            latency_ms = start.elapsed().as_millis() as u64,
+            "request handled"
+        )
+    });
+    // close the request span before exporting: the execution environment may be frozen
+    // as soon as the response is returned
+    drop(span);
+    if let Err(e) = otel::flush().await {
+        warn!(error = %e, "telemetry export failed");
+    }
 
     // Return something that implements IntoResponse.
     // It will be serialized to the right response event automatically by the runtime
     let resp = match result {
-        Ok(data) => Response::builder()
+        Ok(ApiResponse::Json(data)) => Response::builder()
             .status(200)
             .header("content-type", "application/json")
             .body(Body::from(serde_json::to_string(&data)?))?,
+        Ok(ApiResponse::Binary { content_type, data }) => Response::builder()
+            .status(200)
+            .header("content-type", content_type)
+            .body(Body::from(data))?,
+        Ok(ApiResponse::EventStream(events)) => Response::builder()
+            .status(200)
+            .header("content-type", "text/event-stream")
+            .header("cache-control", "no-cache")
+            .body(Body::from(api::encode_events(&events)))?,
+        Ok(ApiResponse::Error(data)) => Response::builder()
+            .status(400)
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_string(&data)?))?,
         Err(e) => Response::builder()
             .status(400)
             .header("content-type", "application/json")
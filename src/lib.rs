@@ -1,3 +1,4 @@
 pub mod api;
 pub mod hosting_method;
 pub mod polytheus;
+pub mod telemetry;
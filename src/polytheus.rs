@@ -1,8 +1,17 @@
 use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
+use std::collections::HashMap;
 use std::env;
-use tokio::time::{sleep, Duration};
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::Poll;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
+
+use crate::telemetry::otel;
+use crate::telemetry::redact;
 
 mod model;
 use model::{Model, Provider};
@@ -16,80 +25,85 @@ use licence::Licence;
 mod benchmark;
 use benchmark::Benchmark;
 
+mod http;
+pub use http::HttpConfig;
+
+mod replicate;
+pub use replicate::WebhookHeaders;
+use replicate::{CancelGuard, WaitError};
+
+mod usage;
+pub use usage::Usage;
+
+mod cache;
+pub use cache::{CacheStore, DiskStore, LruStore, ResponseCache};
+
+mod embedding;
+pub use embedding::{EmbedFuture, Embedder, Embeddings, OpenRouterEmbedder};
+
+mod audio;
+pub use audio::{AudioFile, Speech, SpeechRequest, TranscriptionRequest};
+
+mod image;
+pub use image::{GeneratedImages, ImageRequest};
+
+mod semantic_cache;
+pub use semantic_cache::{BruteForceIndex, SemanticCache, VectorIndex};
+
+mod params;
+pub use params::{cut_at_stop, GenerationParams};
+
+mod logprobs;
+pub use logprobs::{TokenLogprob, TopLogprob};
+
+mod finish_reason;
+pub use finish_reason::FinishReason;
+
+mod reasoning;
+
+mod thinking;
+pub use thinking::ThinkingLevel;
+
+pub mod codec;
+use codec::Dialect;
+
+mod input_schema;
+pub use input_schema::InputSchema;
+
+pub mod media;
+
+pub mod document;
+
+pub mod video;
+
+mod roles;
+
+mod threads;
+pub use threads::{
+    store_from_env as thread_store_from_env, MemoryThreadStore, SqliteThreadStore, Thread,
+    ThreadStore, ThreadSummary,
+};
+
+/// Maximum number of choices of a single request.
+pub const MAX_CHOICES: u32 = 10;
+
+/// Time a failed Replicate schema fetch is remembered before being tried again.
+const SCHEMA_RETRY_DELAY: Duration = Duration::from_secs(60);
+
 /// Build a Replicate prediction request body.
 ///
-/// This is intentionally "model-agnostic" and sends OpenAI-like `messages` under `input.messages`,
-/// plus an optional thinking-level property if the model declares one.
+/// `messages` become the inputs `schema` describes for `model`; the thinking level and
+/// generation parameters are added by the caller.
 fn build_replicate_request_body(
+    model: &Model,
+    schema: &InputSchema,
     messages: &[Message],
-    thinking_level_property: Option<&str>,
-    thinking_level: Option<&str>,
     stream: bool,
 ) -> Result<Value, String> {
     let mut body_map = Map::new();
     body_map.insert("stream".to_string(), json!(stream));
 
-    let mut input_map = Map::new();
-
-    // Convert messages to OpenAI-like format
-    let formatted_messages: Vec<Value> = messages
-        .iter()
-        .map(|msg| {
-            let mut content_list = vec![json!({ "type": "text", "text": msg.input_text })];
-
-            if let Some(img) = &msg.input_image {
-                content_list.push(json!({
-                    "type": "image_url",
-                    "image_url": {
-                        "url": json!(img)
-                    }
-                }));
-            }
-            if let Some(aud) = &msg.input_audio {
-                content_list.push(json!({
-                    "type": "input_audio",
-                    "inputAudio": {
-                        "data": aud,
-                        "format": msg.input_audio_format.as_deref().unwrap_or("unknown")
-                    }
-                }));
-            }
-            if let Some(vid) = &msg.input_video {
-                content_list.push(json!({
-                    "type": "video_url",
-                    "inputVideo": {
-                        "url": vid
-                    }
-                }));
-            }
-
-            json!({
-                "role": msg.role,
-                "content": content_list
-            })
-        })
-        .collect();
-
-    input_map.insert("messages".to_string(), json!(formatted_messages));
-
-    // Handle thinking level
-    if let (Some(tl_prop), Some(tl)) = (thinking_level_property, thinking_level) {
-        let tl_val = tl.trim();
-        let val = if let Ok(b) = tl_val.parse::<bool>() {
-            Value::Bool(b)
-        } else if let Ok(i) = tl_val.parse::<i64>() {
-            Value::Number(i.into())
-        } else if let Ok(f) = tl_val.parse::<f64>() {
-            if let Some(n) = serde_json::Number::from_f64(f) {
-                Value::Number(n)
-            } else {
-                Value::String(tl_val.to_string())
-            }
-        } else {
-            Value::String(tl_val.to_string())
-        };
-        input_map.insert(tl_prop.to_string(), val);
-    }
+    let input_map = schema.build_input(model, messages)?;
 
     body_map.insert("input".to_string(), Value::Object(input_map));
     Ok(Value::Object(body_map))
@@ -138,9 +152,12 @@ pub struct PredictionResponse {
 
     /// URLs related to this prediction.
     pub urls: Option<PredictionUrls>,
+
+    /// Prediction metrics (token counts, predict time...), once it has run.
+    pub metrics: Option<Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Message {
     pub role: String,
     pub input_text: String,
@@ -148,6 +165,91 @@ pub struct Message {
     pub input_audio: Option<String>,
     pub input_audio_format: Option<String>,
     pub input_video: Option<String>,
+
+    /// Document (e.g. a PDF) as an http(s) or base64 `data:` URL.
+    #[serde(default)]
+    pub input_file: Option<String>,
+}
+
+impl Message {
+    /// Input modalities of the attachments of the message (e.g. "image").
+    pub fn attachment_modalities(&self) -> Vec<&'static str> {
+        [
+            (self.input_image.is_some(), "image"),
+            (self.input_audio.is_some(), "audio"),
+            (self.input_video.is_some(), "video"),
+            (self.input_file.is_some(), "file"),
+        ]
+        .into_iter()
+        .filter_map(|(attached, modality)| attached.then_some(modality))
+        .collect()
+    }
+}
+
+/// Per-request options of `Polytheus::run_with_options`.
+///
+/// Every field left to `None` falls back on the model's own setting, then on the default.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Maximum time to wait for the model to answer.
+    pub timeout: Option<Duration>,
+
+    /// Maximum backoff between two polls of a Replicate prediction.
+    pub max_poll_backoff: Option<Duration>,
+
+    /// Neither read nor store this request in the response caches.
+    pub no_cache: bool,
+
+    /// Tenant the request is made for; semantic cache hits never cross tenants.
+    pub tenant: Option<String>,
+
+    /// Sampling parameters, rejected when the model doesn't support one of them.
+    pub params: GenerationParams,
+}
+
+/// Answer of a model run.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Completion {
+    /// Text generated by the model.
+    pub text: String,
+
+    /// Tokens and cost of the run; the cost is zero when served from the cache.
+    pub usage: Usage,
+
+    /// Whether the answer was served from the response or semantic cache.
+    pub cached: bool,
+
+    /// Log probability of each generated token, when requested and supported.
+    #[serde(default)]
+    pub logprobs: Option<Vec<TokenLogprob>>,
+
+    /// Why the model stopped generating.
+    #[serde(default)]
+    pub finish_reason: FinishReason,
+
+    /// Explanation of the model when it refused to answer.
+    #[serde(default)]
+    pub refusal: Option<String>,
+
+    /// Reasoning of the model before its answer, when it returned it.
+    #[serde(default)]
+    pub reasoning: Option<String>,
+
+    /// Stop sequence the answer ended on, when known.
+    #[serde(default)]
+    pub stop_sequence: Option<String>,
+
+    /// Model that answered instead of the requested one, when the request was routed
+    /// to a model taking one of its attachments (e.g. an MP4 video).
+    #[serde(default)]
+    pub routed_to: Option<String>,
+}
+
+impl Completion {
+    /// Name of the model that answered: `requested`, unless the request was routed.
+    pub fn model<'a>(&'a self, requested: &'a str) -> &'a str {
+        self.routed_to.as_deref().unwrap_or(requested)
+    }
 }
 
 #[derive(Debug)]
@@ -157,293 +259,734 @@ pub struct Polytheus {
 
     licences: Option<Vec<Licence>>,
     benchmarks: Vec<Benchmark>,
+
+    /// Public URL Replicate calls back when a prediction completes.
+    /// When set, predictions are awaited through webhooks instead of polling
+    /// (never on Lambda, see `replicate::webhook_url_from_env`).
+    replicate_webhook_url: Option<String>,
+
+    /// Signing secret (`whsec_...`) Replicate webhooks are verified with; without it,
+    /// every webhook is refused.
+    replicate_webhook_secret: Option<String>,
+
+    /// HTTP client reused by every upstream call (keeps TLS sessions and pooled connections).
+    client: Client,
+
+    /// Configuration `client` was built from, including per-provider base URLs.
+    http_config: HttpConfig,
+
+    /// Exact-match cache of completions, disabled when `None`.
+    cache: Option<ResponseCache>,
+
+    /// Cache matching prompts by meaning, consulted after an exact miss; disabled when `None`.
+    semantic_cache: Option<SemanticCache>,
+
+    /// Input schemas fetched from Replicate, by model name, for models the catalog
+    /// doesn't describe; a failed fetch keeps the default schema until its retry time.
+    input_schemas: Mutex<HashMap<String, (InputSchema, Option<Instant>)>>,
 }
 
 impl Polytheus {
     /// fill Polytheus with all the object obligated to allow Polytheus to work
     /// that says models and benchmarks
     pub fn fast_fill() -> Polytheus {
+        let client = http::shared_client().expect("invalid POLYTHEUS_HTTP_* configuration");
+        let http_config = HttpConfig::from_env();
         Polytheus {
             models: Model::fill(),
             organizations: None,
             licences: None,
             benchmarks: Benchmark::fill(),
+            replicate_webhook_url: replicate::webhook_url_from_env(),
+            replicate_webhook_secret: env::var("REPLICATE_WEBHOOK_SECRET").ok(),
+            semantic_cache: SemanticCache::from_env(client.clone(), &http_config),
+            client,
+            http_config,
+            cache: ResponseCache::from_env(),
+            input_schemas: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Serve identical requests from `cache` instead of calling the provider again.
+    pub fn with_cache(mut self, cache: ResponseCache) -> Polytheus {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Serve prompts similar enough to a previous one from `semantic_cache`.
+    pub fn with_semantic_cache(mut self, semantic_cache: SemanticCache) -> Polytheus {
+        self.semantic_cache = Some(semantic_cache);
+        self
+    }
+
+    /// Replace the HTTP client by a dedicated one built from `http_config`.
+    pub fn with_http_config(mut self, http_config: HttpConfig) -> Result<Polytheus, String> {
+        self.client = http_config.build_client()?;
+        self.http_config = http_config;
+        Ok(self)
+    }
+
+    /// Run a model with the default options.
     pub async fn run(
         &self,
         model_name: &str,
         messages: Vec<Message>,
-        thinking_level: Option<String>,
-    ) -> Result<String, String> {
-        println!("--- Preparing to run model '{}' ---", model_name);
-        // Find the model by name
-        let model = self
-            .get_model_by_name(model_name)
-            .ok_or_else(|| format!("Model '{}' not found", model_name))?;
+        thinking_level: Option<ThinkingLevel>,
+    ) -> Result<Completion, String> {
+        self.run_with_options(model_name, messages, thinking_level, &RunOptions::default())
+            .await
+    }
 
-        let client = Client::new();
+    /// Run a model, overriding its timeouts with `options`.
+    ///
+    /// Identical requests are answered from the response cache when one is configured,
+    /// then similar ones from the semantic cache, unless `options.no_cache` is set.
+    ///
+    /// Every run is traced in a `polytheus.run` span recording the provider, latency,
+    /// token counts, cost, cache outcome and status of the call.
+    pub async fn run_with_options(
+        &self,
+        model_name: &str,
+        messages: Vec<Message>,
+        thinking_level: Option<ThinkingLevel>,
+        options: &RunOptions,
+    ) -> Result<Completion, String> {
+        let span = info_span!(
+            "polytheus.run",
+            model = model_name,
+            provider = field::Empty,
+            routed_to = field::Empty,
+            cache = field::Empty,
+            latency_ms = field::Empty,
+            prompt_tokens = field::Empty,
+            completion_tokens = field::Empty,
+            cost_usd = field::Empty,
+            status = field::Empty,
+        );
+        let start = Instant::now();
+        let cache = self.cache.as_ref().filter(|_| !options.no_cache);
+        // parameter-less requests keep the keys they had before parameters existed
+        let params = if options.params.is_empty() {
+            Value::Null
+        } else {
+            json!(options.params)
+        };
+        let thinking = thinking_level.map(|level| level.to_string());
+        let key =
+            cache.map(|_| cache::cache_key(model_name, &messages, thinking.as_deref(), &params));
+        let mut cache_status = if key.is_some() { "miss" } else { "bypass" };
+        let mut hit = cache
+            .zip(key.as_deref())
+            .and_then(|(cache, key)| cache.get(key));
+        if hit.is_some() {
+            cache_status = "hit";
+        }
 
-        // Extract thinking level from the last message if present
-        let thinking_level_property = model.get_thinking_level_property();
+        // on an exact miss, look for an answer to a similar prompt
+        let semantic = self
+            .semantic_cache
+            .as_ref()
+            .filter(|_| !options.no_cache && hit.is_none())
+            .zip(SemanticCache::prompt(&messages));
+        let mut pending = None;
+        if let Some((semantic, prompt)) = semantic {
+            let scope = SemanticCache::scope(
+                model_name,
+                options.tenant.as_deref(),
+                thinking.as_deref(),
+                &params,
+            );
+            match semantic
+                .lookup(&scope, &prompt)
+                .instrument(span.clone())
+                .await
+            {
+                Ok((_, Some(completion))) => {
+                    cache_status = "semantic_hit";
+                    hit = Some(completion);
+                }
+                Ok((vector, None)) => pending = Some((semantic, scope, vector)),
+                Err(e) => warn!(parent: &span, error = %e, "semantic cache lookup failed"),
+            }
+        }
+        span.record("cache", cache_status);
+
+        let result = match hit {
+            Some(completion) => Ok(Completion {
+                usage: Usage {
+                    cost: Some(0.0),
+                    ..completion.usage
+                },
+                cached: true,
+                ..completion
+            }),
+            None => {
+                self.execute(model_name, messages, thinking_level, options)
+                    .instrument(span.clone())
+                    .await
+            }
+        };
+        if let Some(completion) = result.as_ref().ok().filter(|completion| !completion.cached) {
+            if let (Some(cache), Some(key)) = (cache, key) {
+                cache.put(key, completion.clone());
+            }
+            if let Some((semantic, scope, vector)) = pending {
+                semantic.store(&scope, vector, completion.clone());
+            }
+        }
 
-        let thinking_levels_authorized = model.get_thinking_levels_authorized();
-        if let Some(tla) = &thinking_levels_authorized {
-            if let Some(tl) = &thinking_level {
-                if !tla.contains(&tl) {
-                    return Err(format!(
-                        "Thinking level '{}' not authorized for model '{}'",
-                        tl, model_name
-                    ));
+        let _entered = span.enter();
+        let latency_ms = start.elapsed().as_millis() as u64;
+        span.record("latency_ms", latency_ms);
+        let provider = self
+            .get_model_by_name(model_name)
+            .map(|model| format!("{:?}", model.get_provider()))
+            .unwrap_or_else(|| "unknown".to_string());
+        otel::record_run(
+            &provider,
+            model_name,
+            latency_ms,
+            result.as_ref().ok().map(|completion| &completion.usage),
+        );
+        match result {
+            Ok(completion) => {
+                let usage = &completion.usage;
+                span.record("status", "ok");
+                if let Some(tokens) = usage.prompt_tokens {
+                    span.record("prompt_tokens", tokens);
+                }
+                if let Some(tokens) = usage.completion_tokens {
+                    span.record("completion_tokens", tokens);
                 }
+                if let Some(cost) = usage.cost {
+                    span.record("cost_usd", cost);
+                }
+                info!(
+                    latency_ms,
+                    prompt_tokens = usage.prompt_tokens,
+                    completion_tokens = usage.completion_tokens,
+                    cost_usd = usage.cost,
+                    cached = completion.cached,
+                    finish_reason = completion.finish_reason.as_str(),
+                    "model run succeeded"
+                );
+                Ok(completion)
+            }
+            Err(e) => {
+                span.record("status", "error");
+                warn!(latency_ms, error = %e, "model run failed");
+                Err(e)
             }
         }
+    }
 
-        let roles_authorized = model.get_roles_authorized();
-        if let Some(ra) = &roles_authorized {
-            for msg in &messages {
-                if !ra.contains(&msg.role) {
+    /// Run a model `n` times concurrently, for `n` independent choices.
+    ///
+    /// Several choices are only useful when they differ, so the caches are bypassed
+    /// when `n` is above one and a `seed` is offset by the index of each choice (see
+    /// `choice_options`). Fails when any of the runs fails.
+    pub async fn run_choices(
+        &self,
+        model_name: &str,
+        messages: Vec<Message>,
+        thinking_level: Option<ThinkingLevel>,
+        options: &RunOptions,
+        n: u32,
+    ) -> Result<Vec<Completion>, String> {
+        if n == 0 || n > MAX_CHOICES {
+            return Err(format!(
+                "n must be between 1 and {}, got {}",
+                MAX_CHOICES, n
+            ));
+        }
+        let choices = choice_options(options, n);
+        let runs = choices
+            .iter()
+            .map(|options| {
+                self.run_with_options(model_name, messages.clone(), thinking_level, options)
+            })
+            .collect();
+        join_all(runs).await.into_iter().collect()
+    }
+
+    /// Embed `inputs` with an embedding model, in batches of `embedding::MAX_BATCH`.
+    ///
+    /// `dimensions` shortens the vectors, for models that support it.
+    pub async fn embed(
+        &self,
+        model_name: &str,
+        inputs: Vec<String>,
+        dimensions: Option<usize>,
+    ) -> Result<Embeddings, String> {
+        let span = info_span!(
+            "polytheus.embed",
+            model = model_name,
+            inputs = inputs.len(),
+            provider = field::Empty,
+            latency_ms = field::Empty,
+            prompt_tokens = field::Empty,
+            cost_usd = field::Empty,
+            status = field::Empty,
+        );
+        let start = Instant::now();
+        let model = self.get_model_by_name(model_name);
+        let result = async {
+            let model = model.ok_or_else(|| format!("Model '{}' not found", model_name))?;
+            if !model.has_capability("embedding") {
+                return Err(format!("Model '{}' is not an embedding model", model_name));
+            }
+            if inputs.is_empty() {
+                return Err("you are missing the input".to_string());
+            }
+            if let (Some(dimensions), Some(max)) = (dimensions, model.get_embedding_dimensions()) {
+                if dimensions == 0 || dimensions > max as usize {
                     return Err(format!(
-                        "Role '{}' not authorized for model '{}'",
-                        msg.role, model_name
+                        "dimensions must be between 1 and {} for model '{}'",
+                        max, model_name
                     ));
                 }
             }
-        }
 
-        match model.get_provider() {
-            // code for running the model if it's a replicate model
-            Provider::Replicate => {
-                println!("--- Using Replicate Provider ---");
-                let api_token = env::var("REPLICATE_API_TOKEN")
-                    .map_err(|_| "REPLICATE_API_TOKEN not set".to_string())?;
+            match model.get_provider() {
+                Provider::OpenRouter => OpenRouterEmbedder::new(
+                    self.client.clone(),
+                    &self.http_config,
+                    model.get_apiurl(),
+                )
+                .embed_batched(&inputs, dimensions)
+                .await
+                .map(|embeddings| Embeddings {
+                    usage: embeddings.usage.priced(model.get_price()),
+                    ..embeddings
+                }),
+                Provider::Replicate => Err(format!(
+                    "Embeddings are not supported for Replicate models ('{}')",
+                    model_name
+                )),
+            }
+        }
+        .instrument(span.clone())
+        .await;
+
+        finish_call(
+            &span,
+            model_name,
+            model,
+            start,
+            result.as_ref().map(|embeddings| &embeddings.usage),
+            "embedding",
+        );
+        result
+    }
 
-                println!("--- REPLICATE_API_TOKEN retrieved ---");
+    /// Generate `request.n` images with an image model, or edit `request.image`.
+    ///
+    /// Replicate image models produce one image per prediction, so `n` predictions
+    /// are made one after the other.
+    pub async fn generate_images(
+        &self,
+        model_name: &str,
+        request: &ImageRequest,
+        options: &RunOptions,
+    ) -> Result<GeneratedImages, String> {
+        let span = info_span!(
+            "polytheus.images",
+            model = model_name,
+            n = request.n,
+            provider = field::Empty,
+            latency_ms = field::Empty,
+            cost_usd = field::Empty,
+            status = field::Empty,
+        );
+        let start = Instant::now();
+        let model = self.get_model_by_name(model_name);
+        let result = async {
+            let model = model.ok_or_else(|| format!("Model '{}' not found", model_name))?;
+            if !model.has_output_modality("image") {
+                return Err(format!("Model '{}' doesn't generate images", model_name));
+            }
+            if request.image.is_some() && !model.has_input_modality("image") {
+                return Err(format!("Model '{}' can't edit images", model_name));
+            }
+            if request.n == 0 || request.n > image::MAX_IMAGES {
+                return Err(format!("n must be between 1 and {}", image::MAX_IMAGES));
+            }
+            let body = image::build_replicate_input(model, request)?;
+
+            match model.get_provider() {
+                Provider::Replicate => {
+                    let mut images = GeneratedImages {
+                        urls: vec![],
+                        usage: Usage {
+                            cost: Some(0.0),
+                            ..Usage::default()
+                        },
+                    };
+                    while images.urls.len() < request.n as usize {
+                        let prediction = self
+                            .replicate_prediction(model, body.clone(), options)
+                            .await?;
+                        let urls = image::output_urls(&prediction["output"]);
+                        if urls.is_empty() {
+                            return Err(format!(
+                                "Replicate succeeded but returned no image: {}",
+                                prediction
+                            ));
+                        }
+                        let usage = Usage::from_replicate(&prediction).priced(model.get_price());
+                        images.usage.cost = images.usage.cost.zip(usage.cost).map(|(a, b)| a + b);
+                        images.urls.extend(urls);
+                    }
+                    images.urls.truncate(request.n as usize);
+                    Ok(images)
+                }
+                Provider::OpenRouter => Err(format!(
+                    "Image generation is not supported for OpenRouter models ('{}')",
+                    model_name
+                )),
+            }
+        }
+        .instrument(span.clone())
+        .await;
+
+        finish_call(
+            &span,
+            model_name,
+            model,
+            start,
+            result.as_ref().map(|images| &images.usage),
+            "image generation",
+        );
+        result
+    }
 
-                let url = &model.get_apiurl();
+    /// Name of the first model of the catalog producing images, able to edit one when
+    /// `edit` is set.
+    pub fn default_image_model(&self, edit: bool) -> Option<&str> {
+        self.models
+            .iter()
+            .find(|model| {
+                model.has_output_modality("image") && (!edit || model.has_input_modality("image"))
+            })
+            .map(|model| model.get_name())
+    }
 
-                println!("--- API URL Retrieved: {} ---", url);
+    /// Name of the first model of the catalog with `capability` (e.g. "transcription").
+    pub fn default_model_with(&self, capability: &str) -> Option<&str> {
+        self.models
+            .iter()
+            .find(|model| model.has_capability(capability))
+            .map(|model| model.get_name())
+    }
 
-                // Default behavior: non-streaming (poll the prediction "get" URL and return a normal response).
-                let body = build_replicate_request_body(
-                    &messages,
-                    thinking_level_property,
-                    thinking_level.as_deref(),
-                    false,
-                )?;
-
-                println!("request body: {}", body);
-
-                // 4. POST Request to get the stream_url
-                let response = client
-                    .post(*url)
-                    .header("Authorization", format!("Bearer {}", api_token))
-                    .json(&body)
-                    .send()
-                    .await
-                    .map_err(|e| format!("Failed to send request: {}", e))?;
+    /// Names of the chat models of the catalog, in catalog order.
+    pub fn text_models(&self) -> Vec<&str> {
+        self.models
+            .iter()
+            .filter(|model| model.is_chat_model())
+            .map(|model| model.get_name())
+            .collect()
+    }
 
-                println!("--- API Response Received ---");
+    /// Transcribe a recording with a speech-to-text model.
+    ///
+    /// The recording is uploaded to Replicate first, so long meetings aren't limited
+    /// by the size of a data URL.
+    pub async fn transcribe(
+        &self,
+        model_name: &str,
+        request: &TranscriptionRequest,
+        options: &RunOptions,
+    ) -> Result<Completion, String> {
+        let span = info_span!(
+            "polytheus.transcribe",
+            model = model_name,
+            bytes = request.file.data.len(),
+            provider = field::Empty,
+            latency_ms = field::Empty,
+            prompt_tokens = field::Empty,
+            cost_usd = field::Empty,
+            status = field::Empty,
+        );
+        let start = Instant::now();
+        let model = self.get_model_by_name(model_name);
+        let result = async {
+            let model = model.ok_or_else(|| format!("Model '{}' not found", model_name))?;
+            let audio_parameter = model
+                .get_audio_parameters()
+                .filter(|_| model.has_input_modality("audio") && model.has_output_modality("text"))
+                .ok_or_else(|| format!("Model '{}' can't transcribe audio", model_name))?;
+            if request.file.data.is_empty() {
+                return Err("you are missing the file".to_string());
+            }
 
-                // Capture status before consuming the response body (text/json consume the Response)
-                let status = response.status();
-                if status != StatusCode::OK && status != StatusCode::CREATED {
-                    let error_text: String = response
-                        .text()
-                        .await
-                        .map_err(|e| format!("error reading error body: {}", e))?;
-                    return Err(format!(
-                        "First API call failed with status: {} and body: {}",
-                        status, error_text
-                    ));
+            match model.get_provider() {
+                Provider::Replicate => {
+                    let api_token = env::var("REPLICATE_API_TOKEN")
+                        .map_err(|_| "REPLICATE_API_TOKEN not set".to_string())?;
+                    let file_url = replicate::upload_file(
+                        &self.client,
+                        self.http_config.base_url(&Provider::Replicate),
+                        &api_token,
+                        &request.file.filename,
+                        &request.file.content_type,
+                        &request.file.data,
+                    )
+                    .await?;
+                    let body =
+                        audio::build_transcription_input(audio_parameter, &file_url, request);
+                    let prediction = self.replicate_prediction(model, body, options).await?;
+                    Ok(Completion {
+                        text: extract_replicate_output_text(&prediction["output"]),
+                        usage: Usage::from_replicate(&prediction).priced(model.get_price()),
+                        ..Completion::default()
+                    })
                 }
+                Provider::OpenRouter => Err(format!(
+                    "Transcription is not supported for OpenRouter models ('{}')",
+                    model_name
+                )),
+            }
+        }
+        .instrument(span.clone())
+        .await;
+
+        finish_call(
+            &span,
+            model_name,
+            model,
+            start,
+            result.as_ref().map(|completion| &completion.usage),
+            "transcription",
+        );
+        result
+    }
 
-                // 5. Deserialize the response to get the stream URL
-                let prediction: PredictionResponse = response
-                    .json()
-                    .await
-                    .map_err(|e| format!("Failed to parse prediction response: {}", e))?;
+    /// Read `request.input` aloud with a text-to-speech model.
+    pub async fn speak(
+        &self,
+        model_name: &str,
+        request: &SpeechRequest,
+        options: &RunOptions,
+    ) -> Result<Speech, String> {
+        let span = info_span!(
+            "polytheus.speak",
+            model = model_name,
+            chars = request.input.chars().count(),
+            provider = field::Empty,
+            latency_ms = field::Empty,
+            prompt_tokens = field::Empty,
+            cost_usd = field::Empty,
+            status = field::Empty,
+        );
+        let start = Instant::now();
+        let model = self.get_model_by_name(model_name);
+        let result = async {
+            let model = model.ok_or_else(|| format!("Model '{}' not found", model_name))?;
+            if !model.has_output_modality("audio") {
+                return Err(format!("Model '{}' doesn't generate audio", model_name));
+            }
+            let body = audio::build_speech_input(request)?;
 
-                // If Replicate already returned an output (rare for async predictions), return it.
-                if let Some(output) = prediction.output.as_ref() {
-                    return Ok(extract_replicate_output_text(output));
+            match model.get_provider() {
+                Provider::Replicate => {
+                    let prediction = self.replicate_prediction(model, body, options).await?;
+                    let url = audio::output_url(&prediction["output"]).ok_or_else(|| {
+                        format!("Replicate succeeded but returned no audio: {}", prediction)
+                    })?;
+                    let audio = http::download(&self.client, url, http::MAX_DOWNLOAD_BYTES).await?;
+                    let (audio, content_type) = audio::speech_audio(request.format(), audio)?;
+                    Ok(Speech {
+                        audio,
+                        content_type: content_type.to_string(),
+                        usage: Usage::from_replicate(&prediction).priced(model.get_price()),
+                    })
                 }
+                Provider::OpenRouter => Err(format!(
+                    "Speech is not supported for OpenRouter models ('{}')",
+                    model_name
+                )),
+            }
+        }
+        .instrument(span.clone())
+        .await;
+
+        finish_call(
+            &span,
+            model_name,
+            model,
+            start,
+            result.as_ref().map(|speech| &speech.usage),
+            "speech",
+        );
+        result
+    }
 
-                // Poll the prediction "get" URL until it succeeds.
-                let get_url = prediction
-                    .urls
-                    .as_ref()
-                    .and_then(|u| u.get.as_deref())
-                    .map(|s| s.to_string())
-                    .or_else(|| {
-                        prediction
-                            .id
-                            .as_deref()
-                            .map(|id| format!("https://api.replicate.com/v1/predictions/{}", id))
-                    })
-                    .ok_or_else(|| {
-                        "Replicate prediction response missing urls.get and id".to_string()
-                    })?;
+    /// Download the images at `urls` and encode them in base64.
+    pub async fn images_base64(&self, urls: &[String]) -> Result<Vec<String>, String> {
+        let mut encoded = Vec::with_capacity(urls.len());
+        for url in urls {
+            encoded.push(image::download_base64(&self.client, url).await?);
+        }
+        Ok(encoded)
+    }
 
-                let timeout = Duration::from_secs(120);
-                let mut delay = Duration::from_millis(200);
-                let start = std::time::Instant::now();
+    /// Deliver the Replicate webhook `body` to the prediction waiting on it, once its
+    /// `headers` prove Replicate sent it.
+    pub fn receive_replicate_webhook(
+        &self,
+        headers: &WebhookHeaders,
+        body: &[u8],
+    ) -> Result<(), String> {
+        let secret = self
+            .replicate_webhook_secret
+            .as_deref()
+            .ok_or("REPLICATE_WEBHOOK_SECRET not set, Replicate webhooks can't be verified")?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| format!("Invalid system time: {}", e))?
+            .as_secs();
+        replicate::verify_webhook(secret, headers, body, now)?;
+        let payload = serde_json::from_slice(body)
+            .map_err(|e| format!("Invalid Replicate webhook payload: {}", e))?;
+        replicate::receive_webhook(payload)
+    }
 
-                loop {
-                    if start.elapsed() > timeout {
-                        return Err(format!(
-                            "Replicate prediction timed out after {:?}",
-                            timeout
-                        ));
-                    }
+    /// Validate the request and call the model's provider.
+    async fn execute(
+        &self,
+        model_name: &str,
+        messages: Vec<Message>,
+        thinking_level: Option<ThinkingLevel>,
+        options: &RunOptions,
+    ) -> Result<Completion, String> {
+        // Find the model by name
+        let model = self
+            .get_model_by_name(model_name)
+            .ok_or_else(|| format!("Model '{}' not found", model_name))?;
+        tracing::Span::current().record("provider", field::debug(model.get_provider()));
+        if model.has_capability("transcription") || model.has_capability("speech") {
+            return Err(format!(
+                "Model '{}' isn't a chat model, use transcribe or speak instead",
+                model_name
+            ));
+        }
+        if !model.has_output_modality("text") {
+            return Err(format!(
+                "Model '{}' doesn't generate text, use embed or generate_images instead",
+                model_name
+            ));
+        }
 
-                    let poll_resp = client
-                        .get(&get_url)
-                        .header("Authorization", format!("Bearer {}", api_token))
-                        .send()
-                        .await
-                        .map_err(|e| format!("Failed to poll prediction: {}", e))?;
-
-                    let poll_status = poll_resp.status();
-                    if !poll_status.is_success() {
-                        let error_text = poll_resp
-                            .text()
-                            .await
-                            .map_err(|e| format!("error reading poll error body: {}", e))?;
-                        return Err(format!(
-                            "Replicate poll failed with status: {} and body: {}",
-                            poll_status, error_text
-                        ));
-                    }
+        let client = &self.client;
+
+        // adapted first, so that the roles of the messages added for documents and videos
+        // are normalized too
+        let messages = self.adapt_attachments(model, messages).await?;
+        let (model, routed_to) = match self.route_videos(model, &messages) {
+            Some(routed) => {
+                info!(
+                    requested = model_name,
+                    routed = routed.get_name(),
+                    "video can't be sampled, routed to a model taking video"
+                );
+                (routed, Some(routed.get_name().to_string()))
+            }
+            None => (model, None),
+        };
+        if let Some(routed) = &routed_to {
+            tracing::Span::current().record("provider", field::debug(model.get_provider()));
+            tracing::Span::current().record("routed_to", routed.as_str());
+        }
 
-                    let poll_json: Value = poll_resp
-                        .json()
-                        .await
-                        .map_err(|e| format!("Failed to parse poll response JSON: {}", e))?;
-
-                    let status_str = poll_json
-                        .get("status")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("unknown");
-
-                    match status_str {
-                        "succeeded" => {
-                            let output = poll_json.get("output").ok_or_else(|| {
-                                format!("Replicate succeeded but missing output: {}", poll_json)
-                            })?;
-                            return Ok(extract_replicate_output_text(output));
-                        }
-                        "failed" | "canceled" => {
-                            return Err(format!(
-                                "Replicate prediction {}: {}",
-                                status_str, poll_json
-                            ));
-                        }
-                        _ => {
-                            sleep(delay).await;
-                            delay = std::cmp::min(delay * 2, Duration::from_secs(2));
-                        }
+        let schema = match model.get_provider() {
+            Provider::Replicate => Some(self.replicate_input_schema(model).await),
+            Provider::OpenRouter => None,
+        };
+        // prompt inputs place the system messages themselves
+        let system_input = schema
+            .as_ref()
+            .is_some_and(|schema| schema.messages.is_none());
+        let messages = roles::normalize(model, messages, system_input)?;
+        self.check_modalities(model, &messages)?;
+        let messages = self.prepare_images(model, messages).await?;
+
+        match model.get_provider() {
+            // code for running the model if it's a replicate model
+            Provider::Replicate => {
+                // Default behavior: non-streaming (poll the prediction "get" URL and return a normal response).
+                let schema = schema.unwrap_or_default();
+                let mut body = build_replicate_request_body(model, &schema, &messages, false)?;
+                if let Some(input) = body["input"].as_object_mut() {
+                    if let Some(level) = thinking_level {
+                        level.apply(model, input)?;
                     }
+                    options.params.apply(model, input)?;
                 }
+
+                let prediction = self.replicate_prediction(model, body, options).await?;
+                let output = prediction.get("output").ok_or_else(|| {
+                    format!("Replicate succeeded but missing output: {}", prediction)
+                })?;
+                let usage = Usage::from_replicate(&prediction).priced(model.get_price());
+                let (text, reasoning) =
+                    reasoning::split_thinking(extract_replicate_output_text(output));
+                let (text, stop_sequence) = emulate_stop(model, &options.params, text);
+                Ok(Completion {
+                    text,
+                    usage,
+                    reasoning,
+                    stop_sequence,
+                    finish_reason: FinishReason::from_replicate(
+                        &prediction,
+                        options.params.max_tokens,
+                    ),
+                    routed_to,
+                    ..Completion::default()
+                })
             }
             // code for running the model if it's an openrouter model
             Provider::OpenRouter => {
-                println!("--- Using OpenRouter Provider ---");
-
-                let api_key = env::var("OPENROUTER_API_KEY")
-                    .map_err(|_| "OPENROUTER_API_KEY not set".to_string())?;
-                println!("--- OPENROUTER_API_KEY retrieved ---");
-
-                let url = "https://openrouter.ai/api/v1/chat/completions";
+                let url = format!(
+                    "{}/chat/completions",
+                    self.http_config.base_url(&Provider::OpenRouter)
+                );
 
                 // Choose which model id to send. Many people store a model id like "openai/gpt-5-codex"
                 // in model.apiurl or model.name; adapt this as needed:
                 let model_id = model.get_apiurl();
 
-                // Build the messages payload
-                let formatted_messages: Vec<Value> = messages
-                    .iter()
-                    .map(|msg| {
-                        let mut content_list =
-                            vec![json!({ "type": "text", "text": msg.input_text })];
-
-                        if let Some(img) = &msg.input_image {
-                            content_list.push(json!({
-                                "type": "image_url",
-                                "image_url": {
-                                    "url": img
-                                }
-                            }));
-                        }
-                        if let Some(aud) = &msg.input_audio {
-                            content_list.push(json!({
-                                "type": "input_audio",
-                                "inputAudio": {
-                                    "data": aud,
-                                    "format": msg.input_audio_format.as_deref().unwrap_or("unknown")
-                                }
-                            }));
-                        }
-                        if let Some(vid) = &msg.input_video {
-                            content_list.push(json!({
-                                "type": "video_url",
-                                "inputVideo": {
-                                    "url": vid
-                                }
-                            }));
-                        }
-
-                        Ok(json!({
-                            "role": msg.role,
-                            "content": content_list
-                        }))
-                    })
-                    .collect::<Result<Vec<Value>, String>>()?;
-
-                println!("OpenRouter formatted messages: {:?}", formatted_messages);
+                let formatted_messages = codec::encode_turns(&messages, Dialect::OpenRouter)?;
 
                 // Build the request body
                 let mut body_map = Map::new();
                 body_map.insert("model".to_string(), json!(model_id));
                 body_map.insert("messages".to_string(), json!(formatted_messages));
 
-                // Handle thinking level
-                if let Some(tl_prop) = thinking_level_property {
-                    if let Some(tl) = &thinking_level {
-                        let tl_val = tl.trim();
-                        let val = if let Ok(b) = tl_val.parse::<bool>() {
-                            Value::Bool(b)
-                        } else if let Ok(i) = tl_val.parse::<i64>() {
-                            Value::Number(i.into())
-                        } else if let Ok(f) = tl_val.parse::<f64>() {
-                            if let Some(n) = serde_json::Number::from_f64(f) {
-                                Value::Number(n)
-                            } else {
-                                Value::String(tl_val.to_string())
-                            }
-                        } else {
-                            Value::String(tl_val.to_string())
-                        };
-                        body_map.insert(tl_prop.to_string(), val);
-                    }
+                if let Some(level) = thinking_level {
+                    level.apply(model, &mut body_map)?;
                 }
-
+                options.params.apply(model, &mut body_map)?;
                 let body = Value::Object(body_map);
 
-                println!("OpenRouter request body: {}", body);
+                debug!(body = %redact(&body), "OpenRouter request body");
 
-                let response = client
-                    .post(url)
+                let api_key = env::var("OPENROUTER_API_KEY")
+                    .map_err(|_| "OPENROUTER_API_KEY not set".to_string())?;
+
+                let mut request = client
+                    .post(&url)
                     .header("Authorization", format!("Bearer {}", api_key))
                     .header("Content-Type", "application/json")
-                    .json(&body)
-                    .send()
+                    .json(&body);
+                if let Some(timeout) = options.timeout.or_else(|| model.get_timeout()) {
+                    request = request.timeout(timeout);
+                }
+
+                let response = http::send_traced("POST", &url, request)
                     .await
                     .map_err(|e| format!("Failed to send OpenRouter request: {}", e))?;
 
-                println!("--- OpenRouter response received ---");
-
                 let status = response.status();
                 if !status.is_success() {
                     let error_text = response
@@ -532,8 +1075,389 @@ impl Polytheus {
                         .unwrap_or_else(|_| "OpenRouter: unknown response shape".to_string());
                 }
 
-                println!("--- OpenRouter aggregated result: {} ---", result_text);
-                return Ok(result_text);
+                let usage = Usage::from_openrouter(&resp_json).priced(model.get_price());
+                let (text, emulated_stop) = emulate_stop(model, &options.params, result_text);
+                // OpenRouter doesn't report the sequence it stopped on, but some routes
+                // pass through the `stop_reason` of their engine (vLLM...)
+                let stop_sequence = emulated_stop.or_else(|| {
+                    let reported = resp_json["choices"][0]["stop_reason"].as_str()?;
+                    options
+                        .params
+                        .stop
+                        .as_ref()?
+                        .iter()
+                        .find(|sequence| *sequence == reported)
+                        .cloned()
+                });
+                Ok(Completion {
+                    text,
+                    stop_sequence,
+                    usage,
+                    logprobs: logprobs::parse_choice(&resp_json["choices"][0]),
+                    finish_reason: FinishReason::from_openrouter(&resp_json["choices"][0]),
+                    refusal: resp_json["choices"][0]["message"]["refusal"]
+                        .as_str()
+                        .map(str::to_string),
+                    reasoning: reasoning::from_openrouter(&resp_json["choices"][0]["message"]),
+                    routed_to,
+                    ..Completion::default()
+                })
+            }
+        }
+    }
+
+    /// `messages` with the attachments `model` can't take turned into ones it can:
+    /// documents into their text (or, for scans, page images) and, for vision models,
+    /// GIF videos into keyframes. Extra images follow their message in messages of the
+    /// same role.
+    ///
+    /// Other videos (MP4, WebM...) can't be decoded here and are left for
+    /// `route_videos`.
+    async fn adapt_attachments(
+        &self,
+        model: &Model,
+        messages: Vec<Message>,
+    ) -> Result<Vec<Message>, String> {
+        let reads_files = model.has_input_modality("file");
+        let sees_images = model.has_input_modality("image");
+        let samples_videos = sees_images && !model.has_input_modality("video");
+        let mut adapted = Vec::with_capacity(messages.len());
+        for mut message in messages {
+            let mut images = vec![];
+            if let Some(file) = message.input_file.take_if(|_| !reads_files) {
+                let bytes = media::load(&self.http_config, &file)
+                    .await
+                    .map_err(|e| format!("Invalid file: {}", e))?;
+                // PDF parsing is CPU-bound
+                let document = tokio::task::spawn_blocking(move || document::extract(&bytes))
+                    .await
+                    .map_err(|e| format!("File processing failed: {}", e))?
+                    .map_err(|e| format!("Invalid file: {}", e))?;
+                if document.text.is_empty() && (document.page_images.is_empty() || !sees_images) {
+                    return Err(format!(
+                        "Invalid file: '{}' has no text that model '{}' can read",
+                        document::file_name(&file),
+                        model.get_name()
+                    ));
+                }
+                if !document.text.is_empty() {
+                    message.input_text = format!(
+                        "{}\n\n<document name=\"{}\">\n{}\n</document>",
+                        message.input_text,
+                        document::file_name(&file),
+                        document.text
+                    );
+                }
+                images.extend(document.page_images);
+            }
+            if let Some(url) = message.input_video.take_if(|_| samples_videos) {
+                let bytes = media::load(&self.http_config, &url)
+                    .await
+                    .map_err(|e| format!("Invalid video: {}", e))?;
+                if !video::can_sample(&bytes) {
+                    message.input_video = Some(url);
+                    adapted.push(message);
+                    continue;
+                }
+                let frames =
+                    tokio::task::spawn_blocking(move || video::keyframes(&bytes, video::KEYFRAMES))
+                        .await
+                        .map_err(|e| format!("Video processing failed: {}", e))?
+                        .map_err(|e| format!("Invalid video: {}", e))?;
+                images.extend(frames);
+            }
+
+            let mut images = images.into_iter();
+            if message.input_image.is_none() {
+                message.input_image = images.next();
+            }
+            let role = message.role.clone();
+            adapted.push(message);
+            adapted.extend(images.map(|image| Message {
+                role: role.clone(),
+                input_text: String::new(),
+                input_image: Some(image),
+                input_audio: None,
+                input_audio_format: None,
+                input_video: None,
+                input_file: None,
+            }));
+        }
+        Ok(adapted)
+    }
+
+    /// Model to answer `messages` instead of `model` when they hold a video `model`
+    /// doesn't take and that couldn't be sampled into keyframes: the first model of the
+    /// catalog taking every attachment of `messages`.
+    fn route_videos(&self, model: &Model, messages: &[Message]) -> Option<&Model> {
+        let has_video = messages.iter().any(|message| message.input_video.is_some());
+        if !has_video || model.has_input_modality("video") {
+            return None;
+        }
+        self.models_accepting(messages).into_iter().next()
+    }
+
+    /// Reject `messages` when one holds an attachment `model` doesn't take as input,
+    /// naming the models that do.
+    fn check_modalities(&self, model: &Model, messages: &[Message]) -> Result<(), String> {
+        let Some(modality) = messages
+            .iter()
+            .flat_map(Message::attachment_modalities)
+            .find(|modality| !model.has_input_modality(modality))
+        else {
+            return Ok(());
+        };
+        let capable: Vec<&str> = self
+            .models_accepting(messages)
+            .iter()
+            .map(|model| model.get_name())
+            .collect();
+        Err(format!(
+            "Model '{}' doesn't take {} input ({})",
+            model.get_name(),
+            modality,
+            match capable.is_empty() {
+                true => "no model does".to_string(),
+                false => format!("models that do: {}", capable.join(", ")),
+            }
+        ))
+    }
+
+    /// Chat models taking every attachment of `messages`, to route a request the
+    /// requested model can't handle.
+    pub fn models_accepting(&self, messages: &[Message]) -> Vec<&Model> {
+        let modalities: Vec<&str> = messages
+            .iter()
+            .flat_map(Message::attachment_modalities)
+            .collect();
+        self.models
+            .iter()
+            .filter(|model| model.is_chat_model())
+            .filter(|model| modalities.iter().all(|m| model.has_input_modality(m)))
+            .collect()
+    }
+
+    /// `messages` with their images checked against the limits of `model` and, when
+    /// they don't fit, oriented, downscaled and re-encoded, before dispatch.
+    ///
+    /// Image URLs can't be downloaded safely through a proxy (see
+    /// `http::download_public`): with one configured, they are passed to the provider
+    /// unchanged, to fetch them itself.
+    async fn prepare_images(
+        &self,
+        model: &Model,
+        mut messages: Vec<Message>,
+    ) -> Result<Vec<Message>, String> {
+        let limits = media::ImageLimits::of(model);
+        for message in &mut messages {
+            let Some(url) = &message.input_image else {
+                continue;
+            };
+            if self.http_config.proxy.is_some() && !url.starts_with("data:") {
+                debug!(url = %url, "behind a proxy, image URL passed to the provider");
+                continue;
+            }
+            let bytes = media::load(&self.http_config, url)
+                .await
+                .map_err(|e| format!("Invalid image: {}", e))?;
+            let limits = limits.clone();
+            // decoding and resizing are CPU-bound
+            let prepared = tokio::task::spawn_blocking(move || media::prepare(&bytes, &limits))
+                .await
+                .map_err(|e| format!("Image processing failed: {}", e))?
+                .map_err(|e| format!("Invalid image: {}", e))?;
+            message.input_image = Some(prepared);
+        }
+        Ok(messages)
+    }
+
+    /// Input schema of the Replicate text `model`: its catalog descriptor, or else the
+    /// one read from its OpenAPI schema on Replicate, fetched once per process.
+    ///
+    /// Falls back on the `messages` input when the schema can't be fetched, and only
+    /// tries again after `SCHEMA_RETRY_DELAY`.
+    async fn replicate_input_schema(&self, model: &Model) -> InputSchema {
+        if let Some(schema) = model.get_input_schema() {
+            return schema.clone();
+        }
+        if let Some(schema) = self.cached_input_schema(model.get_name()) {
+            return schema;
+        }
+        let (schema, retry_at) = match self.fetch_input_schema(model).await {
+            Ok(schema) => (schema, None),
+            Err(e) => {
+                warn!(model = model.get_name(), error = %e, "Replicate input schema unavailable, sending messages");
+                (
+                    InputSchema::default(),
+                    Some(Instant::now() + SCHEMA_RETRY_DELAY),
+                )
+            }
+        };
+        if let Ok(mut schemas) = self.input_schemas.lock() {
+            schemas.insert(model.get_name().to_string(), (schema.clone(), retry_at));
+        }
+        schema
+    }
+
+    /// Schema fetched for `model_name`, unless it is a failure due for a retry.
+    fn cached_input_schema(&self, model_name: &str) -> Option<InputSchema> {
+        let schemas = self.input_schemas.lock().ok()?;
+        let (schema, retry_at) = schemas.get(model_name)?;
+        match retry_at {
+            Some(retry_at) if Instant::now() >= *retry_at => None,
+            _ => Some(schema.clone()),
+        }
+    }
+
+    /// Input schema of `model` from the OpenAPI schema of its latest Replicate version.
+    async fn fetch_input_schema(&self, model: &Model) -> Result<InputSchema, String> {
+        let api_token = env::var("REPLICATE_API_TOKEN")
+            .map_err(|_| "REPLICATE_API_TOKEN not set".to_string())?;
+        let predictions_url = self
+            .http_config
+            .rebase(&Provider::Replicate, model.get_apiurl());
+        let url = predictions_url
+            .strip_suffix("/predictions")
+            .ok_or_else(|| format!("Unexpected Replicate model URL '{}'", predictions_url))?;
+        let request = self
+            .client
+            .get(url)
+            .header("Authorization", format!("Bearer {}", api_token));
+        let response = http::send_traced("GET", url, request)
+            .await
+            .map_err(|e| format!("Failed to fetch the Replicate model: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "Replicate model fetch failed with status: {}",
+                response.status()
+            ));
+        }
+        let model_json: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse the Replicate model JSON: {}", e))?;
+        InputSchema::from_openapi(
+            &model_json["latest_version"]["openapi_schema"]["components"]["schemas"]["Input"]
+                ["properties"],
+            model.get_image_parameters(),
+        )
+    }
+
+    /// Create a Replicate prediction of `model` with `body` as input and wait for it to
+    /// complete, through the webhook when configured or by polling otherwise.
+    ///
+    /// The prediction is cancelled when it times out or when the returned future is dropped.
+    async fn replicate_prediction(
+        &self,
+        model: &Model,
+        mut body: Value,
+        options: &RunOptions,
+    ) -> Result<Value, String> {
+        let client = &self.client;
+        let api_token = env::var("REPLICATE_API_TOKEN")
+            .map_err(|_| "REPLICATE_API_TOKEN not set".to_string())?;
+
+        let url = self
+            .http_config
+            .rebase(&Provider::Replicate, model.get_apiurl());
+
+        debug!(url = %url, "Replicate prediction URL");
+
+        if let Some(webhook_url) = &self.replicate_webhook_url {
+            body["webhook"] = json!(webhook_url);
+            body["webhook_events_filter"] = json!(["completed"]);
+        }
+
+        debug!(body = %redact(&body), "Replicate request body");
+
+        // 4. POST Request to get the stream_url
+        let request = client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", api_token))
+            .json(&body);
+        let response = http::send_traced("POST", &url, request)
+            .await
+            .map_err(|e| format!("Failed to send request: {}", e))?;
+
+        // Capture status before consuming the response body (text/json consume the Response)
+        let status = response.status();
+        if status != StatusCode::OK && status != StatusCode::CREATED {
+            let error_text: String = response
+                .text()
+                .await
+                .map_err(|e| format!("error reading error body: {}", e))?;
+            return Err(format!(
+                "First API call failed with status: {} and body: {}",
+                status, error_text
+            ));
+        }
+
+        // 5. Deserialize the response to get the stream URL
+        let created: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse prediction response: {}", e))?;
+        let prediction: PredictionResponse = serde_json::from_value(created.clone())
+            .map_err(|e| format!("Failed to parse prediction response: {}", e))?;
+
+        // If Replicate already returned an output (rare for async predictions), return it.
+        if prediction.output.is_some() {
+            return Ok(created);
+        }
+
+        let id = prediction.id.as_deref();
+        let urls = prediction.urls.as_ref();
+        let base_url = self.http_config.base_url(&Provider::Replicate);
+        let get_url = urls
+            .and_then(|u| u.get.as_deref())
+            .map(|url| self.http_config.rebase(&Provider::Replicate, url))
+            .or_else(|| id.map(|id| replicate::default_get_url(base_url, id)))
+            .ok_or_else(|| "Replicate prediction response missing urls.get and id".to_string())?;
+        let cancel_url = urls
+            .and_then(|u| u.cancel.as_deref())
+            .map(|url| self.http_config.rebase(&Provider::Replicate, url))
+            .or_else(|| id.map(|id| replicate::default_cancel_url(base_url, id)))
+            .ok_or_else(|| {
+                "Replicate prediction response missing urls.cancel and id".to_string()
+            })?;
+
+        let timeout = options
+            .timeout
+            .or_else(|| model.get_timeout())
+            .unwrap_or(replicate::DEFAULT_TIMEOUT);
+        let max_backoff = options
+            .max_poll_backoff
+            .or_else(|| model.get_max_poll_backoff())
+            .unwrap_or(replicate::DEFAULT_MAX_POLL_BACKOFF);
+
+        // Cancels the prediction if this future is dropped (e.g. the client disconnected).
+        let guard = CancelGuard::new(client.clone(), cancel_url, api_token.clone());
+
+        let outcome = match (&self.replicate_webhook_url, id) {
+            (Some(_), Some(id)) => replicate::wait_for_webhook(id, timeout).await,
+            _ => {
+                replicate::poll_prediction(client, &get_url, &api_token, timeout, max_backoff).await
+            }
+        };
+
+        match outcome {
+            Ok(prediction) => {
+                guard.disarm();
+                Ok(prediction)
+            }
+            Err(e @ WaitError::Ended(_)) => {
+                guard.disarm();
+                Err(e.to_string())
+            }
+            // timed out, or lost track of it: don't leave it running and billing
+            Err(e) => {
+                let cancel_result = guard.cancel().await;
+                let mut error = e.to_string();
+                if let Err(e) = cancel_result {
+                    warn!(error = %e, "failed to cancel unfinished prediction");
+                    error.push_str(&format!(" ({})", e));
+                }
+                Err(error)
             }
         }
     }
@@ -553,9 +1477,108 @@ impl Polytheus {
     }
 }
 
+/// Poll `futures` concurrently until they all complete, returning their outputs in order.
+async fn join_all<F: Future>(futures: Vec<F>) -> Vec<F::Output> {
+    let mut futures: Vec<Pin<Box<F>>> = futures.into_iter().map(Box::pin).collect();
+    let mut outputs: Vec<Option<F::Output>> = futures.iter().map(|_| None).collect();
+    poll_fn(|cx| {
+        let mut pending = false;
+        for (future, output) in futures.iter_mut().zip(outputs.iter_mut()) {
+            if output.is_none() {
+                match future.as_mut().poll(cx) {
+                    Poll::Ready(value) => *output = Some(value),
+                    Poll::Pending => pending = true,
+                }
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    })
+    .await;
+    outputs.into_iter().flatten().collect()
+}
+
+/// `text` cut at the stop sequences of `params` when `model` can't stop on them itself,
+/// with the sequence it was cut at.
+fn emulate_stop(
+    model: &Model,
+    params: &GenerationParams,
+    text: String,
+) -> (String, Option<String>) {
+    match &params.stop {
+        Some(stop) if model.get_generation_parameter("stop").is_none() => {
+            let (cut, sequence) = cut_at_stop(&text, stop);
+            (cut.to_string(), sequence.map(str::to_string))
+        }
+        _ => (text, None),
+    }
+}
+
+/// Options of each of `n` choices: without the caches when there are several, and
+/// with the `seed`, if any, offset by the index of the choice, so that seeded choices
+/// stay reproducible without being identical.
+fn choice_options(options: &RunOptions, n: u32) -> Vec<RunOptions> {
+    (0..n)
+        .map(|index| {
+            let mut choice = RunOptions {
+                no_cache: options.no_cache || n > 1,
+                ..options.clone()
+            };
+            choice.params.seed = options
+                .params
+                .seed
+                .map(|seed| seed.wrapping_add(index.into()));
+            choice
+        })
+        .collect()
+}
+
+/// Close the span of a call other than `run` (`embed`, `generate_images`...): record
+/// its latency, tokens, cost and status, export its metrics and log the outcome.
+///
+/// Every outcome goes through here, validation errors and unknown models (`model`
+/// being `None`) included.
+fn finish_call(
+    span: &Span,
+    model_name: &str,
+    model: Option<&Model>,
+    start: Instant,
+    outcome: Result<&Usage, &String>,
+    what: &str,
+) {
+    let _entered = span.enter();
+    let latency_ms = start.elapsed().as_millis() as u64;
+    let provider = model
+        .map(|model| format!("{:?}", model.get_provider()))
+        .unwrap_or_else(|| "unknown".to_string());
+    span.record("provider", provider.as_str());
+    span.record("latency_ms", latency_ms);
+    otel::record_run(&provider, model_name, latency_ms, outcome.ok());
+    match outcome {
+        Ok(usage) => {
+            span.record("status", "ok");
+            if let Some(tokens) = usage.prompt_tokens {
+                span.record("prompt_tokens", tokens);
+            }
+            if let Some(cost) = usage.cost {
+                span.record("cost_usd", cost);
+            }
+            info!(latency_ms, cost_usd = usage.cost, "{} succeeded", what);
+        }
+        Err(e) => {
+            span.record("status", "error");
+            warn!(latency_ms, error = %e, "{} failed", what);
+        }
+    }
+}
+
 #[cfg(test)]
 mod replicate_non_stream_tests {
     use super::*;
+    use base64::prelude::*;
     use std::time::Instant;
 
     #[test]
@@ -569,9 +1592,15 @@ mod replicate_non_stream_tests {
             input_audio: None,
             input_audio_format: None,
             input_video: None,
+            input_file: None,
         }];
 
-        let body = build_replicate_request_body(&messages, None, None, false).unwrap();
+        let model = Model::fill()
+            .into_iter()
+            .find(|model| model.get_name() == "gpt-4o")
+            .unwrap();
+        let body = build_replicate_request_body(&model, &InputSchema::default(), &messages, false)
+            .unwrap();
         assert_eq!(body.get("stream").and_then(|v| v.as_bool()), Some(false));
 
         let duration = Instant::now() - start;
@@ -581,6 +1610,34 @@ mod replicate_non_stream_tests {
         );
     }
 
+    #[test]
+    fn test_failed_schema_fetches_are_retried_later() {
+        let start = Instant::now();
+
+        let polytheus = Polytheus::fast_fill();
+        let retry_at = tokio::time::Instant::now() + SCHEMA_RETRY_DELAY;
+        polytheus.input_schemas.lock().unwrap().insert(
+            "failed".to_string(),
+            (InputSchema::default(), Some(retry_at)),
+        );
+        polytheus.input_schemas.lock().unwrap().insert(
+            "due".to_string(),
+            (InputSchema::default(), Some(tokio::time::Instant::now())),
+        );
+        assert_eq!(
+            polytheus.cached_input_schema("failed"),
+            Some(InputSchema::default())
+        );
+        assert_eq!(polytheus.cached_input_schema("due"), None);
+        assert_eq!(polytheus.cached_input_schema("unknown"), None);
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_failed_schema_fetches_are_retried_later took: {:?}",
+            duration
+        );
+    }
+
     #[test]
     fn test_extract_replicate_output_text_string_and_array() {
         let start = Instant::now();
@@ -600,4 +1657,250 @@ mod replicate_non_stream_tests {
             duration
         );
     }
+
+    #[tokio::test]
+    async fn test_cache_hit_is_free_and_skips_the_provider() {
+        let start = Instant::now();
+
+        let messages = vec![Message {
+            role: "user".to_string(),
+            input_text: "hello".to_string(),
+            input_image: None,
+            input_audio: None,
+            input_audio_format: None,
+            input_video: None,
+            input_file: None,
+        }];
+        let cache = ResponseCache::new(
+            Box::new(LruStore::new(8, 1024 * 1024)),
+            Duration::from_secs(60),
+        );
+        cache.put(
+            cache::cache_key("gpt-4o", &messages, None, &Value::Null),
+            Completion {
+                text: "cached answer".to_string(),
+                usage: Usage {
+                    prompt_tokens: Some(5),
+                    completion_tokens: Some(2),
+                    cost: Some(0.01),
+                    ..Usage::default()
+                },
+                ..Completion::default()
+            },
+        );
+        let polytheus = Polytheus::fast_fill().with_cache(cache);
+
+        let completion = polytheus.run("gpt-4o", messages, None).await.unwrap();
+        assert!(completion.cached);
+        assert_eq!(completion.text, "cached answer");
+        assert_eq!(completion.usage.prompt_tokens, Some(5));
+        assert_eq!(completion.usage.cost, Some(0.0));
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_cache_hit_is_free_and_skips_the_provider took: {:?}",
+            duration
+        );
+    }
+
+    #[tokio::test]
+    async fn test_attachments_are_gated_by_input_modality() {
+        let start = Instant::now();
+
+        let messages = vec![Message {
+            role: "user".to_string(),
+            input_text: "What is said in this recording?".to_string(),
+            input_image: None,
+            input_audio: Some("https://example.com/memo.mp3".to_string()),
+            input_audio_format: None,
+            input_video: None,
+            input_file: None,
+        }];
+        let polytheus = Polytheus::fast_fill();
+
+        let error = polytheus
+            .run("gpt-4o", messages.clone(), None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "Model 'gpt-4o' doesn't take audio input (models that do: gemini-3-pro)"
+        );
+        let capable: Vec<&str> = polytheus
+            .models_accepting(&messages)
+            .iter()
+            .map(|model| model.get_name())
+            .collect();
+        assert_eq!(capable, vec!["gemini-3-pro"]);
+        let error = polytheus
+            .run("gpt-4o-transcribe", messages, None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "Model 'gpt-4o-transcribe' isn't a chat model, use transcribe or speak instead"
+        );
+        assert!(!polytheus.text_models().contains(&"gpt-4o-transcribe"));
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_attachments_are_gated_by_input_modality took: {:?}",
+            duration
+        );
+    }
+
+    #[tokio::test]
+    async fn test_documents_and_videos_are_adapted() {
+        let start = Instant::now();
+
+        let mut gif = vec![];
+        {
+            let mut encoder = ::image::codecs::gif::GifEncoder::new(&mut gif);
+            for _ in 0..12 {
+                let frame = ::image::RgbaImage::from_pixel(8, 8, ::image::Rgba([0, 0, 0, 255]));
+                encoder.encode_frame(::image::Frame::new(frame)).unwrap();
+            }
+        }
+        let messages = vec![Message {
+            role: "user".to_string(),
+            input_text: "Summarize these.".to_string(),
+            input_image: None,
+            input_audio: None,
+            input_audio_format: None,
+            input_video: Some(format!(
+                "data:image/gif;base64,{}",
+                BASE64_STANDARD.encode(&gif)
+            )),
+            input_file: Some("data:text/plain;base64,UmV2ZW51ZSBncmV3Lg==".to_string()),
+        }];
+        let polytheus = Polytheus::fast_fill();
+        let gpt = polytheus.get_model_by_name("gpt-4o").unwrap();
+
+        let adapted = polytheus
+            .adapt_attachments(gpt, messages.clone())
+            .await
+            .unwrap();
+        assert_eq!(adapted.len(), video::KEYFRAMES);
+        assert_eq!(
+            adapted[0].input_text,
+            "Summarize these.\n\n<document name=\"document.txt\">\nRevenue grew.\n</document>"
+        );
+        assert!(adapted.iter().all(|message| message.role == "user"
+            && message.input_image.is_some()
+            && message.input_video.is_none()
+            && message.input_file.is_none()));
+
+        let gemini = polytheus.get_model_by_name("gemini-3-pro").unwrap();
+        let native = polytheus
+            .adapt_attachments(gemini, messages.clone())
+            .await
+            .unwrap();
+        assert_eq!(native, messages);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_documents_and_videos_are_adapted took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_image_urls_are_passed_through_behind_a_proxy() {
+        let start = Instant::now();
+
+        let messages = vec![Message {
+            role: "user".to_string(),
+            input_text: "What is in this picture?".to_string(),
+            input_image: Some("https://example.com/cat.jpg".to_string()),
+            input_audio: None,
+            input_audio_format: None,
+            input_video: None,
+            input_file: None,
+        }];
+        let polytheus = Polytheus::fast_fill()
+            .with_http_config(HttpConfig {
+                proxy: Some("http://proxy:3128".to_string()),
+                ..HttpConfig::default()
+            })
+            .unwrap();
+        let gpt = polytheus.get_model_by_name("gpt-4o").unwrap();
+
+        let prepared = polytheus
+            .prepare_images(gpt, messages.clone())
+            .await
+            .unwrap();
+        assert_eq!(prepared, messages);
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_image_urls_are_passed_through_behind_a_proxy took: {:?}",
+            duration
+        );
+    }
+
+    #[test]
+    fn test_seeded_choices_get_distinct_seeds() {
+        let start = Instant::now();
+
+        let options = RunOptions {
+            params: GenerationParams {
+                seed: Some(42),
+                ..GenerationParams::default()
+            },
+            ..RunOptions::default()
+        };
+        let choices = choice_options(&options, 3);
+        let seeds: Vec<Option<i64>> = choices.iter().map(|choice| choice.params.seed).collect();
+        assert_eq!(seeds, vec![Some(42), Some(43), Some(44)]);
+        assert!(choices.iter().all(|choice| choice.no_cache));
+
+        let single = choice_options(&options, 1);
+        assert_eq!(single[0].params.seed, Some(42));
+        assert!(!single[0].no_cache);
+        let unseeded = choice_options(&RunOptions::default(), 2);
+        assert!(unseeded.iter().all(|choice| choice.params.seed.is_none()));
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_seeded_choices_get_distinct_seeds took: {:?}",
+            duration
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsampled_videos_are_routed_to_a_video_model() {
+        let start = Instant::now();
+
+        // the start of an MP4 file: an `ftyp` box
+        let mp4 = b"\0\0\0\x18ftypmp42\0\0\0\0mp42isom";
+        let messages = vec![Message {
+            role: "user".to_string(),
+            input_text: "What happens in this clip?".to_string(),
+            input_image: None,
+            input_audio: None,
+            input_audio_format: None,
+            input_video: Some(format!(
+                "data:video/mp4;base64,{}",
+                BASE64_STANDARD.encode(mp4)
+            )),
+            input_file: None,
+        }];
+        let polytheus = Polytheus::fast_fill();
+        let gpt = polytheus.get_model_by_name("gpt-4o").unwrap();
+
+        let adapted = polytheus
+            .adapt_attachments(gpt, messages.clone())
+            .await
+            .unwrap();
+        assert_eq!(adapted, messages);
+        let routed = polytheus.route_videos(gpt, &adapted).unwrap();
+        assert_eq!(routed.get_name(), "gemini-3-pro");
+
+        let gemini = polytheus.get_model_by_name("gemini-3-pro").unwrap();
+        assert!(polytheus.route_videos(gemini, &adapted).is_none());
+        assert!(polytheus.route_videos(gpt, &[]).is_none());
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_unsampled_videos_are_routed_to_a_video_model took: {:?}",
+            duration
+        );
+    }
 }
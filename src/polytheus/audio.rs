@@ -0,0 +1,364 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use super::Usage;
+
+/// Voices of the OpenAI speech API: they have no equivalent on other models, which
+/// then use their default voice.
+const OPENAI_VOICES: [&str; 11] = [
+    "alloy", "ash", "ballad", "coral", "echo", "fable", "nova", "onyx", "sage", "shimmer", "verse",
+];
+
+/// Formats of the OpenAI speech API speech models can produce: "pcm" (raw 16-bit
+/// little-endian samples at 24 kHz) is cut out of a WAV file. Opus and AAC can't be
+/// produced by the models of the catalog, nor converted to without an encoder.
+pub const SPEECH_FORMATS: [&str; 4] = ["mp3", "wav", "flac", "pcm"];
+
+/// Sample rate of the "pcm" speech format, as the OpenAI speech API.
+const PCM_SAMPLE_RATE: u32 = 24000;
+
+/// An audio file sent by a client.
+#[derive(Debug, Clone)]
+pub struct AudioFile {
+    /// Raw content of the file.
+    pub data: Vec<u8>,
+
+    /// Name of the file, used to guess its type when `content_type` is generic.
+    pub filename: String,
+
+    /// Content type of the file (e.g. "audio/mpeg").
+    pub content_type: String,
+}
+
+impl AudioFile {
+    /// File named `filename`, typed from `content_type` or else from its extension.
+    pub fn new(data: Vec<u8>, filename: &str, content_type: Option<&str>) -> AudioFile {
+        let content_type = content_type
+            .filter(|t| !t.is_empty() && *t != "application/octet-stream")
+            .map(|t| t.to_string())
+            .unwrap_or_else(|| content_type_of(filename).to_string());
+        AudioFile {
+            data,
+            filename: filename.to_string(),
+            content_type,
+        }
+    }
+}
+
+/// Request of a transcription.
+#[derive(Debug, Clone)]
+pub struct TranscriptionRequest {
+    /// The recording to transcribe.
+    pub file: AudioFile,
+
+    /// Language of the recording (ISO-639-1), improves accuracy and latency.
+    pub language: Option<String>,
+
+    /// Text guiding the style or vocabulary of the transcription.
+    pub prompt: Option<String>,
+}
+
+/// Request of a speech synthesis.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SpeechRequest {
+    /// Text to read.
+    pub input: String,
+
+    /// Voice of the model; OpenAI voice names fall back on the model's default voice.
+    pub voice: Option<String>,
+
+    /// Speed multiplier (1.0 is normal speed).
+    pub speed: Option<f64>,
+
+    /// Format of the audio, one of `SPEECH_FORMATS`; the model's default, MP3, when
+    /// missing.
+    pub response_format: Option<String>,
+}
+
+impl SpeechRequest {
+    /// Format of the audio asked for.
+    pub fn format(&self) -> &str {
+        self.response_format.as_deref().unwrap_or("mp3")
+    }
+}
+
+/// Audio generated by a speech model.
+#[derive(Debug, Clone)]
+pub struct Speech {
+    /// Raw audio content.
+    pub audio: Vec<u8>,
+
+    /// Content type of `audio` (e.g. "audio/mpeg").
+    pub content_type: String,
+
+    /// Tokens and cost of the synthesis.
+    pub usage: Usage,
+}
+
+/// Replicate input of a transcription, the recording being available at `file_url`.
+pub fn build_transcription_input(
+    audio_parameter: &str,
+    file_url: &str,
+    request: &TranscriptionRequest,
+) -> Value {
+    let mut input = Map::new();
+    input.insert(audio_parameter.to_string(), json!(file_url));
+    if let Some(language) = &request.language {
+        input.insert("language".to_string(), json!(language));
+    }
+    if let Some(prompt) = &request.prompt {
+        input.insert("prompt".to_string(), json!(prompt));
+    }
+    json!({ "input": input })
+}
+
+/// Replicate input of a speech synthesis.
+pub fn build_speech_input(request: &SpeechRequest) -> Result<Value, String> {
+    if request.input.trim().is_empty() {
+        return Err("you are missing the input".to_string());
+    }
+    let mut input = Map::new();
+    input.insert("text".to_string(), json!(request.input));
+    if let Some(voice) = request
+        .voice
+        .as_deref()
+        .filter(|voice| !OPENAI_VOICES.contains(voice))
+    {
+        input.insert("voice_id".to_string(), json!(voice));
+    }
+    if let Some(speed) = request.speed {
+        if !(0.25..=4.0).contains(&speed) {
+            return Err("speed must be between 0.25 and 4.0".to_string());
+        }
+        input.insert("speed".to_string(), json!(speed));
+    }
+    match request.response_format.as_deref() {
+        None => {}
+        Some("pcm") => {
+            input.insert("audio_format".to_string(), json!("wav"));
+            input.insert("sample_rate".to_string(), json!(PCM_SAMPLE_RATE));
+        }
+        Some(format) if SPEECH_FORMATS.contains(&format) => {
+            input.insert("audio_format".to_string(), json!(format));
+        }
+        Some(format) => {
+            return Err(format!(
+                "Unsupported response_format: {} (supported: {})",
+                format,
+                SPEECH_FORMATS.join(", ")
+            ))
+        }
+    }
+    Ok(json!({ "input": input }))
+}
+
+/// Speech `audio` in `format`, with its content type, once its bytes are checked to
+/// really be in that format: a model answering in another one is an error rather than
+/// mislabeled audio.
+pub fn speech_audio(format: &str, audio: Vec<u8>) -> Result<(Vec<u8>, &'static str), String> {
+    let expected = if format == "pcm" { "wav" } else { format };
+    let actual = sniff_format(&audio);
+    if actual != Some(expected) {
+        return Err(format!(
+            "The model returned {} audio instead of {}",
+            actual.unwrap_or("unknown"),
+            expected
+        ));
+    }
+    match format {
+        "pcm" => Ok((wav_samples(&audio)?, "audio/pcm")),
+        "wav" => Ok((audio, "audio/wav")),
+        "flac" => Ok((audio, "audio/flac")),
+        _ => Ok((audio, "audio/mpeg")),
+    }
+}
+
+/// Format of `audio` from its magic bytes: "mp3", "wav", "flac", "ogg" or "aac".
+fn sniff_format(audio: &[u8]) -> Option<&'static str> {
+    match audio {
+        [b'I', b'D', b'3', ..] => Some("mp3"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some("wav"),
+        [b'f', b'L', b'a', b'C', ..] => Some("flac"),
+        [b'O', b'g', b'g', b'S', ..] => Some("ogg"),
+        // frame sync: MPEG audio layer III, or ADTS (layer bits 00) for AAC
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 => match second & 0x06 {
+            0x00 => Some("aac"),
+            0x02 => Some("mp3"),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Raw samples of a WAV file: its `data` chunk, checked to be 16-bit PCM mono at
+/// `PCM_SAMPLE_RATE`.
+fn wav_samples(wav: &[u8]) -> Result<Vec<u8>, String> {
+    let mut at = 12;
+    let mut format = None;
+    while let Some(header) = wav.get(at..at + 8) {
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let body = &wav[at + 8..wav.len().min(at + 8 + size)];
+        match &header[..4] {
+            b"fmt " if body.len() >= 16 => {
+                let (tag, channels) = (
+                    u16::from_le_bytes([body[0], body[1]]),
+                    u16::from_le_bytes([body[2], body[3]]),
+                );
+                let rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+                let bits = u16::from_le_bytes([body[14], body[15]]);
+                format = Some((tag, channels, rate, bits));
+            }
+            b"data" => {
+                return match format {
+                    Some((1, 1, PCM_SAMPLE_RATE, 16)) => Ok(body.to_vec()),
+                    Some((tag, channels, rate, bits)) => Err(format!(
+                        "The model returned {}-bit, {} channel(s), {} Hz WAV audio (format {}) instead of 16-bit mono {} Hz PCM",
+                        bits, channels, rate, tag, PCM_SAMPLE_RATE
+                    )),
+                    None => Err("Invalid WAV audio: data before format".to_string()),
+                };
+            }
+            _ => {}
+        }
+        // chunks are padded to an even size
+        at += 8 + size + size % 2;
+    }
+    Err("Invalid WAV audio: no data".to_string())
+}
+
+/// Audio URL of a Replicate output: a URL, or the first URL of an array.
+pub fn output_url(output: &Value) -> Option<&str> {
+    output
+        .as_str()
+        .or_else(|| output.as_array()?.iter().find_map(Value::as_str))
+}
+
+/// Content type of an audio file, from the extension of its name or URL.
+pub fn content_type_of(name: &str) -> &'static str {
+    let extension = name
+        .rsplit('/')
+        .next()
+        .and_then(|file| file.split('?').next())
+        .and_then(|file| file.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("mp3" | "mpga" | "mpeg") => "audio/mpeg",
+        Some("wav") => "audio/wav",
+        Some("flac") => "audio/flac",
+        Some("ogg" | "oga") => "audio/ogg",
+        Some("opus") => "audio/opus",
+        Some("m4a" | "mp4") => "audio/mp4",
+        Some("webm") => "audio/webm",
+        Some("aac") => "audio/aac",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod audio_tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_content_type_of() {
+        let start = Instant::now();
+
+        assert_eq!(content_type_of("meeting.MP3"), "audio/mpeg");
+        assert_eq!(
+            content_type_of("https://replicate.delivery/x/output.wav?sig=1"),
+            "audio/wav"
+        );
+        assert_eq!(content_type_of("notes"), "application/octet-stream");
+        assert_eq!(
+            AudioFile::new(vec![], "a.m4a", Some("application/octet-stream")).content_type,
+            "audio/mp4"
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_content_type_of took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_build_speech_input() {
+        let start = Instant::now();
+
+        let openai_voice = SpeechRequest {
+            input: "Hello".to_string(),
+            voice: Some("alloy".to_string()),
+            speed: Some(1.5),
+            response_format: None,
+        };
+        assert_eq!(
+            build_speech_input(&openai_voice).unwrap(),
+            json!({ "input": { "text": "Hello", "speed": 1.5 } })
+        );
+
+        let model_voice = SpeechRequest {
+            voice: Some("Wise_Woman".to_string()),
+            speed: None,
+            ..openai_voice.clone()
+        };
+        assert_eq!(
+            build_speech_input(&model_voice).unwrap()["input"]["voice_id"],
+            json!("Wise_Woman")
+        );
+
+        let too_fast = SpeechRequest {
+            speed: Some(10.0),
+            ..openai_voice.clone()
+        };
+        assert!(build_speech_input(&too_fast).is_err());
+
+        let pcm = SpeechRequest {
+            response_format: Some("pcm".to_string()),
+            ..openai_voice.clone()
+        };
+        let input = build_speech_input(&pcm).unwrap();
+        assert_eq!(input["input"]["audio_format"], json!("wav"));
+        assert_eq!(input["input"]["sample_rate"], json!(24000));
+        let opus = SpeechRequest {
+            response_format: Some("opus".to_string()),
+            ..openai_voice
+        };
+        assert_eq!(
+            build_speech_input(&opus),
+            Err("Unsupported response_format: opus (supported: mp3, wav, flac, pcm)".to_string())
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_build_speech_input took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_speech_audio_is_checked() {
+        let start = Instant::now();
+
+        let mp3 = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+        assert_eq!(
+            speech_audio("mp3", mp3.clone()),
+            Ok((mp3.clone(), "audio/mpeg"))
+        );
+        assert_eq!(
+            speech_audio("flac", mp3),
+            Err("The model returned mp3 audio instead of flac".to_string())
+        );
+
+        // 16-bit mono 24 kHz WAV holding two samples
+        let mut wav = b"RIFF\x2C\x00\x00\x00WAVEfmt \x10\x00\x00\x00".to_vec();
+        wav.extend([1, 0, 1, 0]);
+        wav.extend(24000u32.to_le_bytes());
+        wav.extend(48000u32.to_le_bytes());
+        wav.extend([2, 0, 16, 0]);
+        wav.extend(b"data\x04\x00\x00\x00\x01\x02\x03\x04");
+        assert_eq!(
+            speech_audio("pcm", wav.clone()),
+            Ok((vec![1, 2, 3, 4], "audio/pcm"))
+        );
+        assert_eq!(speech_audio("wav", wav.clone()).unwrap().1, "audio/wav");
+        wav[24..28].copy_from_slice(&44100u32.to_le_bytes());
+        assert!(speech_audio("pcm", wav).unwrap_err().contains("44100 Hz"));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_speech_audio_is_checked took: {:?}", duration);
+    }
+}
@@ -0,0 +1,556 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{Completion, Message};
+
+/// Time to live of an entry when `POLYTHEUS_CACHE_TTL_SECS` is not set.
+const DEFAULT_TTL: Duration = Duration::from_secs(3600);
+
+/// Entries kept in memory when `POLYTHEUS_CACHE_CAPACITY` is not set.
+const DEFAULT_CAPACITY: usize = 1000;
+
+/// Bytes kept in memory when `POLYTHEUS_CACHE_MAX_BYTES` is not set.
+const DEFAULT_MEMORY_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Bytes kept on disk when `POLYTHEUS_CACHE_MAX_BYTES` is not set (Lambda's `/tmp`
+/// holds 512 MB by default).
+const DEFAULT_DISK_MAX_BYTES: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+/// A cached completion and the key it was stored under.
+pub struct CacheEntry {
+    /// Full key, checked on read so hash collisions can't return another prompt's answer.
+    pub key: String,
+
+    /// The completion as returned by the provider.
+    pub completion: Completion,
+
+    /// Unix time (seconds) the entry was stored at.
+    pub created_at: u64,
+}
+
+impl CacheEntry {
+    /// Approximate bytes held by the entry.
+    fn size(&self) -> usize {
+        self.key.len()
+            + serde_json::to_vec(&self.completion).map_or(self.completion.text.len(), |v| v.len())
+    }
+}
+
+/// Storage of cache entries.
+pub trait CacheStore: Send + Sync + Debug {
+    /// Entry stored under `key`, if any.
+    fn get(&self, key: &str) -> Option<CacheEntry>;
+
+    /// Store `entry` under its key.
+    fn put(&self, entry: CacheEntry);
+
+    /// Drop the entry stored under `key`, if any.
+    fn remove(&self, key: &str);
+}
+
+/// In-memory store evicting the least recently used entries past `capacity` entries or
+/// `max_bytes` bytes.
+#[derive(Debug)]
+pub struct LruStore {
+    capacity: usize,
+    max_bytes: usize,
+    state: Mutex<LruState>,
+}
+
+#[derive(Debug, Default)]
+struct LruState {
+    /// Incremented on every access, orders the entries by recency.
+    tick: u64,
+
+    /// Entries by key hash, with the tick they were last used at.
+    entries: HashMap<String, (CacheEntry, u64)>,
+
+    /// Sum of the sizes of `entries`.
+    bytes: usize,
+}
+
+impl LruStore {
+    /// Store keeping at most `capacity` entries, holding at most `max_bytes` bytes.
+    pub fn new(capacity: usize, max_bytes: usize) -> LruStore {
+        LruStore {
+            capacity: capacity.max(1),
+            max_bytes,
+            state: Mutex::new(LruState::default()),
+        }
+    }
+}
+
+impl CacheStore for LruStore {
+    fn get(&self, key: &str) -> Option<CacheEntry> {
+        let mut state = self.state.lock().ok()?;
+        state.tick += 1;
+        let tick = state.tick;
+        let (entry, last_used) = state
+            .entries
+            .get_mut(&hash_key(key))
+            .filter(|(entry, _)| entry.key == key)?;
+        *last_used = tick;
+        Some(entry.clone())
+    }
+
+    fn put(&self, entry: CacheEntry) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let size = entry.size();
+        if size > self.max_bytes {
+            return;
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        let state = &mut *state;
+        let hash = hash_key(&entry.key);
+        if let Some((replaced, _)) = state.entries.remove(&hash) {
+            state.bytes -= replaced.size();
+        }
+        while state.entries.len() >= self.capacity || state.bytes + size > self.max_bytes {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(hash, _)| hash.clone());
+            let Some((evicted, _)) = oldest.and_then(|oldest| state.entries.remove(&oldest)) else {
+                break;
+            };
+            state.bytes -= evicted.size();
+        }
+        state.bytes += size;
+        state.entries.insert(hash, (entry, tick));
+    }
+
+    fn remove(&self, key: &str) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if let Some((removed, _)) = state.entries.remove(&hash_key(key)) {
+            state.bytes -= removed.size();
+        }
+    }
+}
+
+/// Store writing one JSON file per entry in a directory, deleting the oldest files
+/// once they hold more than `max_bytes` bytes.
+#[derive(Debug)]
+pub struct DiskStore {
+    dir: PathBuf,
+    max_bytes: usize,
+}
+
+impl DiskStore {
+    /// Store writing at most `max_bytes` bytes in `dir`, created if needed.
+    pub fn new(dir: impl Into<PathBuf>, max_bytes: usize) -> Result<DiskStore, String> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create cache directory {:?}: {}", dir, e))?;
+        Ok(DiskStore { dir, max_bytes })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", hash_key(key)))
+    }
+
+    /// Delete the least recently written entries until at most `max_bytes` bytes remain.
+    fn evict(&self) {
+        let Ok(files) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut files: Vec<(PathBuf, u64, SystemTime)> = files
+            .filter_map(Result::ok)
+            .filter(|file| file.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|file| {
+                let metadata = file.metadata().ok()?;
+                Some((file.path(), metadata.len(), metadata.modified().ok()?))
+            })
+            .collect();
+        let mut bytes: u64 = files.iter().map(|(_, len, _)| len).sum();
+        files.sort_by_key(|(_, _, modified)| *modified);
+        for (path, len, _) in files {
+            if bytes <= self.max_bytes as u64 {
+                break;
+            }
+            if std::fs::remove_file(path).is_ok() {
+                bytes -= len;
+            }
+        }
+    }
+}
+
+impl CacheStore for DiskStore {
+    fn get(&self, key: &str) -> Option<CacheEntry> {
+        let bytes = std::fs::read(self.path(key)).ok()?;
+        serde_json::from_slice::<CacheEntry>(&bytes)
+            .ok()
+            .filter(|entry| entry.key == key)
+    }
+
+    fn put(&self, entry: CacheEntry) {
+        let path = self.path(&entry.key);
+        if let Ok(bytes) = serde_json::to_vec(&entry) {
+            // write then rename so concurrent readers never see a partial file
+            let tmp = path.with_extension("tmp");
+            if std::fs::write(&tmp, bytes).is_ok() {
+                let _ = std::fs::rename(tmp, path);
+            }
+        }
+        self.evict();
+    }
+
+    fn remove(&self, key: &str) {
+        let _ = std::fs::remove_file(self.path(key));
+    }
+}
+
+/// Exact-match cache of model completions.
+#[derive(Debug)]
+pub struct ResponseCache {
+    store: Box<dyn CacheStore>,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    /// Cache over `store` whose entries expire after `ttl`.
+    pub fn new(store: Box<dyn CacheStore>, ttl: Duration) -> ResponseCache {
+        ResponseCache { store, ttl }
+    }
+
+    /// Build the cache described by `POLYTHEUS_CACHE` ("memory", "disk" or unset for none),
+    /// `POLYTHEUS_CACHE_TTL_SECS`, `POLYTHEUS_CACHE_CAPACITY`, `POLYTHEUS_CACHE_MAX_BYTES`
+    /// and `POLYTHEUS_CACHE_DIR`.
+    pub fn from_env() -> Option<ResponseCache> {
+        let ttl = env::var("POLYTHEUS_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TTL);
+        let max_bytes = env::var("POLYTHEUS_CACHE_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok());
+        let store: Box<dyn CacheStore> = match env::var("POLYTHEUS_CACHE").ok()?.as_str() {
+            "memory" => Box::new(LruStore::new(
+                env::var("POLYTHEUS_CACHE_CAPACITY")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(DEFAULT_CAPACITY),
+                max_bytes.unwrap_or(DEFAULT_MEMORY_MAX_BYTES),
+            )),
+            "disk" => Box::new(
+                DiskStore::new(
+                    env::var("POLYTHEUS_CACHE_DIR")
+                        .unwrap_or_else(|_| "/tmp/polytheus-cache".to_string()),
+                    max_bytes.unwrap_or(DEFAULT_DISK_MAX_BYTES),
+                )
+                .ok()?,
+            ),
+            _ => return None,
+        };
+        Some(ResponseCache::new(store, ttl))
+    }
+
+    /// Cached completion for `key`, unless missing or expired (expired entries are dropped).
+    pub fn get(&self, key: &str) -> Option<Completion> {
+        let entry = self.store.get(key)?;
+        let age = now_secs().saturating_sub(entry.created_at);
+        if age >= self.ttl.as_secs() {
+            self.store.remove(key);
+            return None;
+        }
+        Some(entry.completion)
+    }
+
+    /// Store `completion` under `key`.
+    pub fn put(&self, key: String, completion: Completion) {
+        self.store.put(CacheEntry {
+            key,
+            completion,
+            created_at: now_secs(),
+        });
+    }
+}
+
+/// Key of a request: everything that changes the answer, normalized so that
+/// insignificant differences (line endings, surrounding whitespace, role case) still hit.
+///
+/// Attachments are replaced by their SHA-256, so that the key of a request carrying
+/// large data URLs stays small.
+///
+/// `params` holds the sampling parameters, `Value::Null` when there are none.
+pub fn cache_key(
+    model_name: &str,
+    messages: &[Message],
+    thinking_level: Option<&str>,
+    params: &Value,
+) -> String {
+    let messages: Vec<Value> = messages
+        .iter()
+        .map(|m| {
+            json!({
+                "role": m.role.trim().to_lowercase(),
+                "text": m.input_text.replace("\r\n", "\n").trim(),
+                "image": m.input_image.as_deref().map(hash_attachment),
+                "audio": m.input_audio.as_deref().map(hash_attachment),
+                "audio_format": m.input_audio_format,
+                "video": m.input_video.as_deref().map(hash_attachment),
+                "file": m.input_file.as_deref().map(hash_attachment),
+            })
+        })
+        .collect();
+    json!({
+        "model": model_name,
+        "messages": messages,
+        "thinking_level": thinking_level.map(str::trim),
+        "params": params,
+    })
+    .to_string()
+}
+
+/// SHA-256 of an attachment, hex encoded: collisions would return another
+/// attachment's answer, so unlike `hash_key` it must be collision resistant.
+fn hash_attachment(attachment: &str) -> String {
+    Sha256::digest(attachment.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Stable 128-bit FNV-1a hash of `key`, hex encoded (stable across builds, unlike `DefaultHasher`).
+fn hash_key(key: &str) -> String {
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+    let hash = key.bytes().fold(OFFSET, |hash, byte| {
+        (hash ^ byte as u128).wrapping_mul(PRIME)
+    });
+    format!("{:032x}", hash)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn message(role: &str, text: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            input_text: text.to_string(),
+            input_image: None,
+            input_audio: None,
+            input_audio_format: None,
+            input_video: None,
+            input_file: None,
+        }
+    }
+
+    fn completion(text: &str) -> Completion {
+        Completion {
+            text: text.to_string(),
+            ..Completion::default()
+        }
+    }
+
+    #[test]
+    fn test_cache_key_normalization() {
+        let start = Instant::now();
+
+        let a = cache_key("gpt-4o", &[message("User", " hi\r\n")], None, &Value::Null);
+        let b = cache_key("gpt-4o", &[message("user", "hi")], None, &Value::Null);
+        let c = cache_key(
+            "gpt-4o",
+            &[message("user", "hi")],
+            Some("high"),
+            &Value::Null,
+        );
+        let d = cache_key(
+            "gpt-4o",
+            &[message("user", "hi")],
+            None,
+            &json!({ "seed": 1 }),
+        );
+        assert_eq!(a, b);
+        assert_ne!(b, c);
+        assert_ne!(b, d);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_cache_key_normalization took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let start = Instant::now();
+
+        let cache = ResponseCache::new(
+            Box::new(LruStore::new(2, DEFAULT_MEMORY_MAX_BYTES)),
+            DEFAULT_TTL,
+        );
+        cache.put("a".to_string(), completion("A"));
+        cache.put("b".to_string(), completion("B"));
+        assert!(cache.get("a").is_some());
+        cache.put("c".to_string(), completion("C"));
+
+        assert!(cache.get("b").is_none());
+        assert_eq!(cache.get("a").unwrap().text, "A");
+        assert_eq!(cache.get("c").unwrap().text, "C");
+
+        let duration = Instant::now() - start;
+        eprintln!("test_lru_evicts_least_recently_used took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_expired_entries_are_ignored() {
+        let start = Instant::now();
+
+        let cache = ResponseCache::new(
+            Box::new(LruStore::new(2, DEFAULT_MEMORY_MAX_BYTES)),
+            Duration::ZERO,
+        );
+        cache.put("a".to_string(), completion("A"));
+        assert!(cache.get("a").is_none());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_expired_entries_are_ignored took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_lru_ignores_colliding_entries() {
+        let start = Instant::now();
+
+        let store = LruStore::new(2, DEFAULT_MEMORY_MAX_BYTES);
+        // an entry of another key stored under the hash of "a"
+        let forged = CacheEntry {
+            key: "b".to_string(),
+            completion: completion("B"),
+            created_at: now_secs(),
+        };
+        store
+            .state
+            .lock()
+            .unwrap()
+            .entries
+            .insert(hash_key("a"), (forged, 0));
+        assert!(store.get("a").is_none());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_lru_ignores_colliding_entries took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_cache_key_hashes_attachments() {
+        let start = Instant::now();
+
+        let image = format!("data:image/png;base64,{}", "A".repeat(100_000));
+        let mut with_image = message("user", "hi");
+        with_image.input_image = Some(image.clone());
+        let key = cache_key("gpt-4o", &[with_image.clone()], None, &Value::Null);
+        assert!(key.len() < 1000);
+        assert!(!key.contains(&image));
+
+        with_image.input_image = Some(format!("{}B", image));
+        assert_ne!(key, cache_key("gpt-4o", &[with_image], None, &Value::Null));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_cache_key_hashes_attachments took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_lru_is_bounded_by_bytes() {
+        let start = Instant::now();
+
+        let entry = |key: &str| CacheEntry {
+            key: key.to_string(),
+            completion: completion(&"x".repeat(300)),
+            created_at: now_secs(),
+        };
+        // room for two entries, not three
+        let max_bytes = entry("a").size() * 5 / 2;
+        let store = LruStore::new(100, max_bytes);
+        store.put(entry("a"));
+        store.put(entry("b"));
+        store.put(entry("c"));
+        assert!(store.get("a").is_none());
+        assert!(store.get("b").is_some());
+        assert!(store.get("c").is_some());
+        assert!(store.state.lock().unwrap().bytes <= max_bytes);
+
+        // larger than the whole store: not kept
+        store.put(CacheEntry {
+            completion: completion(&"x".repeat(max_bytes)),
+            ..entry("d")
+        });
+        assert!(store.get("d").is_none());
+        assert!(store.get("c").is_some());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_lru_is_bounded_by_bytes took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_disk_store_evicts_oldest_files() {
+        let start = Instant::now();
+
+        let dir = std::env::temp_dir().join(format!("polytheus-cache-evict-{}", now_secs()));
+        let store = DiskStore::new(&dir, 1000).unwrap();
+        for key in ["a", "b", "c", "d"] {
+            store.put(CacheEntry {
+                key: key.to_string(),
+                completion: completion(&"x".repeat(300)),
+                created_at: now_secs(),
+            });
+        }
+        let bytes: u64 = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter_map(|file| file.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        assert!(bytes <= 1000);
+
+        store.put(CacheEntry {
+            key: "e".to_string(),
+            completion: completion("E"),
+            created_at: now_secs(),
+        });
+        assert!(store.get("e").is_some());
+        store.remove("e");
+        assert!(store.get("e").is_none());
+        let _ = std::fs::remove_dir_all(dir);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_disk_store_evicts_oldest_files took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_disk_store_round_trip() {
+        let start = Instant::now();
+
+        let dir = std::env::temp_dir().join(format!("polytheus-cache-test-{}", now_secs()));
+        let cache = ResponseCache::new(
+            Box::new(DiskStore::new(&dir, DEFAULT_DISK_MAX_BYTES).unwrap()),
+            DEFAULT_TTL,
+        );
+        cache.put("key".to_string(), completion("on disk"));
+        assert_eq!(cache.get("key").unwrap().text, "on disk");
+        assert!(cache.get("other").is_none());
+        let _ = std::fs::remove_dir_all(dir);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_disk_store_round_trip took: {:?}", duration);
+    }
+}
@@ -0,0 +1,339 @@
+use serde_json::{json, Value};
+
+use super::document;
+use super::Message;
+
+/// Flavour of the OpenAI chat format a provider speaks.
+///
+/// Every dialect shares the `text`, `image_url` and `input_audio` parts of the
+/// specification; they differ on the `file` part and the extensions they accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// The OpenAI specification, without extension.
+    OpenAi,
+
+    /// OpenRouter: adds `video_url` parts.
+    OpenRouter,
+
+    /// OpenAI-like `messages` input of Replicate models: adds `video_url` parts, has no
+    /// `file` part.
+    Replicate,
+}
+
+impl Dialect {
+    /// Whether the dialect has a `video_url` part.
+    fn has_video(&self) -> bool {
+        matches!(self, Dialect::OpenRouter | Dialect::Replicate)
+    }
+
+    /// Whether the dialect has the `file` part.
+    fn has_file(&self) -> bool {
+        matches!(self, Dialect::OpenAi | Dialect::OpenRouter)
+    }
+}
+
+/// OpenAI chat messages of `messages` in `dialect`.
+pub fn encode_messages(messages: &[Message], dialect: Dialect) -> Result<Vec<Value>, String> {
+    messages
+        .iter()
+        .map(|message| encode_message(message, dialect))
+        .collect()
+}
+
+/// OpenAI chat turns of `messages` in `dialect`, as sent to a provider.
+///
+/// Consecutive messages of the same role (e.g. a turn with several images) are sent
+/// as a single turn holding all their parts, since some providers reject back-to-back
+/// turns of the same role.
+pub fn encode_turns(messages: &[Message], dialect: Dialect) -> Result<Vec<Value>, String> {
+    let mut turns: Vec<Value> = Vec::with_capacity(messages.len());
+    for message in messages {
+        let mut encoded = encode_message(message, dialect)?;
+        match turns.last_mut() {
+            Some(turn) if turn["role"] == encoded["role"] => {
+                let parts = encoded["content"].as_array_mut().map(std::mem::take);
+                if let (Some(content), Some(parts)) = (turn["content"].as_array_mut(), parts) {
+                    content.extend(
+                        parts
+                            .into_iter()
+                            .filter(|part| part["type"] != "text" || part["text"] != ""),
+                    );
+                }
+            }
+            _ => turns.push(encoded),
+        }
+    }
+    Ok(turns)
+}
+
+/// OpenAI chat message of `message` in `dialect`: a text part, then one part per attachment.
+pub fn encode_message(message: &Message, dialect: Dialect) -> Result<Value, String> {
+    let mut content = vec![json!({ "type": "text", "text": message.input_text })];
+    if let Some(image) = &message.input_image {
+        content.push(json!({ "type": "image_url", "image_url": { "url": image } }));
+    }
+    if let Some(audio) = &message.input_audio {
+        let (data, format) = audio_data(audio, message.input_audio_format.as_deref())?;
+        content.push(json!({
+            "type": "input_audio",
+            "input_audio": { "data": data, "format": format }
+        }));
+    }
+    if let Some(video) = &message.input_video {
+        if !dialect.has_video() {
+            return Err(format!(
+                "Video input is not supported by the {:?} format",
+                dialect
+            ));
+        }
+        content.push(json!({ "type": "video_url", "video_url": { "url": video } }));
+    }
+    if let Some(file) = &message.input_file {
+        if !dialect.has_file() {
+            return Err(format!(
+                "File input is not supported by the {:?} format",
+                dialect
+            ));
+        }
+        content.push(json!({
+            "type": "file",
+            "file": { "filename": document::file_name(file), "file_data": file }
+        }));
+    }
+    Ok(json!({ "role": message.role, "content": content }))
+}
+
+/// Raw base64 data and format of an audio attachment, which may be a `data:` URL.
+///
+/// `input_audio` only takes raw base64: the format comes from `format`, or else from
+/// the media type of the data URL.
+fn audio_data<'a>(audio: &'a str, format: Option<&'a str>) -> Result<(&'a str, &'a str), String> {
+    let (media_type, data) = match audio
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    {
+        Some((media_type, data)) => (Some(media_type), data),
+        None => (None, audio),
+    };
+    let format = format
+        .or_else(|| match media_type? {
+            "audio/mpeg" | "audio/mp3" => Some("mp3"),
+            "audio/wav" | "audio/x-wav" | "audio/wave" => Some("wav"),
+            other => other.strip_prefix("audio/"),
+        })
+        .ok_or("the format of the input audio is unknown, set input_audio_format".to_string())?;
+    Ok((data, format))
+}
+
+/// Polytheus message of an OpenAI chat message.
+///
+/// `content` is a string or a list of parts; text parts are joined with spaces. Each
+/// message holds at most one attachment of each kind. The legacy top-level
+/// `input_image`, `input_audio`, `input_audio_format`, `input_video` and `input_file`
+/// fields are still read.
+pub fn decode_message(value: &Value) -> Result<Message, String> {
+    let role = value["role"]
+        .as_str()
+        .ok_or("you are missing the role")?
+        .to_string();
+    let mut message = Message {
+        role,
+        input_text: String::new(),
+        input_image: None,
+        input_audio: None,
+        input_audio_format: None,
+        input_video: None,
+        input_file: None,
+    };
+    let mut texts: Vec<&str> = vec![];
+    match &value["content"] {
+        Value::String(text) => texts.push(text),
+        Value::Array(parts) => {
+            for part in parts {
+                decode_part(part, &mut texts, &mut message)?;
+            }
+        }
+        _ => return Err("you are missing the content".to_string()),
+    }
+    message.input_text = texts.join(" ");
+
+    let legacy = |name: &str| value[name].as_str().map(str::to_string);
+    message.input_image = legacy("input_image").or(message.input_image);
+    message.input_audio = legacy("input_audio").or(message.input_audio);
+    message.input_audio_format = legacy("input_audio_format").or(message.input_audio_format);
+    message.input_video = legacy("input_video").or(message.input_video);
+    message.input_file = legacy("input_file").or(message.input_file);
+
+    let has_attachment = !message.attachment_modalities().is_empty();
+    if texts.is_empty() && !has_attachment {
+        return Err("you are missing the content".to_string());
+    }
+    Ok(message)
+}
+
+/// Read one content `part` into `texts` or the attachments of `message`.
+fn decode_part<'a>(
+    part: &'a Value,
+    texts: &mut Vec<&'a str>,
+    message: &mut Message,
+) -> Result<(), String> {
+    let set = |slot: &mut Option<String>, value: &Value, kind: &str| {
+        let value = value
+            .as_str()
+            .ok_or_else(|| format!("you are missing the url of a {} part", kind))?;
+        if slot.replace(value.to_string()).is_some() {
+            return Err(format!("only one {} per message is supported", kind));
+        }
+        Ok(())
+    };
+    match part["type"].as_str() {
+        _ if part.is_string() => texts.extend(part.as_str()),
+        Some("image_url") => set(&mut message.input_image, &part["image_url"]["url"], "image")?,
+        Some("video_url") => set(&mut message.input_video, &part["video_url"]["url"], "video")?,
+        Some("file") if part["file"]["file_id"].is_string() => {
+            return Err("file ids are not supported, send the file_data".to_string())
+        }
+        Some("file") => set(&mut message.input_file, &part["file"]["file_data"], "file")?,
+        Some("input_audio") => {
+            let audio = &part["input_audio"];
+            let data = audio["data"]
+                .as_str()
+                .ok_or("you are missing the data of an input_audio part")?;
+            if message.input_audio.replace(data.to_string()).is_some() {
+                return Err("only one audio per message is supported".to_string());
+            }
+            message.input_audio_format = audio["format"].as_str().map(str::to_string);
+        }
+        // text parts, and the variants some clients send ({ "content": "..." } or
+        // a nested list of parts)
+        _ => {
+            if let Some(text) = part["text"].as_str().or_else(|| part["content"].as_str()) {
+                texts.push(text);
+            } else if let Some(inner) = part["content"].as_array() {
+                texts.extend(
+                    inner
+                        .iter()
+                        .filter_map(|inner| inner["text"].as_str().or_else(|| inner.as_str())),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod codec_tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn message() -> Message {
+        Message {
+            role: "user".to_string(),
+            input_text: "What is in these?".to_string(),
+            input_image: Some("https://example.com/cat.png".to_string()),
+            input_audio: Some("UklGRg==".to_string()),
+            input_audio_format: Some("wav".to_string()),
+            input_video: Some("https://example.com/cat.mp4".to_string()),
+            input_file: Some("https://example.com/report.pdf".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let start = Instant::now();
+
+        let encoded = encode_message(&message(), Dialect::OpenRouter).unwrap();
+        assert_eq!(
+            encoded["content"][2],
+            json!({ "type": "input_audio", "input_audio": { "data": "UklGRg==", "format": "wav" } })
+        );
+        assert_eq!(decode_message(&encoded).unwrap(), message());
+
+        let spec = json!({ "role": "user", "content": [
+            { "type": "text", "text": "Describe" },
+            { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } }
+        ]});
+        let decoded = decode_message(&spec).unwrap();
+        assert_eq!(encode_message(&decoded, Dialect::OpenAi).unwrap(), spec);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_round_trip took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_same_role_messages_share_a_turn() {
+        let start = Instant::now();
+
+        let image = |role: &str, text: &str, url: &str| Message {
+            role: role.to_string(),
+            input_text: text.to_string(),
+            input_image: Some(url.to_string()),
+            input_audio: None,
+            input_audio_format: None,
+            input_video: None,
+            input_file: None,
+        };
+        let turns = encode_turns(
+            &[
+                image("user", "Compare", "a"),
+                image("user", "", "b"),
+                image("assistant", "Same cat", "c"),
+            ],
+            Dialect::OpenAi,
+        )
+        .unwrap();
+        assert_eq!(
+            turns,
+            vec![
+                json!({ "role": "user", "content": [
+                    { "type": "text", "text": "Compare" },
+                    { "type": "image_url", "image_url": { "url": "a" } },
+                    { "type": "image_url", "image_url": { "url": "b" } }
+                ]}),
+                json!({ "role": "assistant", "content": [
+                    { "type": "text", "text": "Same cat" },
+                    { "type": "image_url", "image_url": { "url": "c" } }
+                ]}),
+            ]
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_same_role_messages_share_a_turn took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_dialects_and_audio_data_urls() {
+        let start = Instant::now();
+
+        assert!(encode_message(&message(), Dialect::OpenAi).is_err());
+        let data_url = Message {
+            input_audio: Some("data:audio/mpeg;base64,SUQz".to_string()),
+            input_audio_format: None,
+            input_video: None,
+            ..message()
+        };
+        let encoded = encode_message(&data_url, Dialect::OpenAi).unwrap();
+        assert_eq!(
+            encoded["content"][2]["input_audio"],
+            json!({ "data": "SUQz", "format": "mp3" })
+        );
+        let unknown = Message {
+            input_audio: Some("SUQz".to_string()),
+            input_audio_format: None,
+            ..data_url
+        };
+        assert!(encode_message(&unknown, Dialect::OpenAi).is_err());
+
+        let two_images = json!({ "role": "user", "content": [
+            { "type": "image_url", "image_url": { "url": "a" } },
+            { "type": "image_url", "image_url": { "url": "b" } }
+        ]});
+        assert_eq!(
+            decode_message(&two_images),
+            Err("only one image per message is supported".to_string())
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_dialects_and_audio_data_urls took: {:?}", duration);
+    }
+}
@@ -0,0 +1,192 @@
+use base64::prelude::*;
+use lopdf::Document as Pdf;
+
+/// Characters of extracted text passed to a model, the rest being cut.
+pub const MAX_TEXT_CHARS: usize = 200_000;
+
+/// Pages of a scanned PDF sent as images.
+pub const MAX_PAGE_IMAGES: usize = 20;
+
+/// Content of a document, for models that can't read the file itself.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Document {
+    /// Text of the document.
+    pub text: String,
+
+    /// Pages of a scanned PDF (which has no text), as JPEG `data:` URLs.
+    pub page_images: Vec<String>,
+}
+
+/// Name given to the file at `url` in a `file` part: the last segment of an http(s)
+/// URL, else a name matching the media type of a `data:` URL.
+pub fn file_name(url: &str) -> String {
+    if let Some(rest) = url.strip_prefix("data:") {
+        let media_type = rest.split([';', ',']).next().unwrap_or_default();
+        let extension = match media_type {
+            "text/plain" => "txt",
+            "text/markdown" => "md",
+            "text/csv" => "csv",
+            "application/json" => "json",
+            _ => "pdf",
+        };
+        return format!("document.{}", extension);
+    }
+    url.split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("document.pdf")
+        .to_string()
+}
+
+/// Content of the document `bytes`: a PDF or a UTF-8 text file.
+///
+/// A PDF without text (a scan) gives its JPEG page images instead; other PDF images
+/// would need a renderer we don't ship.
+pub fn extract(bytes: &[u8]) -> Result<Document, String> {
+    if !bytes.starts_with(b"%PDF") {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|_| "unsupported document format, expected a PDF or text".to_string())?;
+        return Ok(Document {
+            text: truncate(text),
+            page_images: vec![],
+        });
+    }
+
+    let pdf = Pdf::load_mem(bytes).map_err(|e| format!("invalid PDF: {}", e))?;
+    if pdf.is_encrypted() {
+        return Err("the PDF is encrypted".to_string());
+    }
+    let pages = pdf.get_pages();
+    let numbers: Vec<u32> = pages.keys().copied().collect();
+    let text = pdf
+        .extract_text_chunks(&numbers)
+        .into_iter()
+        .filter_map(Result::ok)
+        .collect::<Vec<_>>()
+        .join("");
+    if !text.trim().is_empty() {
+        return Ok(Document {
+            text: truncate(text.trim()),
+            page_images: vec![],
+        });
+    }
+
+    // scanners store each page as a single JPEG (DCTDecode) image
+    let page_images = pages
+        .values()
+        .flat_map(|page| pdf.get_page_images(*page).unwrap_or_default())
+        .filter(|image| {
+            image
+                .filters
+                .as_ref()
+                .is_some_and(|filters| filters.iter().any(|f| f == "DCTDecode"))
+        })
+        .take(MAX_PAGE_IMAGES)
+        .map(|image| {
+            format!(
+                "data:image/jpeg;base64,{}",
+                BASE64_STANDARD.encode(image.content)
+            )
+        })
+        .collect();
+    Ok(Document {
+        text: String::new(),
+        page_images,
+    })
+}
+
+/// `text` cut to `MAX_TEXT_CHARS` characters.
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_TEXT_CHARS) {
+        Some((end, _)) => format!("{}\n[...]", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod document_tests {
+    use super::*;
+    use lopdf::content::{Content, Operation};
+    use lopdf::{dictionary, Object, Stream};
+    use std::time::Instant;
+
+    /// A one-page PDF showing `text`.
+    fn pdf(text: &str) -> Vec<u8> {
+        let mut pdf = Pdf::with_version("1.5");
+        let pages_id = pdf.new_object_id();
+        let font_id = pdf.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Courier",
+        });
+        let content = Content {
+            operations: vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                Operation::new("Td", vec![72.into(), 720.into()]),
+                Operation::new("Tj", vec![Object::string_literal(text)]),
+                Operation::new("ET", vec![]),
+            ],
+        };
+        let content_id = pdf.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+        let page_id = pdf.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+            "MediaBox" => vec![0.into(), 0.into(), 612.into(), 792.into()],
+        });
+        pdf.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = pdf.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        pdf.trailer.set("Root", catalog_id);
+        let mut bytes = vec![];
+        pdf.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_extract_pdf_and_text() {
+        let start = Instant::now();
+
+        let document = extract(&pdf("Quarterly revenue grew by 12%")).unwrap();
+        assert!(document.text.contains("Quarterly revenue grew by 12%"));
+        assert!(document.page_images.is_empty());
+
+        assert_eq!(extract(b"a,b\n1,2").unwrap().text, "a,b\n1,2");
+        assert!(extract(&[0xff, 0xd8, 0xff, 0xe0]).is_err());
+        assert!(extract(b"%PDF-1.5 truncated").is_err());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_extract_pdf_and_text took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_file_name() {
+        let start = Instant::now();
+
+        assert_eq!(
+            file_name("https://example.com/a/report.pdf?x=1"),
+            "report.pdf"
+        );
+        assert_eq!(file_name("data:text/csv;base64,YSxi"), "document.csv");
+        assert_eq!(
+            file_name("data:application/pdf;base64,JVBE"),
+            "document.pdf"
+        );
+        assert_eq!(file_name("https://example.com/"), "document.pdf");
+
+        let duration = Instant::now() - start;
+        eprintln!("test_file_name took: {:?}", duration);
+    }
+}
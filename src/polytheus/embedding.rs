@@ -0,0 +1,220 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::env;
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+
+use super::http::{self, HttpConfig};
+use super::model::Provider;
+use super::Usage;
+
+/// Maximum number of inputs sent in a single upstream embeddings call.
+pub const MAX_BATCH: usize = 128;
+
+/// Future returned by `Embedder::embed`.
+pub type EmbedFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<Vec<f32>>, String>> + Send + 'a>>;
+
+/// Turns texts into embedding vectors.
+pub trait Embedder: Send + Sync + Debug {
+    /// One vector per input, in the same order.
+    fn embed<'a>(&'a self, inputs: &'a [String]) -> EmbedFuture<'a>;
+}
+
+/// Embedding vectors of a batch of inputs.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Embeddings {
+    /// One vector per input, in the same order.
+    pub vectors: Vec<Vec<f32>>,
+
+    /// Tokens and cost of the inputs (no completion tokens).
+    pub usage: Usage,
+}
+
+/// Embeddings from the OpenAI-compatible `/embeddings` endpoint of OpenRouter.
+#[derive(Debug)]
+pub struct OpenRouterEmbedder {
+    client: Client,
+    url: String,
+    model: String,
+}
+
+impl OpenRouterEmbedder {
+    /// Embedder calling `model` through `client`, on the base URL configured in `http_config`.
+    pub fn new(client: Client, http_config: &HttpConfig, model: &str) -> OpenRouterEmbedder {
+        OpenRouterEmbedder {
+            client,
+            url: format!("{}/embeddings", http_config.base_url(&Provider::OpenRouter)),
+            model: model.to_string(),
+        }
+    }
+
+    /// Embed `inputs` in batches of `MAX_BATCH`, asking for `dimensions` when set.
+    pub async fn embed_batched(
+        &self,
+        inputs: &[String],
+        dimensions: Option<usize>,
+    ) -> Result<Embeddings, String> {
+        let mut vectors = Vec::with_capacity(inputs.len());
+        // unknown as soon as one batch doesn't report its tokens
+        let mut prompt_tokens = Some(0);
+        for batch in inputs.chunks(MAX_BATCH) {
+            let (batch_vectors, tokens) = self.request(batch, dimensions).await?;
+            vectors.extend(batch_vectors);
+            prompt_tokens = prompt_tokens
+                .zip(tokens)
+                .map(|(total, tokens)| total + tokens);
+        }
+        Ok(Embeddings {
+            vectors,
+            usage: Usage {
+                prompt_tokens,
+                completion_tokens: Some(0),
+                cost: None,
+                reasoning_tokens: None,
+            },
+        })
+    }
+
+    /// One upstream call: the vectors and the prompt tokens it reported.
+    async fn request(
+        &self,
+        inputs: &[String],
+        dimensions: Option<usize>,
+    ) -> Result<(Vec<Vec<f32>>, Option<u64>), String> {
+        let api_key =
+            env::var("OPENROUTER_API_KEY").map_err(|_| "OPENROUTER_API_KEY not set".to_string())?;
+        let mut body = Map::new();
+        body.insert("model".to_string(), json!(self.model));
+        body.insert("input".to_string(), json!(inputs));
+        if let Some(dimensions) = dimensions {
+            body.insert("dimensions".to_string(), json!(dimensions));
+        }
+        let request = self
+            .client
+            .post(&self.url)
+            .header("Authorization", format!("Bearer {}", api_key))
+            .json(&Value::Object(body));
+        let response = http::send_traced("POST", &self.url, request)
+            .await
+            .map_err(|e| format!("Failed to send embeddings request: {}", e))?;
+        let status = response.status();
+        let body: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse embeddings response: {}", e))?;
+        if !status.is_success() {
+            return Err(format!(
+                "Embeddings call failed with status: {} and body: {}",
+                status, body
+            ));
+        }
+        let mut vectors = parse_embeddings(&body, inputs.len())?;
+        if let Some(dimensions) = dimensions {
+            // some upstreams ignore `dimensions`: shorten the vectors ourselves
+            vectors = vectors
+                .into_iter()
+                .map(|vector| shorten(vector, dimensions))
+                .collect::<Result<_, _>>()?;
+        }
+        Ok((vectors, body["usage"]["prompt_tokens"].as_u64()))
+    }
+}
+
+impl Embedder for OpenRouterEmbedder {
+    fn embed<'a>(&'a self, inputs: &'a [String]) -> EmbedFuture<'a> {
+        Box::pin(async move { Ok(self.embed_batched(inputs, None).await?.vectors) })
+    }
+}
+
+/// Vectors of an OpenAI-like embeddings response, ordered by `index`.
+pub fn parse_embeddings(body: &Value, expected: usize) -> Result<Vec<Vec<f32>>, String> {
+    let mut data: Vec<&Value> = body["data"]
+        .as_array()
+        .ok_or_else(|| format!("Embeddings response without data: {}", body))?
+        .iter()
+        .collect();
+    data.sort_by_key(|item| item["index"].as_u64().unwrap_or(0));
+    let vectors: Vec<Vec<f32>> = data
+        .iter()
+        .map(|item| {
+            item["embedding"]
+                .as_array()
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(|v| v.as_f64().map(|v| v as f32))
+                        .collect()
+                })
+                .ok_or_else(|| "Embeddings response item without embedding".to_string())
+        })
+        .collect::<Result<_, _>>()?;
+    if vectors.len() != expected {
+        return Err(format!(
+            "Expected {} embeddings, got {}",
+            expected,
+            vectors.len()
+        ));
+    }
+    Ok(vectors)
+}
+
+/// Keep the first `dimensions` values of `vector` and scale them back to unit length.
+///
+/// Valid for Matryoshka-trained models (like OpenAI's `text-embedding-3-*`), whose
+/// leading dimensions carry most of the meaning.
+pub fn shorten(mut vector: Vec<f32>, dimensions: usize) -> Result<Vec<f32>, String> {
+    if dimensions == 0 || dimensions > vector.len() {
+        return Err(format!(
+            "dimensions must be between 1 and {}, got {}",
+            vector.len(),
+            dimensions
+        ));
+    }
+    if dimensions < vector.len() {
+        vector.truncate(dimensions);
+        let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|v| *v /= norm);
+        }
+    }
+    Ok(vector)
+}
+
+#[cfg(test)]
+mod embedding_tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_parse_embeddings_in_index_order() {
+        let start = Instant::now();
+
+        let body = json!({ "data": [
+            { "index": 1, "embedding": [0.0, 1.0] },
+            { "index": 0, "embedding": [1.0, 0.0] }
+        ]});
+        assert_eq!(
+            parse_embeddings(&body, 2).unwrap(),
+            vec![vec![1.0, 0.0], vec![0.0, 1.0]]
+        );
+        assert!(parse_embeddings(&body, 3).is_err());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_parse_embeddings_in_index_order took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_shorten_renormalizes() {
+        let start = Instant::now();
+
+        assert_eq!(shorten(vec![3.0, 4.0, 12.0], 2).unwrap(), vec![0.6, 0.8]);
+        assert_eq!(shorten(vec![1.0, 0.0], 2).unwrap(), vec![1.0, 0.0]);
+        assert!(shorten(vec![1.0, 0.0], 3).is_err());
+        assert!(shorten(vec![1.0, 0.0], 0).is_err());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_shorten_renormalizes took: {:?}", duration);
+    }
+}
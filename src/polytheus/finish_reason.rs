@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Why a model stopped generating, normalized across providers.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// Natural end of the answer or a stop sequence.
+    #[default]
+    Stop,
+
+    /// The answer was truncated at the maximum number of tokens.
+    Length,
+
+    /// The answer was withheld or cut by a moderation filter.
+    ContentFilter,
+
+    /// The model stopped to call a tool.
+    ToolCalls,
+
+    /// The upstream provider failed mid-generation.
+    Error,
+}
+
+impl FinishReason {
+    /// Reason of an OpenAI-like (OpenRouter) choice.
+    ///
+    /// OpenRouter normalizes `finish_reason` but some routes pass the native one
+    /// through (Anthropic's `max_tokens`, Gemini's `SAFETY`...), so both are recognized.
+    pub fn from_openrouter(choice: &Value) -> FinishReason {
+        let reason = choice["finish_reason"].as_str().unwrap_or("stop");
+        match reason.to_ascii_lowercase().as_str() {
+            "length" | "max_tokens" => FinishReason::Length,
+            "content_filter" | "refusal" | "safety" | "recitation" => FinishReason::ContentFilter,
+            "tool_calls" | "tool_use" | "function_call" => FinishReason::ToolCalls,
+            "error" => FinishReason::Error,
+            _ => FinishReason::Stop,
+        }
+    }
+
+    /// Reason of a succeeded Replicate prediction.
+    ///
+    /// Replicate doesn't report one: the answer is deemed truncated when it used
+    /// every token of `max_tokens`.
+    pub fn from_replicate(prediction: &Value, max_tokens: Option<u32>) -> FinishReason {
+        let output_tokens = prediction["metrics"]["output_token_count"].as_u64();
+        match (output_tokens, max_tokens) {
+            (Some(output), Some(max)) if output >= u64::from(max) => FinishReason::Length,
+            _ => FinishReason::Stop,
+        }
+    }
+
+    /// Name of the reason in the OpenAI API.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::Length => "length",
+            FinishReason::ContentFilter => "content_filter",
+            FinishReason::ToolCalls => "tool_calls",
+            FinishReason::Error => "error",
+        }
+    }
+}
+
+#[cfg(test)]
+mod finish_reason_tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Instant;
+
+    #[test]
+    fn test_finish_reason_from_providers() {
+        let start = Instant::now();
+
+        let reason = |finish_reason: Value| {
+            FinishReason::from_openrouter(&json!({ "finish_reason": finish_reason }))
+        };
+        assert_eq!(reason(json!("length")), FinishReason::Length);
+        assert_eq!(reason(json!("SAFETY")), FinishReason::ContentFilter);
+        assert_eq!(reason(json!("tool_calls")), FinishReason::ToolCalls);
+        assert_eq!(reason(Value::Null), FinishReason::Stop);
+
+        let prediction = json!({ "status": "succeeded", "metrics": { "output_token_count": 100 } });
+        assert_eq!(
+            FinishReason::from_replicate(&prediction, Some(100)),
+            FinishReason::Length
+        );
+        assert_eq!(
+            FinishReason::from_replicate(&prediction, Some(101)),
+            FinishReason::Stop
+        );
+        assert_eq!(
+            FinishReason::from_replicate(&prediction, None),
+            FinishReason::Stop
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_finish_reason_from_providers took: {:?}", duration);
+    }
+}
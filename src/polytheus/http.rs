@@ -0,0 +1,511 @@
+use reqwest::header::LOCATION;
+use reqwest::redirect::Policy;
+use reqwest::{Client, ClientBuilder, Proxy, RequestBuilder, Response, Url};
+use std::env;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::OnceLock;
+use tokio::time::Duration;
+use tracing::{field, info_span, Instrument};
+
+use super::model::Provider;
+
+/// Default base URL of the Replicate API.
+pub const REPLICATE_BASE_URL: &str = "https://api.replicate.com/v1";
+
+/// Default base URL of the OpenRouter API.
+pub const OPENROUTER_BASE_URL: &str = "https://openrouter.ai/api/v1";
+
+/// Size, in bytes, above which a download from a provider is aborted.
+pub const MAX_DOWNLOAD_BYTES: usize = 100 * 1024 * 1024;
+
+/// Redirects followed when downloading a URL sent by a client.
+const MAX_REDIRECTS: usize = 5;
+
+/// Configuration of the HTTP client shared by every upstream call of `Polytheus`.
+#[derive(Debug, Clone, Default)]
+pub struct HttpConfig {
+    /// Total timeout of a single HTTP request (a Replicate poll, an OpenRouter call...).
+    pub timeout: Option<Duration>,
+
+    /// Timeout of the TCP/TLS connection establishment.
+    pub connect_timeout: Option<Duration>,
+
+    /// How long an idle pooled connection is kept alive.
+    pub pool_idle_timeout: Option<Duration>,
+
+    /// Proxy URL used for every request (e.g. "http://proxy:3128").
+    pub proxy: Option<String>,
+
+    /// Talk HTTP/2 without ALPN negotiation (only for upstreams known to support it).
+    pub http2_prior_knowledge: bool,
+
+    /// Replaces `REPLICATE_BASE_URL` (e.g. a mock server or an internal gateway).
+    pub replicate_base_url: Option<String>,
+
+    /// Replaces `OPENROUTER_BASE_URL`.
+    pub openrouter_base_url: Option<String>,
+}
+
+/// Parse an environment variable holding a number of seconds.
+fn env_secs(name: &str) -> Option<Duration> {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|secs| *secs > 0.0)
+        .map(Duration::from_secs_f64)
+}
+
+impl HttpConfig {
+    /// Read the configuration from the `POLYTHEUS_HTTP_*`, `REPLICATE_BASE_URL` and
+    /// `OPENROUTER_BASE_URL` environment variables.
+    pub fn from_env() -> HttpConfig {
+        HttpConfig {
+            timeout: env_secs("POLYTHEUS_HTTP_TIMEOUT_SECS"),
+            connect_timeout: env_secs("POLYTHEUS_HTTP_CONNECT_TIMEOUT_SECS"),
+            pool_idle_timeout: env_secs("POLYTHEUS_HTTP_POOL_IDLE_TIMEOUT_SECS"),
+            proxy: env::var("POLYTHEUS_HTTP_PROXY").ok(),
+            http2_prior_knowledge: env::var("POLYTHEUS_HTTP2_PRIOR_KNOWLEDGE")
+                .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+                .unwrap_or(false),
+            replicate_base_url: env::var("REPLICATE_BASE_URL").ok(),
+            openrouter_base_url: env::var("OPENROUTER_BASE_URL").ok(),
+        }
+    }
+
+    /// Build a client from this configuration.
+    pub fn build_client(&self) -> Result<Client, String> {
+        self.client_builder()?
+            .build()
+            .map_err(|e| format!("Failed to build the HTTP client: {}", e))
+    }
+
+    /// Builder of a client from this configuration.
+    fn client_builder(&self) -> Result<ClientBuilder, String> {
+        let mut builder = Client::builder().tcp_keepalive(Duration::from_secs(60));
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            let proxy = Proxy::all(proxy).map_err(|e| format!("Invalid proxy URL: {}", e))?;
+            builder = builder.proxy(proxy);
+        }
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        Ok(builder)
+    }
+
+    /// Base URL to use for `provider`, without trailing slash.
+    pub fn base_url(&self, provider: &Provider) -> &str {
+        let (custom, default) = match provider {
+            Provider::Replicate => (&self.replicate_base_url, REPLICATE_BASE_URL),
+            Provider::OpenRouter => (&self.openrouter_base_url, OPENROUTER_BASE_URL),
+        };
+        custom
+            .as_deref()
+            .map(|url| url.trim_end_matches('/'))
+            .unwrap_or(default)
+    }
+
+    /// Point an absolute `url` of `provider` to the configured base URL.
+    ///
+    /// URLs that don't start with the provider's default base URL are returned unchanged.
+    pub fn rebase(&self, provider: &Provider, url: &str) -> String {
+        let default = match provider {
+            Provider::Replicate => REPLICATE_BASE_URL,
+            Provider::OpenRouter => OPENROUTER_BASE_URL,
+        };
+        match url.strip_prefix(default) {
+            Some(path) => format!("{}{}", self.base_url(provider), path),
+            None => url.to_string(),
+        }
+    }
+}
+
+/// Client configured from the environment, built once per process.
+///
+/// `reqwest::Client` is a handle on a shared connection pool: cloning it keeps the TLS
+/// sessions and open connections, which is what makes warm Lambda invocations cheap.
+pub fn shared_client() -> Result<Client, String> {
+    static CLIENT: OnceLock<Result<Client, String>> = OnceLock::new();
+    CLIENT
+        .get_or_init(|| HttpConfig::from_env().build_client())
+        .clone()
+}
+
+/// Send `request` inside an `http.request` client span recording its status code.
+pub async fn send_traced(
+    method: &'static str,
+    url: &str,
+    request: RequestBuilder,
+) -> reqwest::Result<Response> {
+    let span = info_span!(
+        "http.request",
+        otel.kind = "client",
+        http.method = method,
+        url.full = url,
+        http.status_code = field::Empty,
+    );
+    let response = request.send().instrument(span.clone()).await;
+    if let Ok(response) = &response {
+        span.record("http.status_code", response.status().as_u16());
+    }
+    response
+}
+
+/// Download the content at `url` of a provider (a generated image, a speech file...),
+/// aborted past `max_bytes`.
+pub async fn download(client: &Client, url: &str, max_bytes: usize) -> Result<Vec<u8>, String> {
+    let response = send_traced("GET", url, client.get(url))
+        .await
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    read_body(url, response, max_bytes).await
+}
+
+/// Download the content at `url`, sent by a client (an attachment), aborted past
+/// `max_bytes`.
+///
+/// Only public hosts are reached: the address of every hop, redirects included, is
+/// checked by `public_address`, and the connection is pinned to it so that a second
+/// DNS answer can't point elsewhere.
+///
+/// A proxy would resolve the host itself and defeat the check, so downloads are
+/// refused when `config` has one, and the system proxies (`HTTP_PROXY`...) are ignored.
+pub async fn download_public(
+    config: &HttpConfig,
+    url: &str,
+    max_bytes: usize,
+) -> Result<Vec<u8>, String> {
+    if config.proxy.is_some() {
+        return Err(format!(
+            "Can't download {} through a proxy: send the attachment as a data URL",
+            url
+        ));
+    }
+    let mut url = Url::parse(url).map_err(|e| format!("Invalid URL {}: {}", url, e))?;
+    for _ in 0..=MAX_REDIRECTS {
+        let address = public_address(&url).await?;
+        let mut builder = config.client_builder()?.no_proxy().redirect(Policy::none());
+        if let Some(domain) = url.domain() {
+            builder = builder.resolve(domain, address);
+        }
+        let client = builder
+            .build()
+            .map_err(|e| format!("Failed to build the HTTP client: {}", e))?;
+        let response = send_traced("GET", url.as_str(), client.get(url.clone()))
+            .await
+            .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+        if !response.status().is_redirection() {
+            return read_body(url.as_str(), response, max_bytes).await;
+        }
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| format!("Redirect without location from {}", url))?;
+        url = url
+            .join(location)
+            .map_err(|e| format!("Invalid redirect from {}: {}", url, e))?;
+    }
+    Err(format!("Too many redirects downloading {}", url))
+}
+
+/// Address to connect to for `url`, when its host only resolves to public addresses.
+pub async fn public_address(url: &Url) -> Result<SocketAddr, String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Only http(s) URLs can be downloaded, not {}", url));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("URL without host: {}", url))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<SocketAddr> = tokio::task::spawn_blocking(move || {
+        (host.as_str(), port)
+            .to_socket_addrs()
+            .map(|addresses| addresses.collect())
+    })
+    .await
+    .map_err(|e| format!("Failed to resolve {}: {}", url, e))?
+    .map_err(|e| format!("Failed to resolve {}: {}", url, e))?;
+    match addresses.first() {
+        Some(address) if addresses.iter().all(|a| is_public(a.ip())) => Ok(*address),
+        Some(_) => Err(format!("{} is not a public address", url)),
+        None => Err(format!("Failed to resolve {}", url)),
+    }
+}
+
+/// Whether `ip` is reachable on the internet: not loopback, private, link-local (where
+/// cloud metadata services live), unspecified, shared (CGNAT), multicast, documentation
+/// or reserved.
+///
+/// IPv6 addresses embedding an IPv4 one (mapped, NAT64 `64:ff9b::/96`, 6to4
+/// `2002::/16`) are judged on it; the other transition ranges are refused.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            let embedded = |high: u16, low: u16| {
+                let [a, b] = high.to_be_bytes();
+                let [c, d] = low.to_be_bytes();
+                is_public(IpAddr::V4([a, b, c, d].into()))
+            };
+            match segments {
+                // NAT64 well-known prefix
+                [0x64, 0xff9b, 0, 0, 0, 0, high, low] => embedded(high, low),
+                // 6to4
+                [0x2002, high, low, ..] => embedded(high, low),
+                _ => match ip.to_ipv4_mapped() {
+                    Some(ip) => is_public(IpAddr::V4(ip)),
+                    None => {
+                        let [first, second, ..] = segments;
+                        !(ip.is_loopback()
+                            || ip.is_unspecified()
+                            || ip.is_multicast()
+                            || first & 0xfe00 == 0xfc00
+                            || first & 0xffc0 == 0xfe80
+                            // IPv4-compatible (deprecated)
+                            || segments[..6] == [0; 6]
+                            // NAT64 local-use, Teredo, documentation
+                            || (first == 0x64 && second == 0xff9b)
+                            || (first == 0x2001 && second == 0)
+                            || (first == 0x2001 && second == 0xdb8))
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Body of the successful `response` from `url`, refused past `max_bytes`: up front
+/// when its `Content-Length` says so, else as soon as the received chunks do.
+async fn read_body(url: &str, mut response: Response, max_bytes: usize) -> Result<Vec<u8>, String> {
+    if !response.status().is_success() {
+        return Err(format!(
+            "Download of {} failed with status: {}",
+            url,
+            response.status()
+        ));
+    }
+    let too_large = || format!("{} is larger than {} bytes", url, max_bytes);
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_bytes as u64)
+    {
+        return Err(too_large());
+    }
+    let mut body = vec![];
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?
+    {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod http_tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::time::Instant;
+
+    /// Server on localhost answering every request with `response`.
+    fn serve(response: &'static str) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().filter_map(Result::ok) {
+                let _ = stream.read(&mut [0; 4096]);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_base_url_override() {
+        let start = Instant::now();
+
+        let config = HttpConfig {
+            openrouter_base_url: Some("http://localhost:8080/v1/".to_string()),
+            ..HttpConfig::default()
+        };
+        assert_eq!(config.base_url(&Provider::Replicate), REPLICATE_BASE_URL);
+        assert_eq!(
+            config.base_url(&Provider::OpenRouter),
+            "http://localhost:8080/v1"
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_base_url_override took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_rebase() {
+        let start = Instant::now();
+
+        let config = HttpConfig {
+            replicate_base_url: Some("http://mock/v1".to_string()),
+            ..HttpConfig::default()
+        };
+        assert_eq!(
+            config.rebase(
+                &Provider::Replicate,
+                "https://api.replicate.com/v1/predictions/abc"
+            ),
+            "http://mock/v1/predictions/abc"
+        );
+        assert_eq!(
+            config.rebase(&Provider::Replicate, "https://elsewhere/x"),
+            "https://elsewhere/x"
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_rebase took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_build_client_rejects_invalid_proxy() {
+        let start = Instant::now();
+
+        let config = HttpConfig {
+            proxy: Some("not a url".to_string()),
+            ..HttpConfig::default()
+        };
+        assert!(config.build_client().is_err());
+        assert!(HttpConfig::default().build_client().is_ok());
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_build_client_rejects_invalid_proxy took: {:?}",
+            duration
+        );
+    }
+
+    #[test]
+    fn test_is_public() {
+        let start = Instant::now();
+
+        for private in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::10.0.0.1",
+            "64:ff9b:1::1.1.1.1",
+            "2002:7f00:1::",
+            "2001:db8::1",
+            "2001::1",
+            "::10.0.0.1",
+        ] {
+            assert!(!is_public(private.parse().unwrap()), "{}", private);
+        }
+        for public in [
+            "1.1.1.1",
+            "8.8.8.8",
+            "2606:4700:4700::1111",
+            "64:ff9b::1.1.1.1",
+            "2002:0101:0101::1",
+        ] {
+            assert!(is_public(public.parse().unwrap()), "{}", public);
+        }
+
+        let duration = Instant::now() - start;
+        eprintln!("test_is_public took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_private_urls_are_refused() {
+        let start = Instant::now();
+
+        for url in [
+            "http://169.254.169.254/latest/meta-data/",
+            "http://localhost:8080/admin",
+            "http://[::1]/",
+            "http://10.0.0.1/file.pdf",
+            "http://[64:ff9b::a9fe:a9fe]/latest/meta-data/",
+        ] {
+            let refused = public_address(&Url::parse(url).unwrap()).await;
+            assert_eq!(
+                refused,
+                Err(format!(
+                    "{} is not a public address",
+                    Url::parse(url).unwrap()
+                ))
+            );
+        }
+        let ftp = download_public(&HttpConfig::default(), "ftp://example.com/a.pdf", 10).await;
+        assert!(ftp.unwrap_err().starts_with("Only http(s) URLs"));
+        let proxied = HttpConfig {
+            proxy: Some("http://proxy:3128".to_string()),
+            ..HttpConfig::default()
+        };
+        let proxied = download_public(&proxied, "https://example.com/a.pdf", 10).await;
+        assert!(proxied.unwrap_err().contains("through a proxy"));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_private_urls_are_refused took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_download_is_capped() {
+        let start = Instant::now();
+
+        let client = Client::new();
+        let small = serve("HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello");
+        assert_eq!(download(&client, &small, 5).await, Ok(b"hello".to_vec()));
+
+        let announced =
+            serve("HTTP/1.1 200 OK\r\nContent-Length: 1000000\r\nConnection: close\r\n\r\n");
+        assert!(download(&client, &announced, 10)
+            .await
+            .unwrap_err()
+            .contains("larger than 10 bytes"));
+
+        let chunked = serve(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n8\r\n01234567\r\n8\r\n89abcdef\r\n0\r\n\r\n",
+        );
+        assert!(download(&client, &chunked, 10)
+            .await
+            .unwrap_err()
+            .contains("larger than 10 bytes"));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_download_is_capped took: {:?}", duration);
+    }
+}
@@ -0,0 +1,165 @@
+use base64::prelude::*;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use super::http;
+use super::model::Model;
+use super::Usage;
+
+/// Maximum number of images of a single request (same limit as OpenAI).
+pub const MAX_IMAGES: u32 = 10;
+
+/// Request of an image generation, or of an edit when `image` is set.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ImageRequest {
+    /// Description of the image to generate, or of the edit to make.
+    pub prompt: String,
+
+    /// Image to edit, as a URL or a data URL.
+    pub image: Option<String>,
+
+    /// Mask of the area to repaint (white), as a URL or a data URL.
+    pub mask: Option<String>,
+
+    /// Number of images to generate.
+    pub n: u32,
+
+    /// Size as "WIDTHxHEIGHT", sent as an aspect ratio (e.g. "1792x1024" is "7:4").
+    pub size: Option<String>,
+}
+
+/// Images produced by a model.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GeneratedImages {
+    /// Where each image can be downloaded (URLs expire after about an hour on Replicate).
+    pub urls: Vec<String>,
+
+    /// Cost of the predictions.
+    pub usage: Usage,
+}
+
+/// Replicate input of one prediction of `model` for `request`.
+pub fn build_replicate_input(model: &Model, request: &ImageRequest) -> Result<Value, String> {
+    if request.prompt.trim().is_empty() {
+        return Err("you are missing the prompt".to_string());
+    }
+    let mut input = Map::new();
+    input.insert("prompt".to_string(), json!(request.prompt));
+    if let Some(size) = &request.size {
+        input.insert("aspect_ratio".to_string(), json!(aspect_ratio(size)?));
+    }
+    if let Some(image) = &request.image {
+        let parameter = model.get_image_parameters().ok_or_else(|| {
+            format!(
+                "Model '{}' doesn't accept an image to edit",
+                model.get_name()
+            )
+        })?;
+        input.insert(parameter.to_string(), json!(image));
+    }
+    if let Some(mask) = &request.mask {
+        if !model.has_capability("inpainting") {
+            return Err(format!(
+                "Model '{}' doesn't support masks",
+                model.get_name()
+            ));
+        }
+        input.insert("mask".to_string(), json!(mask));
+    }
+    Ok(json!({ "input": input }))
+}
+
+/// Reduce "WIDTHxHEIGHT" to the "W:H" aspect ratio Replicate image models expect.
+pub fn aspect_ratio(size: &str) -> Result<String, String> {
+    let invalid = || format!("Invalid size '{}', expected WIDTHxHEIGHT", size);
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+    let height: u32 = height.trim().parse().map_err(|_| invalid())?;
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+    let divisor = gcd(width, height);
+    Ok(format!("{}:{}", width / divisor, height / divisor))
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Image URLs of a Replicate output: a single URL or an array of them.
+pub fn output_urls(output: &Value) -> Vec<String> {
+    match output {
+        Value::String(url) => vec![url.clone()],
+        Value::Array(items) => items
+            .iter()
+            .filter_map(|item| item.as_str().map(|url| url.to_string()))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Base64 content of the image at `url` (data URLs are decoded in place).
+pub async fn download_base64(client: &Client, url: &str) -> Result<String, String> {
+    if let Some(data) = url.strip_prefix("data:") {
+        return data
+            .split_once(";base64,")
+            .map(|(_, data)| data.to_string())
+            .ok_or_else(|| "Only base64 data URLs are supported".to_string());
+    }
+    let bytes = http::download(client, url, http::MAX_DOWNLOAD_BYTES).await?;
+    Ok(BASE64_STANDARD.encode(bytes))
+}
+
+#[cfg(test)]
+mod image_tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_aspect_ratio() {
+        let start = Instant::now();
+
+        assert_eq!(aspect_ratio("1024x1024").unwrap(), "1:1");
+        assert_eq!(aspect_ratio("1792x1024").unwrap(), "7:4");
+        assert_eq!(aspect_ratio("1920x1080").unwrap(), "16:9");
+        assert!(aspect_ratio("large").is_err());
+        assert!(aspect_ratio("0x10").is_err());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_aspect_ratio took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_output_urls_and_data_url_download() {
+        let start = Instant::now();
+
+        assert_eq!(
+            output_urls(&json!("https://x/1.webp")),
+            vec!["https://x/1.webp"]
+        );
+        assert_eq!(
+            output_urls(&json!(["https://x/1.webp", "https://x/2.webp"])).len(),
+            2
+        );
+        assert!(output_urls(&json!(null)).is_empty());
+
+        let client = Client::new();
+        assert_eq!(
+            download_base64(&client, "data:image/png;base64,AAAA")
+                .await
+                .unwrap(),
+            "AAAA"
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_output_urls_and_data_url_download took: {:?}",
+            duration
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use super::codec::{self, Dialect};
+use super::model::Model;
+use super::Message;
+
+/// Inputs of a Replicate text model, describing how `Message`s become its `input`.
+///
+/// Images go to the model's `image_parameters` input, when it has one.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct InputSchema {
+    /// Input taking the OpenAI-like conversation; the prompt inputs are unused when set.
+    pub messages: Option<String>,
+
+    /// Input taking the text of the conversation.
+    pub prompt: Option<String>,
+
+    /// Input taking the system and developer messages; they are prepended to the
+    /// prompt when the model has none.
+    pub system_prompt: Option<String>,
+
+    /// Whether the image input takes a list of images rather than a single one.
+    pub image_list: bool,
+}
+
+impl Default for InputSchema {
+    /// The conversation under `messages`, the historical input of every Replicate model.
+    fn default() -> InputSchema {
+        InputSchema {
+            messages: Some("messages".to_string()),
+            prompt: None,
+            system_prompt: None,
+            image_list: false,
+        }
+    }
+}
+
+impl InputSchema {
+    /// Schema of a model from the `Input` properties of its Replicate OpenAPI schema
+    /// (`latest_version.openapi_schema.components.schemas.Input.properties`).
+    pub fn from_openapi(
+        properties: &Value,
+        image_parameter: Option<&str>,
+    ) -> Result<InputSchema, String> {
+        let properties = properties
+            .as_object()
+            .ok_or("the OpenAPI schema has no input properties".to_string())?;
+        let named = |name: &str| properties.contains_key(name).then(|| name.to_string());
+        let schema = InputSchema {
+            messages: named("messages"),
+            prompt: named("prompt"),
+            system_prompt: named("system_prompt"),
+            image_list: image_parameter
+                .and_then(|name| properties.get(name))
+                .is_some_and(|property| property["type"] == "array"),
+        };
+        if schema.messages.is_none() && schema.prompt.is_none() {
+            return Err("the model takes neither messages nor a prompt".to_string());
+        }
+        Ok(schema)
+    }
+
+    /// Replicate `input` of `model` for `messages`.
+    pub fn build_input(
+        &self,
+        model: &Model,
+        messages: &[Message],
+    ) -> Result<Map<String, Value>, String> {
+        let mut input = Map::new();
+        let image_parameter = model.get_image_parameters();
+        let images: Vec<&str> = messages
+            .iter()
+            .filter_map(|message| message.input_image.as_deref())
+            .collect();
+
+        match (&self.messages, &self.prompt) {
+            (Some(name), _) => {
+                // images go to their own input when the model has one
+                let messages: Vec<Message> = messages
+                    .iter()
+                    .map(|message| Message {
+                        input_image: message
+                            .input_image
+                            .clone()
+                            .filter(|_| image_parameter.is_none()),
+                        ..message.clone()
+                    })
+                    .collect();
+                input.insert(
+                    name.clone(),
+                    json!(codec::encode_turns(&messages, Dialect::Replicate)?),
+                );
+            }
+            (None, Some(name)) => {
+                let unsupported = messages.iter().find_map(|message| {
+                    match (&message.input_audio, &message.input_video) {
+                        (Some(_), _) => Some("audio"),
+                        (_, Some(_)) => Some("video"),
+                        _ => None,
+                    }
+                });
+                if let Some(kind) = unsupported {
+                    return Err(format!(
+                        "Model '{}' takes no {} input",
+                        model.get_name(),
+                        kind
+                    ));
+                }
+                let is_system =
+                    |message: &&Message| matches!(message.role.as_str(), "system" | "developer");
+                let system: Vec<&str> = messages
+                    .iter()
+                    .filter(is_system)
+                    .map(|message| message.input_text.as_str())
+                    .collect();
+                let turns: Vec<&Message> = messages.iter().filter(|m| !is_system(m)).collect();
+                let mut prompt = prompt_text(&turns);
+                match (&self.system_prompt, system.is_empty()) {
+                    (_, true) => {}
+                    (Some(system_name), false) => {
+                        input.insert(system_name.clone(), json!(system.join("\n\n")));
+                    }
+                    (None, false) => prompt = format!("{}\n\n{}", system.join("\n\n"), prompt),
+                }
+                input.insert(name.clone(), json!(prompt));
+            }
+            (None, None) => {
+                return Err(format!(
+                    "Model '{}' takes neither messages nor a prompt",
+                    model.get_name()
+                ))
+            }
+        }
+
+        if let (Some(parameter), false) = (image_parameter, images.is_empty()) {
+            if self.image_list {
+                input.insert(parameter.to_string(), json!(images));
+            } else if let [image] = images[..] {
+                input.insert(parameter.to_string(), json!(image));
+            } else {
+                return Err(format!(
+                    "Model '{}' takes a single image, got {}",
+                    model.get_name(),
+                    images.len()
+                ));
+            }
+        } else if image_parameter.is_none() && self.messages.is_none() && !images.is_empty() {
+            return Err(format!("Model '{}' takes no image input", model.get_name()));
+        }
+        Ok(input)
+    }
+}
+
+/// Prompt of the conversation `turns`: the text of a single turn, or a transcript
+/// with the role of each turn.
+fn prompt_text(turns: &[&Message]) -> String {
+    match turns {
+        [turn] => turn.input_text.clone(),
+        _ => turns
+            .iter()
+            .map(|turn| {
+                let mut role = turn.role.clone();
+                if let Some(first) = role.get_mut(..1) {
+                    first.make_ascii_uppercase();
+                }
+                format!("{}: {}", role, turn.input_text)
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+    }
+}
+
+#[cfg(test)]
+mod input_schema_tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn model(name: &str) -> Model {
+        Model::fill()
+            .into_iter()
+            .find(|model| model.get_name() == name)
+            .unwrap()
+    }
+
+    fn message(role: &str, text: &str, image: Option<&str>) -> Message {
+        Message {
+            role: role.to_string(),
+            input_text: text.to_string(),
+            input_image: image.map(str::to_string),
+            input_audio: None,
+            input_audio_format: None,
+            input_video: None,
+            input_file: None,
+        }
+    }
+
+    #[test]
+    fn test_prompt_schema() {
+        let start = Instant::now();
+
+        let claude = model("claude-4-sonnet");
+        let schema = claude.get_input_schema().unwrap();
+        let messages = vec![
+            message("system", "Be brief.", None),
+            message("user", "What is this?", Some("https://example.com/cat.png")),
+        ];
+        assert_eq!(
+            Value::Object(schema.build_input(&claude, &messages).unwrap()),
+            json!({
+                "system_prompt": "Be brief.",
+                "prompt": "What is this?",
+                "image": "https://example.com/cat.png"
+            })
+        );
+
+        let conversation = vec![
+            message("user", "Hi", None),
+            message("assistant", "Hello!", Some("a")),
+            message("user", "And this?", Some("b")),
+        ];
+        assert_eq!(
+            schema.build_input(&claude, &conversation),
+            Err("Model 'claude-4-sonnet' takes a single image, got 2".to_string())
+        );
+        assert_eq!(
+            prompt_text(&conversation.iter().collect::<Vec<_>>()),
+            "User: Hi\n\nAssistant: Hello!\n\nUser: And this?"
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_prompt_schema took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_messages_schema_and_openapi() {
+        let start = Instant::now();
+
+        let gpt = model("gpt-4o");
+        let schema = gpt.get_input_schema().unwrap();
+        let messages = vec![message(
+            "user",
+            "What is this?",
+            Some("https://example.com/cat.png"),
+        )];
+        let input = schema.build_input(&gpt, &messages).unwrap();
+        assert_eq!(input["image_input"], json!(["https://example.com/cat.png"]));
+        assert_eq!(input["messages"][0]["content"].as_array().unwrap().len(), 1);
+
+        let properties = json!({
+            "prompt": { "type": "string" },
+            "system_prompt": { "type": "string" },
+            "image": { "type": "string", "format": "uri" }
+        });
+        assert_eq!(
+            InputSchema::from_openapi(&properties, Some("image")),
+            Ok(InputSchema {
+                messages: None,
+                prompt: Some("prompt".to_string()),
+                system_prompt: Some("system_prompt".to_string()),
+                image_list: false,
+            })
+        );
+        assert!(InputSchema::from_openapi(&json!({ "seed": {} }), None).is_err());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_messages_schema_and_openapi took: {:?}", duration);
+    }
+}
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Maximum number of alternatives per token (same limit as OpenAI).
+pub const MAX_TOP_LOGPROBS: u32 = 20;
+
+/// Log probability of one generated token, as in the OpenAI `logprobs.content` list.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TokenLogprob {
+    /// The token.
+    pub token: String,
+
+    /// Natural logarithm of its probability.
+    pub logprob: f64,
+
+    /// UTF-8 bytes of the token, when it is only part of a character.
+    pub bytes: Option<Vec<u8>>,
+
+    /// Most likely tokens at this position, when requested with `top_logprobs`.
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// One of the most likely tokens at a position.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TopLogprob {
+    /// The token.
+    pub token: String,
+
+    /// Natural logarithm of its probability.
+    pub logprob: f64,
+
+    /// UTF-8 bytes of the token.
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// Token log probabilities of an OpenAI-like choice, `None` when the upstream
+/// returned none.
+pub fn parse_choice(choice: &Value) -> Option<Vec<TokenLogprob>> {
+    let content = choice["logprobs"]["content"].as_array()?;
+    content
+        .iter()
+        .map(|token| serde_json::from_value(token.clone()).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod logprobs_tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Instant;
+
+    #[test]
+    fn test_parse_choice() {
+        let start = Instant::now();
+
+        let choice = json!({ "logprobs": { "content": [{
+            "token": "Yes",
+            "logprob": -0.01,
+            "bytes": [89, 101, 115],
+            "top_logprobs": [
+                { "token": "Yes", "logprob": -0.01, "bytes": [89, 101, 115] },
+                { "token": "No", "logprob": -4.6, "bytes": null }
+            ]
+        }]}});
+        let logprobs = parse_choice(&choice).unwrap();
+        assert_eq!(logprobs.len(), 1);
+        assert_eq!(logprobs[0].token, "Yes");
+        assert_eq!(logprobs[0].top_logprobs[1].logprob, -4.6);
+        assert_eq!(
+            serde_json::to_value(&logprobs).unwrap(),
+            choice["logprobs"]["content"]
+        );
+
+        assert_eq!(parse_choice(&json!({ "logprobs": null })), None);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_parse_choice took: {:?}", duration);
+    }
+}
@@ -0,0 +1,311 @@
+use ::image::codecs::jpeg::JpegEncoder;
+use ::image::imageops::FilterType;
+use ::image::metadata::Orientation;
+use ::image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use base64::prelude::*;
+use std::io::Cursor;
+
+use super::http::{self, HttpConfig};
+use super::model::Model;
+
+/// Longest side, in pixels, of images sent to a model that doesn't define one.
+pub const DEFAULT_MAX_DIMENSION: u32 = 2048;
+
+/// Size, in bytes, of images sent to a model that doesn't define one.
+pub const DEFAULT_MAX_BYTES: u64 = 20 * 1024 * 1024;
+
+/// Size, in bytes, above which an attachment is rejected before being decoded.
+pub const MAX_SOURCE_BYTES: usize = 50 * 1024 * 1024;
+
+/// Media types accepted by a model that doesn't define them.
+const DEFAULT_FORMATS: [&str; 4] = ["image/jpeg", "image/png", "image/webp", "image/gif"];
+
+/// Quality of the JPEG images produced.
+const JPEG_QUALITY: u8 = 85;
+
+/// Downscales tried, by three quarters each, to fit an image in the size limit.
+const MAX_SHRINKS: usize = 6;
+
+/// What a model accepts as an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageLimits {
+    /// Longest side, in pixels.
+    pub max_dimension: u32,
+
+    /// Size of the encoded image, in bytes.
+    pub max_bytes: u64,
+
+    /// Accepted media types (e.g. "image/png").
+    pub formats: Vec<String>,
+}
+
+impl ImageLimits {
+    /// Limits of `model`, the defaults filling the ones it doesn't define.
+    pub fn of(model: &Model) -> ImageLimits {
+        ImageLimits {
+            max_dimension: model
+                .get_max_image_dimension()
+                .unwrap_or(DEFAULT_MAX_DIMENSION),
+            max_bytes: model.get_max_image_bytes().unwrap_or(DEFAULT_MAX_BYTES),
+            formats: model
+                .get_image_formats()
+                .map(|formats| formats.to_vec())
+                .unwrap_or_else(|| DEFAULT_FORMATS.iter().map(|f| f.to_string()).collect()),
+        }
+    }
+
+    fn accepts(&self, format: ImageFormat) -> bool {
+        self.formats
+            .iter()
+            .any(|accepted| accepted == format.to_mime_type())
+    }
+}
+
+/// Bytes of the attachment at `url`: a base64 `data:` URL or an http(s) URL of a
+/// public host.
+pub async fn load(config: &HttpConfig, url: &str) -> Result<Vec<u8>, String> {
+    if url.starts_with("data:") {
+        return decode_data_url(url);
+    }
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err("attachments must be http(s) or base64 data URLs".to_string());
+    }
+    http::download_public(config, url, MAX_SOURCE_BYTES).await
+}
+
+/// Bytes of a base64 `data:` URL.
+pub fn decode_data_url(url: &str) -> Result<Vec<u8>, String> {
+    let (_, data) = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+        .ok_or("Only base64 data URLs are supported".to_string())?;
+    // the decoded size is three quarters of the encoded one
+    if data.len() / 4 * 3 > MAX_SOURCE_BYTES {
+        return Err(format!(
+            "the attachment is larger than {} bytes",
+            MAX_SOURCE_BYTES
+        ));
+    }
+    BASE64_STANDARD
+        .decode(data.trim())
+        .map_err(|e| format!("invalid base64 data: {}", e))
+}
+
+/// Image `bytes` within `limits`, as a `data:` URL.
+///
+/// Images the model accepts as they are, without EXIF metadata, are passed through
+/// unchanged. Others are turned upright and re-encoded, which drops their EXIF
+/// metadata (camera, location...): PNG sources and transparent images stay lossless
+/// (PNG, else WebP) when the model accepts it, everything else becomes JPEG, or a
+/// lossless format for models without JPEG. Images over the size limit are downscaled
+/// until they fit.
+pub fn prepare(bytes: &[u8], limits: &ImageLimits) -> Result<String, String> {
+    if bytes.len() > MAX_SOURCE_BYTES {
+        return Err(format!(
+            "the image is larger than {} bytes",
+            MAX_SOURCE_BYTES
+        ));
+    }
+    let source_format =
+        ::image::guess_format(bytes).map_err(|_| "unrecognized image format".to_string())?;
+    let mut decoder = ImageReader::with_format(Cursor::new(bytes), source_format)
+        .into_decoder()
+        .map_err(|e| format!("invalid image: {}", e))?;
+    let has_exif = decoder.exif_metadata().ok().flatten().is_some();
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let (width, height) = decoder.dimensions();
+    if !has_exif
+        && orientation == Orientation::NoTransforms
+        && limits.accepts(source_format)
+        && width.max(height) <= limits.max_dimension
+        && bytes.len() as u64 <= limits.max_bytes
+    {
+        return Ok(format!(
+            "data:{};base64,{}",
+            source_format.to_mime_type(),
+            BASE64_STANDARD.encode(bytes)
+        ));
+    }
+    let mut image =
+        DynamicImage::from_decoder(decoder).map_err(|e| format!("invalid image: {}", e))?;
+    image.apply_orientation(orientation);
+
+    if image.width().max(image.height()) > limits.max_dimension {
+        image = image.resize(
+            limits.max_dimension,
+            limits.max_dimension,
+            FilterType::Lanczos3,
+        );
+    }
+
+    let lossless = source_format == ImageFormat::Png || image.color().has_alpha();
+    let preferred: &[ImageFormat] = match lossless {
+        true => &[ImageFormat::Png, ImageFormat::WebP, ImageFormat::Jpeg],
+        false => &[ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::WebP],
+    };
+    let mut format = preferred
+        .iter()
+        .copied()
+        .find(|format| limits.accepts(*format))
+        .ok_or_else(|| {
+            format!(
+                "the model accepts none of the image formats we produce ({})",
+                limits.formats.join(", ")
+            )
+        })?;
+
+    let mut encoded = encode(&image, format)?;
+    for _ in 0..MAX_SHRINKS {
+        if encoded.len() as u64 <= limits.max_bytes {
+            break;
+        }
+        // lossy compression is the first saving, then resolution
+        if limits.accepts(ImageFormat::Jpeg) {
+            format = ImageFormat::Jpeg;
+        }
+        let (width, height) = (image.width() * 3 / 4, image.height() * 3 / 4);
+        image = image.resize(width.max(1), height.max(1), FilterType::Lanczos3);
+        encoded = encode(&image, format)?;
+    }
+    if encoded.len() as u64 > limits.max_bytes {
+        return Err(format!(
+            "the image doesn't fit in {} bytes",
+            limits.max_bytes
+        ));
+    }
+    Ok(format!(
+        "data:{};base64,{}",
+        format.to_mime_type(),
+        BASE64_STANDARD.encode(encoded)
+    ))
+}
+
+/// `image` encoded as PNG, JPEG or (lossless) WebP.
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, String> {
+    let mut encoded = Cursor::new(vec![]);
+    let result = match format {
+        // JPEG has no alpha channel
+        ImageFormat::Jpeg => {
+            JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY).encode_image(&image.to_rgb8())
+        }
+        // the WebP encoder only takes 8-bit RGBA
+        ImageFormat::WebP => {
+            DynamicImage::ImageRgba8(image.to_rgba8()).write_to(&mut encoded, format)
+        }
+        _ => image.write_to(&mut encoded, format),
+    };
+    result.map_err(|e| format!("failed to encode the image: {}", e))?;
+    Ok(encoded.into_inner())
+}
+
+#[cfg(test)]
+mod media_tests {
+    use super::*;
+    use ::image::{Rgb, RgbImage, Rgba, RgbaImage};
+    use std::time::Instant;
+
+    fn limits(max_dimension: u32, max_bytes: u64) -> ImageLimits {
+        ImageLimits {
+            max_dimension,
+            max_bytes,
+            formats: DEFAULT_FORMATS.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    fn encoded(image: DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(vec![]);
+        image.write_to(&mut bytes, format).unwrap();
+        bytes.into_inner()
+    }
+
+    fn decoded(data_url: &str) -> DynamicImage {
+        ::image::load_from_memory(&decode_data_url(data_url).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_prepare_downscales_and_converts() {
+        let start = Instant::now();
+
+        let photo = DynamicImage::ImageRgb8(RgbImage::from_fn(400, 200, |x, y| {
+            Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+        }));
+        let bmp = encoded(photo, ImageFormat::Bmp);
+        let prepared = prepare(&bmp, &limits(100, DEFAULT_MAX_BYTES)).unwrap();
+        assert!(prepared.starts_with("data:image/jpeg;base64,"));
+        let image = decoded(&prepared);
+        assert_eq!((image.width(), image.height()), (100, 50));
+
+        let logo = DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 0])));
+        let prepared = prepare(&encoded(logo, ImageFormat::Png), &limits(100, 1024)).unwrap();
+        assert!(prepared.starts_with("data:image/png;base64,"));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_prepare_downscales_and_converts took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_prepare_passes_fitting_images_through() {
+        let start = Instant::now();
+
+        let photo = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 32, |x, y| {
+            Rgb([(x * 4) as u8, (y * 8) as u8, 128])
+        }));
+        let jpeg = encoded(photo.clone(), ImageFormat::Jpeg);
+        let prepared = prepare(&jpeg, &limits(100, DEFAULT_MAX_BYTES)).unwrap();
+        assert_eq!(
+            prepared,
+            format!("data:image/jpeg;base64,{}", BASE64_STANDARD.encode(&jpeg))
+        );
+
+        // too large: re-encoded
+        let prepared = prepare(&jpeg, &limits(32, DEFAULT_MAX_BYTES)).unwrap();
+        assert_eq!(decoded(&prepared).width(), 32);
+
+        // no JPEG: converted to a lossless format instead of refused
+        let png_only = ImageLimits {
+            formats: vec!["image/png".to_string()],
+            ..limits(100, DEFAULT_MAX_BYTES)
+        };
+        let prepared = prepare(&encoded(photo.clone(), ImageFormat::Bmp), &png_only).unwrap();
+        assert!(prepared.starts_with("data:image/png;base64,"));
+        let webp_only = ImageLimits {
+            formats: vec!["image/webp".to_string()],
+            ..limits(100, DEFAULT_MAX_BYTES)
+        };
+        let prepared = prepare(&jpeg, &webp_only).unwrap();
+        assert!(prepared.starts_with("data:image/webp;base64,"));
+        assert_eq!(decoded(&prepared).width(), 64);
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_prepare_passes_fitting_images_through took: {:?}",
+            duration
+        );
+    }
+
+    #[test]
+    fn test_prepare_enforces_limits() {
+        let start = Instant::now();
+
+        let noise = DynamicImage::ImageRgb8(RgbImage::from_fn(256, 256, |x, y| {
+            Rgb([
+                ((x * 31) ^ (y * 17)) as u8,
+                (x * y) as u8,
+                (x + y * 7) as u8,
+            ])
+        }));
+        let png = encoded(noise, ImageFormat::Png);
+        let prepared = prepare(&png, &limits(256, 4096)).unwrap();
+        assert!(decode_data_url(&prepared).unwrap().len() <= 4096);
+        assert!(prepare(&png, &limits(256, 10)).is_err());
+
+        assert_eq!(
+            prepare(b"not an image", &limits(256, 4096)),
+            Err("unrecognized image format".to_string())
+        );
+        assert!(decode_data_url("data:image/png;base64,***").is_err());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_prepare_enforces_limits took: {:?}", duration);
+    }
+}
@@ -1,4 +1,8 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::input_schema::InputSchema;
 
 #[derive(Debug, Serialize, Deserialize)]
 /// Representation of an AI model entry in the catalog.
@@ -13,10 +17,12 @@ pub struct Model {
     /// Provider of the model (e.g. "Replicate").
     provider: Provider,
 
-    /// Name of the propeties for manipulating the level of "thinking".
+    /// Name of the propeties for manipulating the level of "thinking" (Replicate input;
+    /// OpenRouter models always take the `reasoning` object).
     thinking_level_property: Option<String>,
 
-    /// Type of thinking levels supported by the model.
+    /// Type of thinking levels supported by the model: "false"/"true" for a switch,
+    /// otherwise efforts ("low", "medium", "high"). See `ThinkingLevel::apply`.
     thinking_levels_authorized: Option<Vec<String>>,
 
     /// Optional characteristics of the model.
@@ -35,7 +41,8 @@ pub struct Model {
     /// Optional list of capabilities (e.g. ["chat", "completion"]).
     capability: Option<Vec<String>>,
 
-    /// Optional list of supported input modalities (e.g. ["text","audio"]).
+    /// Optional list of supported input modalities: "text", "image", "audio", "video"
+    /// and "file" (PDFs and other documents read natively).
     input_modality: Option<Vec<String>>,
 
     /// Optional list of supported output modalities (e.g. ["text","audio"]).
@@ -50,8 +57,25 @@ pub struct Model {
     /// the parameters to make for allowing the model to take a image as input.
     image_parameters: Option<String>,
 
+    /// Name of the input parameter receiving an audio file.
+    audio_parameters: Option<String>,
+
+    /// Input name of each generation parameter the model supports, by OpenAI name
+    /// (e.g. "max_tokens" -> "max_completion_tokens"). When `None`, OpenRouter models
+    /// take every parameter under its OpenAI name and Replicate models take none.
+    generation_parameters: Option<HashMap<String, String>>,
+
+    /// Inputs of a Replicate text model; fetched from its OpenAPI schema when `None`.
+    input_schema: Option<InputSchema>,
+
     /// Role that the model can accept
     roles_authorized: Option<Vec<String>>,
+
+    /// Maximum time in seconds to wait for a prediction of this model.
+    timeout_secs: Option<u64>,
+
+    /// Maximum backoff in milliseconds between two polls of a prediction of this model.
+    max_poll_backoff_ms: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -80,6 +104,15 @@ pub struct Characteristic {
 
     /// Optional maximum output length (tokens).
     max_output_length: Option<u32>,
+
+    /// Longest side, in pixels, of the images the model takes.
+    max_image_dimension: Option<u32>,
+
+    /// Size, in bytes, of the images the model takes.
+    max_image_bytes: Option<u64>,
+
+    /// Media types of the images the model takes (e.g. "image/png").
+    image_formats: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -126,7 +159,7 @@ impl Model {
                 provider: Provider::Replicate,
                 thinking_level_property: None,
                 thinking_levels_authorized: None,
-                characteristic: Some(Characteristic { size: None, parameter_count: None, context_window: Some(128_000), architecture: None, max_output_length: Some(16_384) }),
+                characteristic: Some(Characteristic { size: None, parameter_count: None, context_window: Some(128_000), architecture: None, max_output_length: Some(16_384), max_image_dimension: Some(2048), max_image_bytes: Some(20_000_000), image_formats: Some(vec!["image/jpeg".to_string(), "image/png".to_string(), "image/webp".to_string(), "image/gif".to_string()]) }),
                 price: Price::PerIoFlat { input_price: 2.50, output_price: 10.00 },
                 organization: Some("Open AI".to_string()),
                 licence: "Proprietary".to_string(),
@@ -136,7 +169,12 @@ impl Model {
                 description: Some("Versatile multi-modal assistant tailored for complex reasoning, long-context synthesis, and structured instruction execution.".to_string()),
                 apiurl: "https://api.replicate.com/v1/models/openai/gpt-4o/predictions".to_string(),
                 image_parameters: Some("image_input".to_string()),
+                audio_parameters: None,
+                generation_parameters: Some(HashMap::from([("temperature".to_string(), "temperature".to_string()), ("top_p".to_string(), "top_p".to_string()), ("max_tokens".to_string(), "max_completion_tokens".to_string()), ("presence_penalty".to_string(), "presence_penalty".to_string()), ("frequency_penalty".to_string(), "frequency_penalty".to_string())])),
+                input_schema: Some(InputSchema { messages: Some("messages".to_string()), prompt: Some("prompt".to_string()), system_prompt: Some("system_prompt".to_string()), image_list: true }),
                 roles_authorized: Some(vec!["user".to_string(), "assistant".to_string(), "developer".to_string(), "system".to_string()]),
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
             },
             Model {
                 name: "gpt-4o-mini".to_string(),
@@ -144,7 +182,7 @@ impl Model {
                 provider: Provider::Replicate,
                 thinking_level_property: None,
                 thinking_levels_authorized: None,
-               characteristic: Some(Characteristic { size: None, parameter_count: None, context_window: Some(128_000), architecture: None, max_output_length: Some(16_384) }),
+               characteristic: Some(Characteristic { size: None, parameter_count: None, context_window: Some(128_000), architecture: None, max_output_length: Some(16_384), max_image_dimension: Some(2048), max_image_bytes: Some(20_000_000), image_formats: Some(vec!["image/jpeg".to_string(), "image/png".to_string(), "image/webp".to_string(), "image/gif".to_string()]) }),
                 price: Price::PerIoFlat { input_price: 2.50, output_price: 10.00 },
                 organization: Some("Open AI".to_string()),
                 licence: "Proprietary".to_string(),
@@ -154,7 +192,12 @@ impl Model {
                 description: Some("Lightweight, latency-focused variant of GPT‑4o optimized for fast interactive edits, short-form coding tasks, and cost-sensitive deployments.".to_string()),
                 apiurl: "https://api.replicate.com/v1/models/openai/gpt-4o-mini/predictions".to_string(),
                 image_parameters: Some("image_input".to_string()),
+                audio_parameters: None,
+                generation_parameters: Some(HashMap::from([("temperature".to_string(), "temperature".to_string()), ("top_p".to_string(), "top_p".to_string()), ("max_tokens".to_string(), "max_completion_tokens".to_string()), ("presence_penalty".to_string(), "presence_penalty".to_string()), ("frequency_penalty".to_string(), "frequency_penalty".to_string())])),
+                input_schema: Some(InputSchema { messages: Some("messages".to_string()), prompt: Some("prompt".to_string()), system_prompt: Some("system_prompt".to_string()), image_list: true }),
                 roles_authorized: Some(vec!["user".to_string(), "assistant".to_string(), "developer".to_string(), "system".to_string()]),
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
             },
             Model {
                 name: "claude-4-sonnet".to_string(),
@@ -162,7 +205,7 @@ impl Model {
                 provider: Provider::Replicate,
                 thinking_level_property: Some("extended_thinking".to_string()),
                 thinking_levels_authorized: Some(vec!["false".to_string(), "true".to_string()]),
-                characteristic: Some(Characteristic { size: None, parameter_count: None, context_window: Some(200_000), architecture: None, max_output_length: Some(64_000) }),
+                characteristic: Some(Characteristic { size: None, parameter_count: None, context_window: Some(200_000), architecture: None, max_output_length: Some(64_000), max_image_dimension: Some(1568), max_image_bytes: Some(5_000_000), image_formats: Some(vec!["image/jpeg".to_string(), "image/png".to_string(), "image/webp".to_string(), "image/gif".to_string()]) }),
                 price: Price::PerIoFlat { input_price: 3.0, output_price: 15.0 },
                 organization: Some("Anthropic".to_string()),
                 licence: "Proprietary".to_string(),
@@ -172,7 +215,12 @@ impl Model {
                 description: Some("Anthropic's Sonnet: a careful conversationalist excelling at iterative refinement, summarization, and safety-conscious dialogue.".to_string()),
                 apiurl: "https://api.replicate.com/v1/models/anthropic/claude-4-sonnet/predictions".to_string(),
                 image_parameters: Some("image".to_string()),
+                audio_parameters: None,
+                generation_parameters: Some(HashMap::from([("max_tokens".to_string(), "max_tokens".to_string())])),
+                input_schema: Some(InputSchema { messages: None, prompt: Some("prompt".to_string()), system_prompt: Some("system_prompt".to_string()), image_list: false }),
                 roles_authorized: Some(vec!["user".to_string(), "assistant".to_string()]),
+                timeout_secs: Some(300),
+                max_poll_backoff_ms: None,
             },
             Model {
                 name: "gpt-5-codex".to_string(),
@@ -186,6 +234,9 @@ impl Model {
                     context_window: Some(400_000),
                     architecture: None,
                     max_output_length: Some(128_000),
+                    max_image_dimension: Some(2048),
+                    max_image_bytes: Some(20_000_000),
+                    image_formats: Some(vec!["image/jpeg".to_string(), "image/png".to_string(), "image/webp".to_string(), "image/gif".to_string()]),
                 }),
                 price: Price::PerIoFlat { input_price: 1.25, output_price: 10.0 },
                 organization: Some("Open AI".to_string()),
@@ -196,7 +247,12 @@ impl Model {
                 description: Some("High-throughput GPT‑5 variant engineered for code generation, multi-step algorithm design, and complex reasoning pipelines.".to_string()),
                 apiurl: "openai/gpt-5-codex".to_string(),
                 image_parameters: None,
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
                 roles_authorized: Some(vec!["user".to_string(), "system".to_string(), "assistant".to_string()]),
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
             },
             Model {
                 name: "grok-4".to_string(),
@@ -210,17 +266,25 @@ impl Model {
                     context_window: Some(256_000),
                     architecture: None,
                     max_output_length: Some(256_000),
+                    max_image_dimension: None,
+                    max_image_bytes: Some(20_000_000),
+                    image_formats: Some(vec!["image/jpeg".to_string(), "image/png".to_string()]),
                 }),
                 price: Price::PerIoWithTiers { input_tiers: vec![PriceTier { max_tokens: Some(128_000), price_per_million: 3.0 }, PriceTier { max_tokens: Some(256_000), price_per_million: 6.0 }], output_tiers: vec![PriceTier { max_tokens: Some(128_000), price_per_million: 15.0 }, PriceTier { max_tokens: Some(256_000), price_per_million: 30.0 }] },
                 organization: Some("xAI".to_string()),
                 licence: "Proprietary".to_string(),
-                capability: Some(vec!["generalist".to_string()]),
+                capability: Some(vec!["generalist".to_string(), "logprobs".to_string()]),
                 input_modality: Some(vec!["text".to_string(), "image".to_string()]),
                 output_modality: Some(vec!["text".to_string()]),
                 description: Some("xAI's Grok 4: pragmatic reasoning engine optimized for developer workflows, factual recall, and real-world problem solving.".to_string()),
                 apiurl: "x-ai/grok-4".to_string(),
                 image_parameters: None,
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
                 roles_authorized: Some(vec!["user".to_string(), "system".to_string(), "assistant".to_string()]),
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
             },
             /*Model {
                 name: "GPT 5 pro".to_string(),
@@ -255,6 +319,9 @@ impl Model {
                     context_window: Some(1_000_000),  // likely same as Sonnet 4 family :contentReference[oaicite:0]{index=0}
                     max_output_length: Some(64_000), // consistent with long-form generation regime :contentReference[oaicite:1]{index=1}
                     architecture: None,
+                    max_image_dimension: Some(1568),
+                    max_image_bytes: Some(5_000_000),
+                    image_formats: Some(vec!["image/jpeg".to_string(), "image/png".to_string(), "image/webp".to_string(), "image/gif".to_string()]),
                 }),
                 price: Price::PerIoFlat {
                     input_price: 3.00,   // $3 per million tokens → 0.003 per 1,000 tokens
@@ -263,12 +330,17 @@ impl Model {
                 organization: Some("Anthropic".to_string()),
                 licence: "Proprietary".to_string(),
                 capability: Some(vec!["generalist".to_string()]),
-                input_modality: Some(vec!["text".to_string(), "image".to_string()]),
+                input_modality: Some(vec!["text".to_string(), "image".to_string(), "file".to_string()]),
                 output_modality: Some(vec!["text".to_string()]),
                 description: Some("Claude Sonnet 4.5 — long-context specialist focused on sustained reasoning, autonomous task orchestration, and alignment-aware responses.".to_string()),
                 apiurl: "anthropic/claude-sonnet-4.5".to_string(),
                 image_parameters: None,
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
                 roles_authorized: Some(vec!["user".to_string(), "system".to_string(), "assistant".to_string()]),
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
             },
             Model {
                 name: "grok-4-fast".to_string(),
@@ -282,17 +354,25 @@ impl Model {
                     context_window: Some(2_000_000),
                     architecture: None,
                     max_output_length: Some(30_000),
+                    max_image_dimension: None,
+                    max_image_bytes: Some(20_000_000),
+                    image_formats: Some(vec!["image/jpeg".to_string(), "image/png".to_string()]),
                 }),
                 price: Price::PerIoWithTiers { input_tiers: vec![PriceTier { max_tokens: Some(128_000), price_per_million: 0.2 }, PriceTier { max_tokens: Some(256_000), price_per_million: 0.4 }], output_tiers: vec![PriceTier { max_tokens: Some(128_000), price_per_million: 0.5 }, PriceTier { max_tokens: Some(256_000), price_per_million: 1.0 }] },
                 organization: Some("xAI".to_string()),
                 licence: "Proprietary".to_string(),
-                capability: Some(vec!["generalist".to_string()]),
+                capability: Some(vec!["generalist".to_string(), "logprobs".to_string()]),
                 input_modality: Some(vec!["text".to_string(), "image".to_string()]),
                 output_modality: Some(vec!["text".to_string()]),
                 description: Some("Grok 4 Fast: ultra-low-latency flavor tuned for rapid interactive sessions, command-line workflows, and concise reasoning.".to_string()),
                 apiurl: "x-ai/grok-4-fast".to_string(),
                 image_parameters: None,
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
                 roles_authorized: Some(vec!["user".to_string(), "system".to_string(), "assistant".to_string()]),
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
             },
             Model {
                 name: "gemini-3-pro".to_string(),
@@ -306,19 +386,210 @@ impl Model {
                     context_window: Some(1_048_576),
                     architecture: None,
                     max_output_length: Some(65_536),
+                    max_image_dimension: None,
+                    max_image_bytes: Some(20_000_000),
+                    image_formats: Some(vec!["image/png".to_string(), "image/jpeg".to_string(), "image/webp".to_string()]),
                 }),
                 price: Price::PerIoWithTiers { input_tiers: vec![PriceTier { max_tokens: Some(200_000), price_per_million: 2.0 }, PriceTier { max_tokens: Some(1_048_576), price_per_million: 4.0 }], output_tiers: vec![PriceTier { max_tokens: Some(200_000), price_per_million: 12.0 }, PriceTier { max_tokens: Some(1_048_576), price_per_million: 18.0 }], },
                 organization: Some("Google".to_string()),
                 licence: "Proprietary".to_string(),
                 capability: Some(vec!["generalist".to_string()]),
-                input_modality: Some(vec!["text".to_string(), "image".to_string(), "audio".to_string(), "video".to_string(), "PDF".to_string()]),
+                input_modality: Some(vec!["text".to_string(), "image".to_string(), "audio".to_string(), "video".to_string(), "file".to_string()]),
                 output_modality: Some(vec!["text".to_string()]),
                 description: Some("Gemini 3 Pro: a generalist model that excels at a wide range of tasks, from coding to creative writing, and is optimized for speed and efficiency.".to_string()),
                 apiurl: "google/gemini-3-pro-preview".to_string(),
                 image_parameters: None,
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
                 roles_authorized: Some(vec!["user".to_string(), "system".to_string(), "assistant".to_string()]),
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
+            },
+            Model {
+                name: "text-embedding-3-small".to_string(),
+                url: Some("https://openrouter.ai/openai/text-embedding-3-small".to_string()),
+                provider: Provider::OpenRouter,
+                thinking_level_property: None,
+                thinking_levels_authorized: None,
+                characteristic: Some(Characteristic { size: None, parameter_count: None, context_window: Some(8_191), architecture: None, max_output_length: Some(1_536), max_image_dimension: None, max_image_bytes: None, image_formats: None }),
+                price: Price::PerIoFlat { input_price: 0.02, output_price: 0.0 },
+                organization: Some("Open AI".to_string()),
+                licence: "Proprietary".to_string(),
+                capability: Some(vec!["embedding".to_string()]),
+                input_modality: Some(vec!["text".to_string()]),
+                output_modality: Some(vec!["embedding".to_string()]),
+                description: Some("Compact embedding model for retrieval and clustering; vectors can be shortened with `dimensions`.".to_string()),
+                apiurl: "openai/text-embedding-3-small".to_string(),
+                image_parameters: None,
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
+                roles_authorized: None,
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
+            },
+            Model {
+                name: "text-embedding-3-large".to_string(),
+                url: Some("https://openrouter.ai/openai/text-embedding-3-large".to_string()),
+                provider: Provider::OpenRouter,
+                thinking_level_property: None,
+                thinking_levels_authorized: None,
+                characteristic: Some(Characteristic { size: None, parameter_count: None, context_window: Some(8_191), architecture: None, max_output_length: Some(3_072), max_image_dimension: None, max_image_bytes: None, image_formats: None }),
+                price: Price::PerIoFlat { input_price: 0.13, output_price: 0.0 },
+                organization: Some("Open AI".to_string()),
+                licence: "Proprietary".to_string(),
+                capability: Some(vec!["embedding".to_string()]),
+                input_modality: Some(vec!["text".to_string()]),
+                output_modality: Some(vec!["embedding".to_string()]),
+                description: Some("Higher quality embedding model for retrieval-heavy workloads; vectors can be shortened with `dimensions`.".to_string()),
+                apiurl: "openai/text-embedding-3-large".to_string(),
+                image_parameters: None,
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
+                roles_authorized: None,
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
+            },
+            Model {
+                name: "flux-schnell".to_string(),
+                url: Some("https://replicate.com/black-forest-labs/flux-schnell".to_string()),
+                provider: Provider::Replicate,
+                thinking_level_property: None,
+                thinking_levels_authorized: None,
+                characteristic: None,
+                price: Price::PerRun { run_price: 0.003 },
+                organization: Some("Black Forest Labs".to_string()),
+                licence: "Proprietary".to_string(),
+                capability: Some(vec!["image_generation".to_string()]),
+                input_modality: Some(vec!["text".to_string()]),
+                output_modality: Some(vec!["image".to_string()]),
+                description: Some("FLUX.1 [schnell]: the fastest FLUX text-to-image model, suited to drafts and high volume generation.".to_string()),
+                apiurl: "https://api.replicate.com/v1/models/black-forest-labs/flux-schnell/predictions".to_string(),
+                image_parameters: None,
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
+                roles_authorized: None,
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
+            },
+            Model {
+                name: "flux-1.1-pro".to_string(),
+                url: Some("https://replicate.com/black-forest-labs/flux-1.1-pro".to_string()),
+                provider: Provider::Replicate,
+                thinking_level_property: None,
+                thinking_levels_authorized: None,
+                characteristic: None,
+                price: Price::PerRun { run_price: 0.04 },
+                organization: Some("Black Forest Labs".to_string()),
+                licence: "Proprietary".to_string(),
+                capability: Some(vec!["image_generation".to_string()]),
+                input_modality: Some(vec!["text".to_string()]),
+                output_modality: Some(vec!["image".to_string()]),
+                description: Some("FLUX1.1 [pro]: high quality text-to-image generation with strong prompt adherence.".to_string()),
+                apiurl: "https://api.replicate.com/v1/models/black-forest-labs/flux-1.1-pro/predictions".to_string(),
+                image_parameters: None,
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
+                roles_authorized: None,
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
+            },
+            Model {
+                name: "flux-kontext-pro".to_string(),
+                url: Some("https://replicate.com/black-forest-labs/flux-kontext-pro".to_string()),
+                provider: Provider::Replicate,
+                thinking_level_property: None,
+                thinking_levels_authorized: None,
+                characteristic: None,
+                price: Price::PerRun { run_price: 0.04 },
+                organization: Some("Black Forest Labs".to_string()),
+                licence: "Proprietary".to_string(),
+                capability: Some(vec!["image_editing".to_string()]),
+                input_modality: Some(vec!["text".to_string(), "image".to_string()]),
+                output_modality: Some(vec!["image".to_string()]),
+                description: Some("FLUX.1 Kontext [pro]: edits an input image from a text instruction while keeping its style and subjects.".to_string()),
+                apiurl: "https://api.replicate.com/v1/models/black-forest-labs/flux-kontext-pro/predictions".to_string(),
+                image_parameters: Some("input_image".to_string()),
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
+                roles_authorized: None,
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
+            },
+            Model {
+                name: "flux-fill-pro".to_string(),
+                url: Some("https://replicate.com/black-forest-labs/flux-fill-pro".to_string()),
+                provider: Provider::Replicate,
+                thinking_level_property: None,
+                thinking_levels_authorized: None,
+                characteristic: None,
+                price: Price::PerRun { run_price: 0.05 },
+                organization: Some("Black Forest Labs".to_string()),
+                licence: "Proprietary".to_string(),
+                capability: Some(vec!["image_editing".to_string(), "inpainting".to_string()]),
+                input_modality: Some(vec!["text".to_string(), "image".to_string()]),
+                output_modality: Some(vec!["image".to_string()]),
+                description: Some("FLUX.1 Fill [pro]: repaints the masked area of an image from a text prompt.".to_string()),
+                apiurl: "https://api.replicate.com/v1/models/black-forest-labs/flux-fill-pro/predictions".to_string(),
+                image_parameters: Some("image".to_string()),
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
+                roles_authorized: None,
+                timeout_secs: None,
+                max_poll_backoff_ms: None,
+            },
+            Model {
+                name: "gpt-4o-transcribe".to_string(),
+                url: Some("https://replicate.com/openai/gpt-4o-transcribe".to_string()),
+                provider: Provider::Replicate,
+                thinking_level_property: None,
+                thinking_levels_authorized: None,
+                characteristic: None,
+                price: Price::PerIoFlat { input_price: 2.50, output_price: 10.00 },
+                organization: Some("Open AI".to_string()),
+                licence: "Proprietary".to_string(),
+                capability: Some(vec!["transcription".to_string()]),
+                input_modality: Some(vec!["audio".to_string()]),
+                output_modality: Some(vec!["text".to_string()]),
+                description: Some("Whisper-style speech-to-text built on GPT-4o, with better word error rates than Whisper on noisy recordings.".to_string()),
+                apiurl: "https://api.replicate.com/v1/models/openai/gpt-4o-transcribe/predictions".to_string(),
+                image_parameters: None,
+                audio_parameters: Some("audio_file".to_string()),
+                generation_parameters: None,
+                input_schema: None,
+                roles_authorized: None,
+                timeout_secs: Some(600),
+                max_poll_backoff_ms: None,
+            },
+            Model {
+                name: "speech-02-turbo".to_string(),
+                url: Some("https://replicate.com/minimax/speech-02-turbo".to_string()),
+                provider: Provider::Replicate,
+                thinking_level_property: None,
+                thinking_levels_authorized: None,
+                characteristic: None,
+                price: Price::PerIoFlat { input_price: 60.00, output_price: 0.0 },
+                organization: Some("MiniMax".to_string()),
+                licence: "Proprietary".to_string(),
+                capability: Some(vec!["speech".to_string()]),
+                input_modality: Some(vec!["text".to_string()]),
+                output_modality: Some(vec!["audio".to_string()]),
+                description: Some("Low-latency text-to-speech with natural prosody, suited to real-time voice applications.".to_string()),
+                apiurl: "https://api.replicate.com/v1/models/minimax/speech-02-turbo/predictions".to_string(),
+                image_parameters: None,
+                audio_parameters: None,
+                generation_parameters: None,
+                input_schema: None,
+                roles_authorized: None,
+                timeout_secs: Some(600),
+                max_poll_backoff_ms: None,
             },
-
 
         ]
     }
@@ -348,6 +619,44 @@ impl Model {
         self.image_parameters.as_deref()
     }
 
+    /// getter for the audio parameters of a model
+    pub fn get_audio_parameters(&self) -> Option<&str> {
+        self.audio_parameters.as_deref()
+    }
+
+    /// getter for the longest side, in pixels, of the images a model takes
+    pub fn get_max_image_dimension(&self) -> Option<u32> {
+        self.characteristic.as_ref()?.max_image_dimension
+    }
+
+    /// getter for the size, in bytes, of the images a model takes
+    pub fn get_max_image_bytes(&self) -> Option<u64> {
+        self.characteristic.as_ref()?.max_image_bytes
+    }
+
+    /// getter for the media types of the images a model takes
+    pub fn get_image_formats(&self) -> Option<&[String]> {
+        self.characteristic.as_ref()?.image_formats.as_deref()
+    }
+
+    /// getter for the Replicate input schema of a model
+    pub fn get_input_schema(&self) -> Option<&InputSchema> {
+        self.input_schema.as_ref()
+    }
+
+    /// Input name of the generation parameter `name` (an OpenAI name), `None` when the
+    /// model doesn't support it.
+    pub fn get_generation_parameter<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        if matches!(name, "logprobs" | "top_logprobs") && !self.has_capability("logprobs") {
+            return None;
+        }
+        match (&self.generation_parameters, &self.provider) {
+            (Some(parameters), _) => parameters.get(name).map(String::as_str),
+            (None, Provider::OpenRouter) => Some(name),
+            (None, Provider::Replicate) => None,
+        }
+    }
+
     /// getter for the role authorized of a model
     pub fn get_roles_authorized(&self) -> Option<&Vec<String>> {
         self.roles_authorized.as_ref()
@@ -357,6 +666,59 @@ impl Model {
     pub fn get_name(&self) -> &str {
         &self.name
     }
+
+    /// Whether the model lists `capability` (e.g. "embedding").
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capability
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.iter().any(|c| c == capability))
+    }
+
+    /// Whether the model chats: it answers with text, and isn't a speech-to-text or
+    /// text-to-speech model (only reached through transcribe and speak).
+    pub fn is_chat_model(&self) -> bool {
+        self.has_output_modality("text")
+            && !self.has_capability("transcription")
+            && !self.has_capability("speech")
+    }
+
+    /// Whether the model accepts `modality` (e.g. "image") as input.
+    pub fn has_input_modality(&self, modality: &str) -> bool {
+        self.input_modality
+            .as_ref()
+            .is_some_and(|modalities| modalities.iter().any(|m| m == modality))
+    }
+
+    /// Whether the model produces `modality` (e.g. "image"); models without
+    /// `output_modality` are assumed to produce text only.
+    pub fn has_output_modality(&self, modality: &str) -> bool {
+        match &self.output_modality {
+            Some(modalities) => modalities.iter().any(|m| m == modality),
+            None => modality == "text",
+        }
+    }
+
+    /// Length of the vectors of an embedding model (its maximum output length).
+    pub fn get_embedding_dimensions(&self) -> Option<u32> {
+        self.characteristic
+            .as_ref()
+            .and_then(|characteristic| characteristic.max_output_length)
+    }
+
+    /// getter for the price of a model
+    pub fn get_price(&self) -> &Price {
+        &self.price
+    }
+
+    /// getter for the prediction timeout of a model
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(Duration::from_secs)
+    }
+
+    /// getter for the maximum poll backoff of a model
+    pub fn get_max_poll_backoff(&self) -> Option<Duration> {
+        self.max_poll_backoff_ms.map(Duration::from_millis)
+    }
 }
 
 impl Price {
@@ -0,0 +1,291 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+use super::logprobs::MAX_TOP_LOGPROBS;
+use super::model::Model;
+
+/// Maximum number of stop sequences (same limit as OpenAI).
+pub const MAX_STOP_SEQUENCES: usize = 4;
+
+/// Sampling parameters of a run, under their OpenAI names.
+///
+/// Each model maps them to its own input names; a parameter the model doesn't
+/// support makes the run fail rather than being silently dropped.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct GenerationParams {
+    /// Sampling temperature, between 0 and 2.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+
+    /// Nucleus sampling probability mass, between 0 and 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+
+    /// Maximum number of tokens to generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+
+    /// Sequences where the generation stops, at most `MAX_STOP_SEQUENCES`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+
+    /// Seed for best-effort deterministic sampling.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+
+    /// Penalty on tokens already present, between -2 and 2.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+
+    /// Penalty proportional to token frequency, between -2 and 2.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+
+    /// Bias added to the logits of token ids, between -100 and 100.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<BTreeMap<String, f64>>,
+
+    /// Return the log probability of each generated token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+
+    /// Alternatives returned per token, at most `MAX_TOP_LOGPROBS` (requires `logprobs`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
+}
+
+impl GenerationParams {
+    /// Whether no parameter is set.
+    pub fn is_empty(&self) -> bool {
+        *self == GenerationParams::default()
+    }
+
+    /// Check every parameter is within its range.
+    pub fn validate(&self) -> Result<(), String> {
+        let in_range = |name: &str, value: Option<f64>, min: f64, max: f64| match value {
+            Some(v) if !(min..=max).contains(&v) => Err(format!(
+                "{} must be between {} and {}, got {}",
+                name, min, max, v
+            )),
+            _ => Ok(()),
+        };
+        in_range("temperature", self.temperature, 0.0, 2.0)?;
+        in_range("top_p", self.top_p, 0.0, 1.0)?;
+        in_range("presence_penalty", self.presence_penalty, -2.0, 2.0)?;
+        in_range("frequency_penalty", self.frequency_penalty, -2.0, 2.0)?;
+        if self.max_tokens == Some(0) {
+            return Err("max_tokens must be a positive integer".to_string());
+        }
+        if let Some(stop) = &self.stop {
+            if stop.len() > MAX_STOP_SEQUENCES {
+                return Err(format!(
+                    "at most {} stop sequences are supported",
+                    MAX_STOP_SEQUENCES
+                ));
+            }
+        }
+        for bias in self.logit_bias.iter().flat_map(|biases| biases.values()) {
+            in_range("logit_bias", Some(*bias), -100.0, 100.0)?;
+        }
+        if let Some(top_logprobs) = self.top_logprobs {
+            if top_logprobs > MAX_TOP_LOGPROBS {
+                return Err(format!(
+                    "top_logprobs must be between 0 and {}, got {}",
+                    MAX_TOP_LOGPROBS, top_logprobs
+                ));
+            }
+            if self.logprobs != Some(true) {
+                return Err("top_logprobs requires logprobs to be true".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Set parameters as (OpenAI name, value) pairs.
+    fn values(&self) -> Vec<(&'static str, Value)> {
+        let mut values = vec![];
+        let mut push = |name, value: Option<Value>| {
+            if let Some(value) = value {
+                values.push((name, value));
+            }
+        };
+        push("temperature", self.temperature.map(|v| json!(v)));
+        push("top_p", self.top_p.map(|v| json!(v)));
+        push("max_tokens", self.max_tokens.map(|v| json!(v)));
+        push("stop", self.stop.as_ref().map(|v| json!(v)));
+        push("seed", self.seed.map(|v| json!(v)));
+        push("presence_penalty", self.presence_penalty.map(|v| json!(v)));
+        push(
+            "frequency_penalty",
+            self.frequency_penalty.map(|v| json!(v)),
+        );
+        push("logit_bias", self.logit_bias.as_ref().map(|v| json!(v)));
+        // `logprobs: false` is the default of every model, no need to support it
+        push("logprobs", self.logprobs.filter(|v| *v).map(|v| json!(v)));
+        push("top_logprobs", self.top_logprobs.map(|v| json!(v)));
+        values
+    }
+
+    /// Insert the parameters into `target` (an OpenRouter body or a Replicate input)
+    /// under the names `model` expects.
+    ///
+    /// `stop` is left out for models without native support: the caller cuts the output.
+    pub fn apply(&self, model: &Model, target: &mut Map<String, Value>) -> Result<(), String> {
+        self.validate()?;
+        let mut unsupported = vec![];
+        for (name, value) in self.values() {
+            match model.get_generation_parameter(name) {
+                Some(native) => {
+                    target.insert(native.to_string(), value);
+                }
+                // emulated on the output, see `cut_at_stop`
+                None if name == "stop" => {}
+                None => unsupported.push(name),
+            }
+        }
+        if !unsupported.is_empty() {
+            return Err(format!(
+                "Parameters not supported by model '{}': {}",
+                model.get_name(),
+                unsupported.join(", ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `text` cut before the earliest of `stop` sequences, with the sequence that matched.
+///
+/// Used to emulate `stop` on models that don't support it.
+pub fn cut_at_stop<'a, S: AsRef<str>>(text: &'a str, stop: &'a [S]) -> (&'a str, Option<&'a str>) {
+    stop.iter()
+        .map(AsRef::as_ref)
+        .filter(|sequence| !sequence.is_empty())
+        .filter_map(|sequence| Some((text.find(sequence)?, sequence)))
+        .min_by_key(|(at, _)| *at)
+        .map(|(at, sequence)| (&text[..at], Some(sequence)))
+        .unwrap_or((text, None))
+}
+
+#[cfg(test)]
+mod params_tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn model(name: &str) -> Model {
+        Model::fill()
+            .into_iter()
+            .find(|model| model.get_name() == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_apply_uses_native_names() {
+        let start = Instant::now();
+
+        let params = GenerationParams {
+            temperature: Some(0.2),
+            max_tokens: Some(100),
+            ..GenerationParams::default()
+        };
+
+        let mut openrouter = Map::new();
+        params.apply(&model("grok-4"), &mut openrouter).unwrap();
+        assert_eq!(
+            Value::Object(openrouter),
+            json!({ "temperature": 0.2, "max_tokens": 100 })
+        );
+
+        let mut replicate = Map::new();
+        params.apply(&model("gpt-4o"), &mut replicate).unwrap();
+        assert_eq!(
+            Value::Object(replicate),
+            json!({ "temperature": 0.2, "max_completion_tokens": 100 })
+        );
+
+        let seeded = GenerationParams {
+            seed: Some(7),
+            ..params
+        };
+        assert_eq!(
+            seeded.apply(&model("claude-4-sonnet"), &mut Map::new()),
+            Err(
+                "Parameters not supported by model 'claude-4-sonnet': temperature, seed"
+                    .to_string()
+            )
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_apply_uses_native_names took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_logprobs_need_the_capability() {
+        let start = Instant::now();
+
+        let params = GenerationParams {
+            logprobs: Some(true),
+            top_logprobs: Some(5),
+            ..GenerationParams::default()
+        };
+        let mut body = Map::new();
+        params.apply(&model("grok-4-fast"), &mut body).unwrap();
+        assert_eq!(
+            Value::Object(body),
+            json!({ "logprobs": true, "top_logprobs": 5 })
+        );
+        assert_eq!(
+            params.apply(&model("gpt-5-codex"), &mut Map::new()),
+            Err(
+                "Parameters not supported by model 'gpt-5-codex': logprobs, top_logprobs"
+                    .to_string()
+            )
+        );
+        let without_logprobs = GenerationParams {
+            logprobs: Some(false),
+            ..params
+        };
+        assert!(without_logprobs.validate().is_err());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_logprobs_need_the_capability took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_cut_at_stop() {
+        let start = Instant::now();
+
+        assert_eq!(
+            cut_at_stop("one, two. three", &["three", "."]),
+            ("one, two", Some("."))
+        );
+        assert_eq!(cut_at_stop("one", &["two".to_string()]), ("one", None));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_cut_at_stop took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_validate_ranges() {
+        let start = Instant::now();
+
+        assert!(GenerationParams::default().validate().is_ok());
+        let too_hot = GenerationParams {
+            temperature: Some(3.0),
+            ..GenerationParams::default()
+        };
+        assert_eq!(
+            too_hot.validate(),
+            Err("temperature must be between 0 and 2, got 3".to_string())
+        );
+        let too_many_stops = GenerationParams {
+            stop: Some(vec!["a".to_string(); 5]),
+            ..GenerationParams::default()
+        };
+        assert!(too_many_stops.validate().is_err());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_validate_ranges took: {:?}", duration);
+    }
+}
@@ -0,0 +1,76 @@
+use serde_json::Value;
+
+/// Reasoning of an OpenAI-like (OpenRouter) message, `None` when the model returned none.
+///
+/// OpenRouter sends the plain `reasoning` text and the structured `reasoning_details`;
+/// the details are only read when the text is missing. Encrypted details can't be
+/// shown and are skipped.
+pub fn from_openrouter(message: &Value) -> Option<String> {
+    if let Some(reasoning) = message["reasoning"].as_str().filter(|r| !r.is_empty()) {
+        return Some(reasoning.to_string());
+    }
+    let texts: Vec<&str> = message["reasoning_details"]
+        .as_array()?
+        .iter()
+        .filter_map(|detail| match detail["type"].as_str() {
+            Some("reasoning.text") => detail["text"].as_str(),
+            Some("reasoning.summary") => detail["summary"].as_str(),
+            _ => None,
+        })
+        .collect();
+    if texts.is_empty() {
+        None
+    } else {
+        Some(texts.join("\n"))
+    }
+}
+
+/// Answer and thinking of a text where the model thinks between `<think>` tags first,
+/// as Replicate thinking models do.
+pub fn split_thinking(text: String) -> (String, Option<String>) {
+    let trimmed = text.trim_start();
+    let Some(rest) = trimmed.strip_prefix("<think>") else {
+        return (text, None);
+    };
+    match rest.split_once("</think>") {
+        Some((thinking, answer)) => (
+            answer.trim_start().to_string(),
+            Some(thinking.trim().to_string()),
+        ),
+        // truncated while thinking: everything is thinking
+        None => (String::new(), Some(rest.trim().to_string())),
+    }
+}
+
+#[cfg(test)]
+mod reasoning_tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Instant;
+
+    #[test]
+    fn test_reasoning_from_providers() {
+        let start = Instant::now();
+
+        let message = json!({ "content": "4", "reasoning": "2 + 2 = 4" });
+        assert_eq!(from_openrouter(&message).as_deref(), Some("2 + 2 = 4"));
+        let message = json!({ "content": "4", "reasoning_details": [
+            { "type": "reasoning.summary", "summary": "Added the numbers." },
+            { "type": "reasoning.encrypted", "data": "gAAAA" }
+        ]});
+        assert_eq!(
+            from_openrouter(&message).as_deref(),
+            Some("Added the numbers.")
+        );
+        assert_eq!(from_openrouter(&json!({ "content": "4" })), None);
+
+        assert_eq!(
+            split_thinking("<think>\n2 + 2 = 4\n</think>\n\n4".to_string()),
+            ("4".to_string(), Some("2 + 2 = 4".to_string()))
+        );
+        assert_eq!(split_thinking("4".to_string()), ("4".to_string(), None));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_reasoning_from_providers took: {:?}", duration);
+    }
+}
@@ -0,0 +1,607 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::oneshot;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{field, info_span, warn, Instrument};
+
+use super::http::send_traced;
+
+/// Timeout applied when neither the request nor the model defines one.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Maximum backoff between two polls when neither the request nor the model defines one.
+pub const DEFAULT_MAX_POLL_BACKOFF: Duration = Duration::from_secs(2);
+
+/// First delay between two polls, doubled after every unfinished poll.
+const INITIAL_POLL_DELAY: Duration = Duration::from_millis(200);
+
+/// Largest gap, in seconds, between the timestamp of a webhook and the time it is
+/// received, so that a captured webhook can't be replayed later.
+const WEBHOOK_TOLERANCE_SECS: u64 = 5 * 60;
+
+/// How long a webhook arriving before its prediction is awaited is kept.
+const EARLY_WEBHOOK_TTL: Duration = Duration::from_secs(60);
+
+/// Webhooks arriving before their prediction is awaited kept at most.
+const MAX_EARLY_WEBHOOKS: usize = 1024;
+
+/// Reason why waiting for a prediction stopped without a usable output.
+#[derive(Debug)]
+pub enum WaitError {
+    /// The prediction did not finish before the deadline.
+    TimedOut(Duration),
+
+    /// The prediction reached the terminal `failed` or `canceled` status.
+    Ended(String),
+
+    /// Any other failure (network, upstream status, parsing...): the prediction may
+    /// still be running.
+    Failed(String),
+}
+
+impl std::fmt::Display for WaitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WaitError::TimedOut(timeout) => {
+                write!(f, "Replicate prediction timed out after {:?}", timeout)
+            }
+            WaitError::Ended(e) | WaitError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Webhook URL of `REPLICATE_WEBHOOK_URL`, unless running on AWS Lambda.
+///
+/// Webhooks are delivered to the process waiting on them, but a Lambda environment
+/// handles one invocation at a time: the webhook would always reach another
+/// environment and the prediction time out. Lambda keeps polling instead.
+pub fn webhook_url_from_env() -> Option<String> {
+    webhook_url(
+        std::env::var("REPLICATE_WEBHOOK_URL").ok(),
+        std::env::var_os("AWS_LAMBDA_FUNCTION_NAME").is_some(),
+    )
+}
+
+fn webhook_url(url: Option<String>, on_lambda: bool) -> Option<String> {
+    match (url, on_lambda) {
+        (Some(_), true) => {
+            warn!("REPLICATE_WEBHOOK_URL is ignored on AWS Lambda, predictions are polled");
+            None
+        }
+        (url, _) => url,
+    }
+}
+
+/// URL used to fetch a prediction when Replicate did not return `urls.get`.
+pub fn default_get_url(base_url: &str, id: &str) -> String {
+    format!("{}/predictions/{}", base_url, id)
+}
+
+/// URL used to cancel a prediction when Replicate did not return `urls.cancel`.
+pub fn default_cancel_url(base_url: &str, id: &str) -> String {
+    format!("{}/predictions/{}/cancel", base_url, id)
+}
+
+/// Next delay of the exponential backoff, capped at `max`.
+fn next_delay(delay: Duration, max: Duration) -> Duration {
+    std::cmp::min(delay * 2, max)
+}
+
+/// Poll the prediction `get_url` until it reaches a terminal status or `timeout` elapses.
+///
+/// Returns the final prediction JSON when it succeeded. Every poll is bounded by the
+/// time left, so a hung connection can't outlive the deadline.
+pub async fn poll_prediction(
+    client: &Client,
+    get_url: &str,
+    api_token: &str,
+    timeout: Duration,
+    max_backoff: Duration,
+) -> Result<Value, WaitError> {
+    let mut delay = std::cmp::min(INITIAL_POLL_DELAY, max_backoff);
+    let start = Instant::now();
+
+    let mut attempt: u64 = 0;
+
+    loop {
+        attempt += 1;
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Err(WaitError::TimedOut(timeout));
+        }
+
+        let span = info_span!("replicate.poll", attempt, prediction.status = field::Empty);
+        let poll = poll_once(client, get_url, api_token).instrument(span.clone());
+        let poll_json = tokio::time::timeout(remaining, poll)
+            .await
+            .map_err(|_| WaitError::TimedOut(timeout))??;
+        if let Some(status) = poll_json.get("status").and_then(|v| v.as_str()) {
+            span.record("prediction.status", status);
+        }
+
+        if let Some(result) = terminal_result(poll_json) {
+            return result;
+        }
+
+        // never sleep past the deadline
+        let remaining = timeout.saturating_sub(start.elapsed());
+        sleep(std::cmp::min(delay, remaining)).await;
+        delay = next_delay(delay, max_backoff);
+    }
+}
+
+/// Fetch the prediction once.
+async fn poll_once(client: &Client, get_url: &str, api_token: &str) -> Result<Value, WaitError> {
+    let request = client
+        .get(get_url)
+        .header("Authorization", format!("Bearer {}", api_token));
+    let poll_resp = send_traced("GET", get_url, request)
+        .await
+        .map_err(|e| WaitError::Failed(format!("Failed to poll prediction: {}", e)))?;
+
+    let poll_status = poll_resp.status();
+    if !poll_status.is_success() {
+        let error_text = poll_resp
+            .text()
+            .await
+            .map_err(|e| WaitError::Failed(format!("error reading poll error body: {}", e)))?;
+        return Err(WaitError::Failed(format!(
+            "Replicate poll failed with status: {} and body: {}",
+            poll_status, error_text
+        )));
+    }
+
+    poll_resp
+        .json()
+        .await
+        .map_err(|e| WaitError::Failed(format!("Failed to parse poll response JSON: {}", e)))
+}
+
+/// Interpret a prediction JSON: `None` while it is still running, the outcome otherwise.
+fn terminal_result(prediction: Value) -> Option<Result<Value, WaitError>> {
+    let status = prediction
+        .get("status")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown")
+        .to_string();
+
+    match status.as_str() {
+        "succeeded" => Some(Ok(prediction)),
+        "failed" | "canceled" => Some(Err(WaitError::Ended(format!(
+            "Replicate prediction {}: {}",
+            status, prediction
+        )))),
+        _ => None,
+    }
+}
+
+/// Ask Replicate to cancel a running prediction so it stops being billed.
+pub async fn cancel_prediction(
+    client: &Client,
+    cancel_url: &str,
+    api_token: &str,
+) -> Result<(), String> {
+    let request = client
+        .post(cancel_url)
+        .header("Authorization", format!("Bearer {}", api_token));
+    let response = send_traced("POST", cancel_url, request)
+        .await
+        .map_err(|e| format!("Failed to cancel prediction: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Replicate cancel failed with status: {}",
+            response.status()
+        ));
+    }
+    Ok(())
+}
+
+/// Upload `data` to the Replicate Files API and return the URL to pass as a model input.
+///
+/// Data URLs are only reliable for small files; uploads work for long recordings too.
+pub async fn upload_file(
+    client: &Client,
+    base_url: &str,
+    api_token: &str,
+    filename: &str,
+    content_type: &str,
+    data: &[u8],
+) -> Result<String, String> {
+    let url = format!("{}/files", base_url);
+    // reqwest's multipart support isn't enabled, the body is small enough to build by hand
+    // RandomState is seeded randomly: the boundary can't be predicted from the file
+    let boundary = format!("polytheus-{:016x}", RandomState::new().hash_one(data.len()));
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"content\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+        boundary,
+        filename.replace('"', ""),
+        content_type
+    )
+    .into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    let request = client
+        .post(&url)
+        .header("Authorization", format!("Bearer {}", api_token))
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(body);
+    let response = send_traced("POST", &url, request)
+        .await
+        .map_err(|e| format!("Failed to upload file: {}", e))?;
+    let status = response.status();
+    let file: Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse file upload response: {}", e))?;
+    if !status.is_success() {
+        return Err(format!(
+            "Replicate file upload failed with status: {} and body: {}",
+            status, file
+        ));
+    }
+    file["urls"]["get"]
+        .as_str()
+        .map(|url| url.to_string())
+        .ok_or_else(|| format!("Replicate file upload response missing urls.get: {}", file))
+}
+
+/// Cancels the prediction when dropped while still armed.
+///
+/// The guard lives for as long as `Polytheus::run` waits on a prediction: if the caller
+/// goes away (client disconnect, dropped future), the prediction is cancelled in the
+/// background instead of running, and billing, until Replicate's own limit.
+pub struct CancelGuard {
+    client: Client,
+    cancel_url: Option<String>,
+    api_token: String,
+}
+
+impl CancelGuard {
+    /// Arm a guard for the prediction reachable at `cancel_url`.
+    pub fn new(client: Client, cancel_url: String, api_token: String) -> CancelGuard {
+        CancelGuard {
+            client,
+            cancel_url: Some(cancel_url),
+            api_token,
+        }
+    }
+
+    /// The prediction reached a terminal status: nothing to cancel anymore.
+    pub fn disarm(mut self) {
+        self.cancel_url = None;
+    }
+
+    /// Cancel the prediction now and wait for Replicate to acknowledge it.
+    pub async fn cancel(mut self) -> Result<(), String> {
+        match self.cancel_url.take() {
+            Some(url) => cancel_prediction(&self.client, &url, &self.api_token).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        let Some(url) = self.cancel_url.take() else {
+            return;
+        };
+        // Drop can't be async: hand the request over to the runtime if there still is one.
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let client = self.client.clone();
+            let api_token = std::mem::take(&mut self.api_token);
+            handle.spawn(async move {
+                let _ = cancel_prediction(&client, &url, &api_token).await;
+            });
+        }
+    }
+}
+
+/// Predictions waiting for a webhook, and webhooks that arrived before anyone waited,
+/// with their arrival time.
+#[derive(Default)]
+struct WebhookRegistry {
+    waiting: HashMap<String, oneshot::Sender<Value>>,
+    arrived: HashMap<String, (Value, Instant)>,
+}
+
+/// Process-wide registry: the webhook may be received by another `Polytheus` instance
+/// than the one waiting for it.
+fn webhook_registry() -> &'static Mutex<WebhookRegistry> {
+    static REGISTRY: OnceLock<Mutex<WebhookRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(WebhookRegistry::default()))
+}
+
+/// Headers Replicate signs its webhooks with, following the Standard Webhooks spec.
+pub struct WebhookHeaders<'a> {
+    /// `webhook-id`: unique id of the delivery.
+    pub id: &'a str,
+
+    /// `webhook-timestamp`: seconds since the Unix epoch when it was sent.
+    pub timestamp: &'a str,
+
+    /// `webhook-signature`: space-separated `v1,<base64 HMAC-SHA256>` signatures.
+    pub signature: &'a str,
+}
+
+/// Check that `body` was signed by Replicate with `secret` (the `whsec_...` signing
+/// secret of the account), less than `WEBHOOK_TOLERANCE_SECS` away from `now`
+/// (seconds since the Unix epoch).
+pub fn verify_webhook(
+    secret: &str,
+    headers: &WebhookHeaders,
+    body: &[u8],
+    now: u64,
+) -> Result<(), String> {
+    let key = STANDARD
+        .decode(secret.trim_start_matches("whsec_"))
+        .map_err(|_| "Invalid Replicate webhook signing secret".to_string())?;
+    let timestamp: u64 = headers
+        .timestamp
+        .parse()
+        .map_err(|_| "Invalid Replicate webhook timestamp".to_string())?;
+    if timestamp.abs_diff(now) > WEBHOOK_TOLERANCE_SECS {
+        return Err("Replicate webhook timestamp is too old or in the future".to_string());
+    }
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key)
+        .map_err(|_| "Invalid Replicate webhook signing secret".to_string())?;
+    mac.update(format!("{}.{}.", headers.id, headers.timestamp).as_bytes());
+    mac.update(body);
+    let signed = headers
+        .signature
+        .split_whitespace()
+        .filter_map(|signature| signature.strip_prefix("v1,"))
+        .filter_map(|signature| STANDARD.decode(signature).ok())
+        .any(|signature| mac.clone().verify_slice(&signature).is_ok());
+    match signed {
+        true => Ok(()),
+        false => Err("Invalid Replicate webhook signature".to_string()),
+    }
+}
+
+/// Deliver a verified Replicate webhook payload to the `Polytheus::run` call waiting on
+/// it.
+///
+/// Non-terminal events are ignored. A webhook for a prediction nobody waits for yet is
+/// kept `EARLY_WEBHOOK_TTL`, in case it beat the creation response of the prediction.
+/// Only the process that created the prediction can resolve it, so webhook mode
+/// requires a long-lived server: `webhook_url_from_env` turns it off on Lambda.
+pub fn receive_webhook(payload: Value) -> Result<(), String> {
+    let id = payload
+        .get("id")
+        .and_then(|v| v.as_str())
+        .ok_or("Replicate webhook payload is missing the prediction id")?
+        .to_string();
+
+    let status = payload.get("status").and_then(|v| v.as_str());
+    if !matches!(status, Some("succeeded" | "failed" | "canceled")) {
+        return Ok(());
+    }
+
+    let mut registry = webhook_registry()
+        .lock()
+        .map_err(|_| "Replicate webhook registry poisoned".to_string())?;
+    if let Some(sender) = registry.waiting.remove(&id) {
+        let _ = sender.send(payload);
+        return Ok(());
+    }
+    registry
+        .arrived
+        .retain(|_, (_, arrived_at)| arrived_at.elapsed() < EARLY_WEBHOOK_TTL);
+    if registry.arrived.len() >= MAX_EARLY_WEBHOOKS {
+        return Err(format!(
+            "Replicate webhook for unknown prediction '{}' refused: too many pending",
+            id
+        ));
+    }
+    registry.arrived.insert(id, (payload, Instant::now()));
+    Ok(())
+}
+
+/// Wait for the webhook of prediction `id` instead of polling it.
+pub async fn wait_for_webhook(id: &str, timeout: Duration) -> Result<Value, WaitError> {
+    let receiver = {
+        let mut registry = webhook_registry()
+            .lock()
+            .map_err(|_| WaitError::Failed("Replicate webhook registry poisoned".to_string()))?;
+        let arrived = registry
+            .arrived
+            .remove(id)
+            .filter(|(_, arrived_at)| arrived_at.elapsed() < EARLY_WEBHOOK_TTL);
+        if let Some((payload, _)) = arrived {
+            return terminal_result(payload).unwrap_or_else(|| {
+                Err(WaitError::Failed(
+                    "Replicate webhook without final status".to_string(),
+                ))
+            });
+        }
+        let (sender, receiver) = oneshot::channel();
+        registry.waiting.insert(id.to_string(), sender);
+        receiver
+    };
+
+    let outcome = tokio::time::timeout(timeout, receiver).await;
+    if let Ok(mut registry) = webhook_registry().lock() {
+        registry.waiting.remove(id);
+    }
+
+    match outcome {
+        Ok(Ok(payload)) => terminal_result(payload).unwrap_or_else(|| {
+            Err(WaitError::Failed(
+                "Replicate webhook without final status".to_string(),
+            ))
+        }),
+        Ok(Err(_)) => Err(WaitError::Failed(
+            "Replicate webhook channel closed".to_string(),
+        )),
+        Err(_) => Err(WaitError::TimedOut(timeout)),
+    }
+}
+
+#[cfg(test)]
+mod replicate_tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Instant;
+
+    #[test]
+    fn test_next_delay_is_capped() {
+        let start = Instant::now();
+
+        let max = Duration::from_millis(500);
+        assert_eq!(
+            next_delay(Duration::from_millis(200), max),
+            Duration::from_millis(400)
+        );
+        assert_eq!(next_delay(Duration::from_millis(400), max), max);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_next_delay_is_capped took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_webhooks_are_off_on_lambda() {
+        let start = Instant::now();
+
+        let url = Some("https://example.com/v1/webhooks/replicate".to_string());
+        assert_eq!(webhook_url(url.clone(), false), url);
+        assert_eq!(webhook_url(url, true), None);
+        assert_eq!(webhook_url(None, false), None);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_webhooks_are_off_on_lambda took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_default_urls() {
+        let start = Instant::now();
+
+        let base = "https://api.replicate.com/v1";
+        assert_eq!(
+            default_cancel_url(base, "abc"),
+            "https://api.replicate.com/v1/predictions/abc/cancel"
+        );
+        assert_eq!(
+            default_get_url(base, "abc"),
+            "https://api.replicate.com/v1/predictions/abc"
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_default_urls took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_resolves_waiting_prediction() {
+        let start = Instant::now();
+
+        let waiter = tokio::spawn(async {
+            wait_for_webhook("webhook-waiting", Duration::from_secs(5)).await
+        });
+        // let the waiter register itself
+        sleep(Duration::from_millis(20)).await;
+        receive_webhook(json!({ "id": "webhook-waiting", "status": "processing" })).unwrap();
+        receive_webhook(json!({ "id": "webhook-waiting", "status": "succeeded", "output": "hi" }))
+            .unwrap();
+
+        let prediction = waiter.await.unwrap().unwrap();
+        assert_eq!(prediction["output"], json!("hi"));
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_webhook_resolves_waiting_prediction took: {:?}",
+            duration
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook_arrived_before_waiting() {
+        let start = Instant::now();
+
+        receive_webhook(json!({ "id": "webhook-early", "status": "failed" })).unwrap();
+        let result = wait_for_webhook("webhook-early", Duration::from_secs(1)).await;
+        assert!(matches!(result, Err(WaitError::Ended(_))));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_webhook_arrived_before_waiting took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_wait_times_out() {
+        let start = Instant::now();
+
+        let result = wait_for_webhook("webhook-never", Duration::from_millis(10)).await;
+        assert!(matches!(result, Err(WaitError::TimedOut(_))));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_webhook_wait_times_out took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_hung_poll_times_out() {
+        let start = Instant::now();
+
+        // accepts the connection but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/predictions/hung", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let _streams: Vec<_> = listener.incoming().collect();
+        });
+
+        let timeout = Duration::from_millis(200);
+        let result = poll_prediction(
+            &Client::new(),
+            &url,
+            "token",
+            timeout,
+            DEFAULT_MAX_POLL_BACKOFF,
+        )
+        .await;
+        assert!(matches!(result, Err(WaitError::TimedOut(_))));
+        assert!(Instant::now() - start < Duration::from_secs(2));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_hung_poll_times_out took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_webhook_signature_is_verified() {
+        let start = Instant::now();
+
+        // example of the Standard Webhooks specification
+        let secret = "whsec_MfKQ9r8GKYqrTwjUPD8ILPZIo2LaLaSw";
+        let body = br#"{"test": 2432232314}"#;
+        let headers = WebhookHeaders {
+            id: "msg_p5jXN8AQM9LWM0D4loKWxJek",
+            timestamp: "1614265330",
+            signature: "v1,bm9wZQ== v1,g0hM9SsE+OTPJTGt/tmIKtSyZlE3uFJELVlNIOLJ1OE=",
+        };
+        assert_eq!(verify_webhook(secret, &headers, body, 1614265330), Ok(()));
+
+        let tampered = br#"{"test": 2432232315}"#;
+        assert_eq!(
+            verify_webhook(secret, &headers, tampered, 1614265330),
+            Err("Invalid Replicate webhook signature".to_string())
+        );
+        let other_secret = "whsec_c2VjcmV0";
+        assert!(verify_webhook(other_secret, &headers, body, 1614265330).is_err());
+        assert_eq!(
+            verify_webhook(secret, &headers, body, 1614265330 + 3600),
+            Err("Replicate webhook timestamp is too old or in the future".to_string())
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_webhook_signature_is_verified took: {:?}", duration);
+    }
+}
@@ -0,0 +1,236 @@
+use super::model::Model;
+use super::Message;
+
+/// Conversation of `messages` in the roles `model` accepts.
+///
+/// - roles are trimmed and lowercased, and `developer` stands in for `system` (and the
+///   reverse) on models that only take one of them;
+/// - the system messages of a model taking neither stay as they are when the model has
+///   a system input (`system_input`, e.g. a Replicate `system_prompt`), and are
+///   otherwise prepended to the first user turn;
+/// - consecutive messages of the same role are merged, unless they both hold an
+///   attachment of the same kind;
+/// - models without a system role (Anthropic-like) must see a user turn first.
+///
+/// Any other role the model doesn't authorize is an error.
+pub fn normalize(
+    model: &Model,
+    messages: Vec<Message>,
+    system_input: bool,
+) -> Result<Vec<Message>, String> {
+    let authorized = |role: &str| {
+        model
+            .get_roles_authorized()
+            .is_none_or(|roles| roles.iter().any(|r| r == role))
+    };
+    let has_system_role = authorized("system") || authorized("developer");
+
+    let mut normalized: Vec<Message> = Vec::with_capacity(messages.len());
+    let mut system_texts = vec![];
+    for mut message in messages {
+        message.role = message.role.trim().to_ascii_lowercase();
+        if is_system(&message.role) {
+            if !authorized(&message.role) {
+                match (has_system_role, system_input) {
+                    (true, _) => message.role = other_system_role(&message.role).to_string(),
+                    (false, true) => message.role = "system".to_string(),
+                    (false, false) => {
+                        if !message.attachment_modalities().is_empty() {
+                            return Err(format!(
+                                "Model '{}' takes no attachment in system messages",
+                                model.get_name()
+                            ));
+                        }
+                        system_texts.push(message.input_text);
+                        continue;
+                    }
+                }
+            }
+        } else if !authorized(&message.role) {
+            return Err(format!(
+                "Role '{}' not authorized for model '{}'",
+                message.role,
+                model.get_name()
+            ));
+        }
+
+        match normalized.last_mut() {
+            Some(previous) if previous.role == message.role && !collide(previous, &message) => {
+                merge(previous, message)
+            }
+            _ => normalized.push(message),
+        }
+    }
+
+    if !system_texts.is_empty() {
+        let system = system_texts.join("\n\n");
+        match normalized.iter_mut().find(|message| message.role == "user") {
+            Some(first) if first.input_text.is_empty() => first.input_text = system,
+            Some(first) => first.input_text = format!("{}\n\n{}", system, first.input_text),
+            None => normalized.insert(
+                0,
+                Message {
+                    role: "user".to_string(),
+                    input_text: system,
+                    input_image: None,
+                    input_audio: None,
+                    input_audio_format: None,
+                    input_video: None,
+                    input_file: None,
+                },
+            ),
+        }
+    }
+
+    let first_turn = normalized.iter().find(|message| !is_system(&message.role));
+    if !has_system_role && first_turn.is_some_and(|turn| turn.role != "user") {
+        return Err(format!(
+            "Model '{}' needs the conversation to start with a user message",
+            model.get_name()
+        ));
+    }
+    Ok(normalized)
+}
+
+fn is_system(role: &str) -> bool {
+    matches!(role, "system" | "developer")
+}
+
+fn other_system_role(role: &str) -> &'static str {
+    match role {
+        "developer" => "system",
+        _ => "developer",
+    }
+}
+
+/// Whether `a` and `b` both hold an attachment of the same kind.
+fn collide(a: &Message, b: &Message) -> bool {
+    let modalities = a.attachment_modalities();
+    b.attachment_modalities()
+        .iter()
+        .any(|modality| modalities.contains(modality))
+}
+
+/// Append the text and attachments of `message` to `previous`.
+fn merge(previous: &mut Message, message: Message) {
+    previous.input_text = match (
+        previous.input_text.is_empty(),
+        message.input_text.is_empty(),
+    ) {
+        (_, true) => std::mem::take(&mut previous.input_text),
+        (true, false) => message.input_text,
+        (false, false) => format!("{}\n\n{}", previous.input_text, message.input_text),
+    };
+    if message.input_audio.is_some() {
+        previous.input_audio = message.input_audio;
+        previous.input_audio_format = message.input_audio_format;
+    }
+    previous.input_image = previous.input_image.take().or(message.input_image);
+    previous.input_video = previous.input_video.take().or(message.input_video);
+    previous.input_file = previous.input_file.take().or(message.input_file);
+}
+
+#[cfg(test)]
+mod roles_tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn model(name: &str) -> Model {
+        Model::fill()
+            .into_iter()
+            .find(|model| model.get_name() == name)
+            .unwrap()
+    }
+
+    fn message(role: &str, text: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            input_text: text.to_string(),
+            input_image: None,
+            input_audio: None,
+            input_audio_format: None,
+            input_video: None,
+            input_file: None,
+        }
+    }
+
+    fn turns(messages: &[Message]) -> Vec<(&str, &str)> {
+        messages
+            .iter()
+            .map(|message| (message.role.as_str(), message.input_text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_system_messages_are_adapted() {
+        let start = Instant::now();
+
+        let claude = model("claude-4-sonnet");
+        let messages = vec![
+            message("System", "Be brief."),
+            message("developer", "Answer in JSON."),
+            message("user", "Hi"),
+        ];
+        let prepended = normalize(&claude, messages.clone(), false).unwrap();
+        assert_eq!(
+            turns(&prepended),
+            vec![("user", "Be brief.\n\nAnswer in JSON.\n\nHi")]
+        );
+        let kept = normalize(&claude, messages.clone(), true).unwrap();
+        assert_eq!(
+            turns(&kept),
+            vec![("system", "Be brief.\n\nAnswer in JSON."), ("user", "Hi")]
+        );
+
+        let grok = model("grok-4");
+        let developer = normalize(&grok, messages, false).unwrap();
+        assert_eq!(developer[0].role, "system");
+
+        assert_eq!(
+            normalize(&claude, vec![message("tool", "42")], false),
+            Err("Role 'tool' not authorized for model 'claude-4-sonnet'".to_string())
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_system_messages_are_adapted took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_turns_are_merged_and_ordered() {
+        let start = Instant::now();
+
+        let claude = model("claude-4-sonnet");
+        let image = |text: &str, url: &str| Message {
+            input_image: Some(url.to_string()),
+            ..message("user", text)
+        };
+        let messages = vec![
+            message("user", "Look:"),
+            image("", "a"),
+            image("And this one", "b"),
+            message("assistant", "Sure."),
+        ];
+        let merged = normalize(&claude, messages, false).unwrap();
+        assert_eq!(
+            turns(&merged),
+            vec![
+                ("user", "Look:"),
+                ("user", "And this one"),
+                ("assistant", "Sure.")
+            ]
+        );
+        assert_eq!(merged[0].input_image.as_deref(), Some("a"));
+
+        assert_eq!(
+            normalize(&claude, vec![message("assistant", "Hello")], false),
+            Err(
+                "Model 'claude-4-sonnet' needs the conversation to start with a user message"
+                    .to_string()
+            )
+        );
+        assert!(normalize(&model("gpt-4o"), vec![message("assistant", "Hello")], false).is_ok());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_turns_are_merged_and_ordered took: {:?}", duration);
+    }
+}
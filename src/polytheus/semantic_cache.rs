@@ -0,0 +1,334 @@
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::embedding::{Embedder, OpenRouterEmbedder};
+use super::http::HttpConfig;
+use super::{Completion, Message};
+
+/// Similarity above which a previous prompt is considered the same question.
+const DEFAULT_THRESHOLD: f32 = 0.95;
+
+/// Embedding model used when `POLYTHEUS_SEMANTIC_CACHE_MODEL` is not set.
+const DEFAULT_EMBEDDING_MODEL: &str = "openai/text-embedding-3-small";
+
+/// Entries kept per scope when `POLYTHEUS_SEMANTIC_CACHE_CAPACITY` is not set.
+const DEFAULT_CAPACITY: usize = 1000;
+
+/// Time to live of an entry when `POLYTHEUS_SEMANTIC_CACHE_TTL_SECS` is not set.
+const DEFAULT_TTL: Duration = Duration::from_secs(3600);
+
+/// Nearest-neighbour search over cached completions, partitioned by scope.
+pub trait VectorIndex: Send + Sync + Debug {
+    /// Most similar entry of `scope` to `vector`, with its cosine similarity.
+    fn nearest(&self, scope: &str, vector: &[f32]) -> Option<(f32, Completion)>;
+
+    /// Add `completion`, embedded as `vector`, to `scope`.
+    fn insert(&self, scope: &str, vector: Vec<f32>, completion: Completion);
+}
+
+/// Index comparing the query with every entry of the scope.
+///
+/// Fine for a few thousand entries per scope; oldest entries are evicted past `capacity`.
+#[derive(Debug)]
+pub struct BruteForceIndex {
+    capacity: usize,
+    ttl: Duration,
+    scopes: Mutex<HashMap<String, Vec<IndexEntry>>>,
+}
+
+#[derive(Debug)]
+struct IndexEntry {
+    /// Normalized embedding, so the dot product is the cosine similarity.
+    vector: Vec<f32>,
+    completion: Completion,
+    created_at: Instant,
+}
+
+impl BruteForceIndex {
+    /// Index keeping at most `capacity` entries per scope, each for `ttl`.
+    pub fn new(capacity: usize, ttl: Duration) -> BruteForceIndex {
+        BruteForceIndex {
+            capacity: capacity.max(1),
+            ttl,
+            scopes: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl VectorIndex for BruteForceIndex {
+    fn nearest(&self, scope: &str, vector: &[f32]) -> Option<(f32, Completion)> {
+        let query = normalize(vector.to_vec())?;
+        let scopes = self.scopes.lock().ok()?;
+        scopes
+            .get(scope)?
+            .iter()
+            .filter(|entry| entry.created_at.elapsed() < self.ttl)
+            .filter(|entry| entry.vector.len() == query.len())
+            .map(|entry| (dot(&entry.vector, &query), entry))
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(similarity, entry)| (similarity, entry.completion.clone()))
+    }
+
+    fn insert(&self, scope: &str, vector: Vec<f32>, completion: Completion) {
+        let (Some(vector), Ok(mut scopes)) = (normalize(vector), self.scopes.lock()) else {
+            return;
+        };
+        let entries = scopes.entry(scope.to_string()).or_default();
+        entries.retain(|entry| entry.created_at.elapsed() < self.ttl);
+        if entries.len() >= self.capacity {
+            entries.remove(0);
+        }
+        entries.push(IndexEntry {
+            vector,
+            completion,
+            created_at: Instant::now(),
+        });
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// `vector` scaled to unit length, `None` for a null vector.
+fn normalize(mut vector: Vec<f32>) -> Option<Vec<f32>> {
+    let norm = dot(&vector, &vector).sqrt();
+    if norm == 0.0 || !norm.is_finite() {
+        return None;
+    }
+    vector.iter_mut().for_each(|v| *v /= norm);
+    Some(vector)
+}
+
+/// Cache answering prompts close enough to a previous one, within the same scope.
+#[derive(Debug)]
+pub struct SemanticCache {
+    embedder: Box<dyn Embedder>,
+    index: Box<dyn VectorIndex>,
+    threshold: f32,
+}
+
+impl SemanticCache {
+    /// Cache embedding prompts with `embedder`, matching when the cosine similarity
+    /// reaches `threshold`.
+    pub fn new(
+        embedder: Box<dyn Embedder>,
+        index: Box<dyn VectorIndex>,
+        threshold: f32,
+    ) -> SemanticCache {
+        SemanticCache {
+            embedder,
+            index,
+            threshold,
+        }
+    }
+
+    /// Build the cache when `POLYTHEUS_SEMANTIC_CACHE` is enabled, configured by
+    /// `POLYTHEUS_SEMANTIC_CACHE_{THRESHOLD,MODEL,CAPACITY,TTL_SECS}`.
+    pub fn from_env(client: Client, http_config: &HttpConfig) -> Option<SemanticCache> {
+        let enabled = env::var("POLYTHEUS_SEMANTIC_CACHE")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+        let var = |name: &str| env::var(name).ok();
+        let model = var("POLYTHEUS_SEMANTIC_CACHE_MODEL")
+            .unwrap_or_else(|| DEFAULT_EMBEDDING_MODEL.to_string());
+        let index = BruteForceIndex::new(
+            var("POLYTHEUS_SEMANTIC_CACHE_CAPACITY")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_CAPACITY),
+            var("POLYTHEUS_SEMANTIC_CACHE_TTL_SECS")
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_TTL),
+        );
+        Some(SemanticCache::new(
+            Box::new(OpenRouterEmbedder::new(client, http_config, &model)),
+            Box::new(index),
+            var("POLYTHEUS_SEMANTIC_CACHE_THRESHOLD")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_THRESHOLD),
+        ))
+    }
+
+    /// Partition of the index a request may match in: answers never cross models,
+    /// tenants, thinking levels or generation parameters.
+    pub fn scope(
+        model_name: &str,
+        tenant: Option<&str>,
+        thinking_level: Option<&str>,
+        params: &Value,
+    ) -> String {
+        json!([model_name, tenant, thinking_level, params]).to_string()
+    }
+
+    /// Text embedded for `messages`, `None` when they carry attachments (not comparable).
+    pub fn prompt(messages: &[Message]) -> Option<String> {
+        let has_attachment = messages.iter().any(|m| {
+            m.input_image.is_some()
+                || m.input_audio.is_some()
+                || m.input_video.is_some()
+                || m.input_file.is_some()
+        });
+        (!has_attachment).then(|| {
+            messages
+                .iter()
+                .map(|m| format!("{}: {}", m.role.trim().to_lowercase(), m.input_text.trim()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+    }
+
+    /// Embed `prompt` and look for a close enough answer in `scope`.
+    ///
+    /// The vector is returned so a miss can be stored without embedding again.
+    pub async fn lookup(
+        &self,
+        scope: &str,
+        prompt: &str,
+    ) -> Result<(Vec<f32>, Option<Completion>), String> {
+        let vector = self
+            .embedder
+            .embed(&[prompt.to_string()])
+            .await?
+            .pop()
+            .ok_or_else(|| "No embedding returned for the prompt".to_string())?;
+        let hit = self
+            .index
+            .nearest(scope, &vector)
+            .filter(|(similarity, _)| *similarity >= self.threshold)
+            .map(|(_, completion)| completion);
+        Ok((vector, hit))
+    }
+
+    /// Remember `completion` as the answer of the prompt embedded as `vector`.
+    pub fn store(&self, scope: &str, vector: Vec<f32>, completion: Completion) {
+        self.index.insert(scope, vector, completion);
+    }
+}
+
+#[cfg(test)]
+mod semantic_cache_tests {
+    use super::*;
+    use crate::polytheus::EmbedFuture;
+    use std::time::Instant;
+
+    /// Bag-of-words embedder: one dimension per known word.
+    #[derive(Debug)]
+    struct WordEmbedder;
+
+    impl Embedder for WordEmbedder {
+        fn embed<'a>(&'a self, inputs: &'a [String]) -> EmbedFuture<'a> {
+            const WORDS: [&str; 6] = ["opening", "hours", "store", "refund", "policy", "the"];
+            Box::pin(async move {
+                Ok(inputs
+                    .iter()
+                    .map(|input| {
+                        let input = input.to_lowercase();
+                        WORDS
+                            .iter()
+                            .map(|word| input.matches(word).count() as f32)
+                            .collect()
+                    })
+                    .collect())
+            })
+        }
+    }
+
+    fn completion(text: &str) -> Completion {
+        Completion {
+            text: text.to_string(),
+            ..Completion::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_similar_prompt_hits_within_scope_only() {
+        let start = Instant::now();
+
+        let cache = SemanticCache::new(
+            Box::new(WordEmbedder),
+            Box::new(BruteForceIndex::new(10, DEFAULT_TTL)),
+            0.9,
+        );
+        let scope = SemanticCache::scope("gpt-4o", Some("acme"), None, &Value::Null);
+        let (vector, hit) = cache
+            .lookup(&scope, "What are the store opening hours?")
+            .await
+            .unwrap();
+        assert!(hit.is_none());
+        cache.store(&scope, vector, completion("9 to 5"));
+
+        let (_, hit) = cache
+            .lookup(&scope, "What are the opening hours of the store?")
+            .await
+            .unwrap();
+        assert_eq!(hit.unwrap().text, "9 to 5");
+
+        let (_, hit) = cache.lookup(&scope, "refund policy").await.unwrap();
+        assert!(hit.is_none());
+
+        let other_tenant = SemanticCache::scope("gpt-4o", Some("globex"), None, &Value::Null);
+        let (_, hit) = cache
+            .lookup(&other_tenant, "store opening hours")
+            .await
+            .unwrap();
+        assert!(hit.is_none());
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_similar_prompt_hits_within_scope_only took: {:?}",
+            duration
+        );
+    }
+
+    #[test]
+    fn test_brute_force_index_capacity() {
+        let start = Instant::now();
+
+        let index = BruteForceIndex::new(1, DEFAULT_TTL);
+        index.insert("s", vec![1.0, 0.0], completion("first"));
+        index.insert("s", vec![0.0, 1.0], completion("second"));
+        let (similarity, found) = index.nearest("s", &[1.0, 0.0]).unwrap();
+        assert_eq!(found.text, "second");
+        assert_eq!(similarity, 0.0);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_brute_force_index_capacity took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_attachments_are_not_semantically_cached() {
+        let start = Instant::now();
+
+        let message = |input_file: Option<&str>| Message {
+            role: "user".to_string(),
+            input_text: "Summarize this document".to_string(),
+            input_image: None,
+            input_audio: None,
+            input_audio_format: None,
+            input_video: None,
+            input_file: input_file.map(str::to_string),
+        };
+        assert_eq!(
+            SemanticCache::prompt(&[message(None)]).as_deref(),
+            Some("user: Summarize this document")
+        );
+        assert!(
+            SemanticCache::prompt(&[message(Some("data:application/pdf;base64,JVBE"))]).is_none()
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_attachments_are_not_semantically_cached took: {:?}",
+            duration
+        );
+    }
+}
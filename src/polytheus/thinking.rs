@@ -0,0 +1,283 @@
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+use super::model::{Model, Provider};
+
+/// Thinking budget, in tokens, of the `Low` level.
+pub const LOW_BUDGET: u32 = 4096;
+
+/// Thinking budget, in tokens, of the `Medium` level.
+pub const MEDIUM_BUDGET: u32 = 16384;
+
+/// Thinking budget, in tokens, of the `High` level.
+pub const HIGH_BUDGET: u32 = 32768;
+
+/// Effort levels, from the least to the most thinking.
+const EFFORTS: [&str; 3] = ["low", "medium", "high"];
+
+/// How much a model thinks before answering, whatever the provider.
+///
+/// Each model translates it into its own parameter: a switch (`extended_thinking`),
+/// an effort (`low`, `medium`, `high`) or, on OpenRouter, the `reasoning` object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThinkingLevel {
+    /// No thinking.
+    None,
+
+    /// Quick thinking.
+    Low,
+
+    /// The default amount of thinking.
+    Medium,
+
+    /// Thorough thinking.
+    High,
+
+    /// Thinking within a number of tokens.
+    Budget(u32),
+}
+
+impl ThinkingLevel {
+    /// Thinking budget of the level, in tokens.
+    pub fn budget(&self) -> u32 {
+        match self {
+            ThinkingLevel::None => 0,
+            ThinkingLevel::Low => LOW_BUDGET,
+            ThinkingLevel::Medium => MEDIUM_BUDGET,
+            ThinkingLevel::High => HIGH_BUDGET,
+            ThinkingLevel::Budget(budget) => *budget,
+        }
+    }
+
+    /// The level `model` honors: a token budget becomes the effort level holding it on
+    /// models that don't take a budget (all but the OpenRouter ones with a thinking
+    /// switch), instead of being lost in `apply`.
+    pub fn honored_by(self, model: &Model) -> ThinkingLevel {
+        let Some(levels) = model.get_thinking_levels_authorized() else {
+            return self;
+        };
+        let takes_budget = *model.get_provider() == Provider::OpenRouter
+            && levels.iter().any(|level| level == "true");
+        match (self, self.effort_rank()) {
+            (ThinkingLevel::Budget(_), Some(rank)) if !takes_budget => [
+                ThinkingLevel::Low,
+                ThinkingLevel::Medium,
+                ThinkingLevel::High,
+            ][rank],
+            _ => self,
+        }
+    }
+
+    /// Rank of the level among low, medium and high, a budget counting as the
+    /// smallest level holding it; `None` for no thinking.
+    fn effort_rank(&self) -> Option<usize> {
+        match self {
+            ThinkingLevel::None => None,
+            level if level.budget() <= LOW_BUDGET => Some(0),
+            level if level.budget() <= MEDIUM_BUDGET => Some(1),
+            _ => Some(2),
+        }
+    }
+
+    /// Insert the level into `target` (an OpenRouter body or a Replicate input) in the
+    /// shape `model` expects.
+    ///
+    /// Effort levels the model lacks fall back to the closest one it accepts, the
+    /// higher one on a tie.
+    pub fn apply(&self, model: &Model, target: &mut Map<String, Value>) -> Result<(), String> {
+        let Some(levels) = model.get_thinking_levels_authorized() else {
+            return match self {
+                ThinkingLevel::None => Ok(()),
+                _ => Err(format!(
+                    "Model '{}' doesn't support thinking",
+                    model.get_name()
+                )),
+            };
+        };
+        let switch = levels.iter().any(|level| level == "true");
+        let effort = match self.effort_rank() {
+            Some(wanted) if !switch => {
+                Some(closest_effort(levels, wanted).ok_or_else(|| {
+                    format!("Model '{}' has no thinking effort", model.get_name())
+                })?)
+            }
+            _ => None,
+        };
+        let cannot_stop = || {
+            format!(
+                "Model '{}' can't turn thinking off (levels: {})",
+                model.get_name(),
+                levels.join(", ")
+            )
+        };
+
+        match model.get_provider() {
+            Provider::OpenRouter => {
+                let reasoning = match (self, switch) {
+                    (ThinkingLevel::None, true) => json!({ "enabled": false }),
+                    (ThinkingLevel::None, false) => return Err(cannot_stop()),
+                    (ThinkingLevel::Budget(budget), true) => json!({ "max_tokens": budget }),
+                    // OpenRouter turns efforts into budgets for switch-only models
+                    (_, true) => json!({ "effort": self.effort_rank().map(|rank| EFFORTS[rank]) }),
+                    (_, false) => json!({ "effort": effort }),
+                };
+                target.insert("reasoning".to_string(), reasoning);
+            }
+            Provider::Replicate => {
+                let property = model.get_thinking_level_property().ok_or_else(|| {
+                    format!(
+                        "Model '{}' has no thinking level property",
+                        model.get_name()
+                    )
+                })?;
+                let value = match (self, switch) {
+                    (level, true) => json!(*level != ThinkingLevel::None),
+                    (ThinkingLevel::None, false) => return Err(cannot_stop()),
+                    (_, false) => json!(effort),
+                };
+                target.insert(property.to_string(), value);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Effort among `levels` closest to the `wanted` rank (0 low, 1 medium, 2 high).
+fn closest_effort(levels: &[String], wanted: usize) -> Option<&str> {
+    let rank = |level: &str| EFFORTS.iter().position(|l| *l == level);
+    levels
+        .iter()
+        .filter_map(|level| Some((rank(level)?, level.as_str())))
+        .min_by_key(|(rank, _)| 2 * rank.abs_diff(wanted) + usize::from(*rank < wanted))
+        .map(|(_, level)| level)
+}
+
+impl fmt::Display for ThinkingLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThinkingLevel::None => write!(f, "none"),
+            ThinkingLevel::Low => write!(f, "low"),
+            ThinkingLevel::Medium => write!(f, "medium"),
+            ThinkingLevel::High => write!(f, "high"),
+            ThinkingLevel::Budget(budget) => write!(f, "{}", budget),
+        }
+    }
+}
+
+impl FromStr for ThinkingLevel {
+    type Err = String;
+
+    /// Parse a level name, a switch ("true" is the default level) or a token budget.
+    fn from_str(s: &str) -> Result<ThinkingLevel, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "none" | "false" | "off" | "disabled" | "0" => Ok(ThinkingLevel::None),
+            "minimal" | "low" => Ok(ThinkingLevel::Low),
+            "medium" | "true" | "on" | "enabled" => Ok(ThinkingLevel::Medium),
+            "high" => Ok(ThinkingLevel::High),
+            other => other.parse().map(ThinkingLevel::Budget).map_err(|_| {
+                format!(
+                    "Unknown thinking level '{}': expected none, low, medium, high or a token budget",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod thinking_tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn model(name: &str) -> Model {
+        Model::fill()
+            .into_iter()
+            .find(|model| model.get_name() == name)
+            .unwrap()
+    }
+
+    fn applied(level: ThinkingLevel, name: &str) -> Result<Value, String> {
+        let mut target = Map::new();
+        level.apply(&model(name), &mut target)?;
+        Ok(Value::Object(target))
+    }
+
+    #[test]
+    fn test_parse_thinking_level() {
+        let start = Instant::now();
+
+        assert_eq!("High".parse(), Ok(ThinkingLevel::High));
+        assert_eq!("true".parse(), Ok(ThinkingLevel::Medium));
+        assert_eq!("false".parse(), Ok(ThinkingLevel::None));
+        assert_eq!("2048".parse(), Ok(ThinkingLevel::Budget(2048)));
+        assert!("extreme".parse::<ThinkingLevel>().is_err());
+        assert_eq!(ThinkingLevel::Budget(2048).to_string(), "2048");
+
+        let duration = Instant::now() - start;
+        eprintln!("test_parse_thinking_level took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_apply_native_shapes() {
+        let start = Instant::now();
+
+        assert_eq!(
+            applied(ThinkingLevel::High, "claude-4-sonnet"),
+            Ok(json!({ "extended_thinking": true }))
+        );
+        assert_eq!(
+            applied(ThinkingLevel::None, "claude-4-sonnet"),
+            Ok(json!({ "extended_thinking": false }))
+        );
+        assert_eq!(
+            applied(ThinkingLevel::Budget(1024), "gpt-5-codex"),
+            Ok(json!({ "reasoning": { "effort": "low" } }))
+        );
+        assert_eq!(
+            applied(ThinkingLevel::Medium, "gemini-3-pro"),
+            Ok(json!({ "reasoning": { "effort": "high" } }))
+        );
+        assert_eq!(
+            applied(ThinkingLevel::Budget(8000), "claude-4.5-sonnet"),
+            Ok(json!({ "reasoning": { "max_tokens": 8000 } }))
+        );
+        assert!(applied(ThinkingLevel::None, "grok-4").is_err());
+        assert_eq!(
+            applied(ThinkingLevel::Low, "gpt-4o"),
+            Err("Model 'gpt-4o' doesn't support thinking".to_string())
+        );
+        assert_eq!(applied(ThinkingLevel::None, "gpt-4o"), Ok(json!({})));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_apply_native_shapes took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_budgets_become_levels_without_budget() {
+        let start = Instant::now();
+
+        assert_eq!(
+            ThinkingLevel::Budget(20000).honored_by(&model("gpt-5-codex")),
+            ThinkingLevel::High
+        );
+        assert_eq!(
+            ThinkingLevel::Budget(1024).honored_by(&model("claude-4-sonnet")),
+            ThinkingLevel::Low
+        );
+        assert_eq!(
+            ThinkingLevel::Budget(8000).honored_by(&model("claude-4.5-sonnet")),
+            ThinkingLevel::Budget(8000)
+        );
+        assert_eq!(
+            ThinkingLevel::Budget(8000).honored_by(&model("gpt-4o")),
+            ThinkingLevel::Budget(8000)
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_budgets_become_levels_without_budget took: {:?}",
+            duration
+        );
+    }
+}
@@ -0,0 +1,653 @@
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use super::Message;
+
+/// Database of the SQLite store when `POLYTHEUS_THREADS_DB` is not set.
+const DEFAULT_DB_PATH: &str = "/tmp/polytheus-threads.db";
+
+/// Threads kept in memory when `POLYTHEUS_THREADS_CAPACITY` is not set.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Bytes of threads (metadata, text and attachments) kept in memory when
+/// `POLYTHEUS_THREADS_MAX_BYTES` is not set.
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// A conversation kept across calls, so clients only send the new turns and several
+/// models (or agents) can share the same history.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Thread {
+    /// Identifier of the thread ("thread_..."), random enough not to be guessed.
+    pub id: String,
+
+    /// Tenant owning the thread; only the same tenant (or, without one, anonymous
+    /// callers) can see it.
+    #[serde(default)]
+    pub owner: Option<String>,
+
+    /// Unix time (seconds) the thread was created at.
+    pub created_at: u64,
+
+    /// Free-form metadata given at creation.
+    pub metadata: Value,
+
+    /// Messages of the thread, oldest first.
+    pub messages: Vec<Message>,
+}
+
+impl Thread {
+    /// Bytes of metadata, text and attachments held by the thread.
+    fn size(&self) -> usize {
+        self.metadata.to_string().len() + messages_size(&self.messages)
+    }
+}
+
+/// Bytes of text and attachments held by `messages`.
+fn messages_size(messages: &[Message]) -> usize {
+    messages
+        .iter()
+        .map(|message| {
+            message.input_text.len()
+                + [
+                    &message.input_image,
+                    &message.input_audio,
+                    &message.input_video,
+                    &message.input_file,
+                ]
+                .iter()
+                .map(|attachment| attachment.as_ref().map_or(0, String::len))
+                .sum::<usize>()
+        })
+        .sum()
+}
+
+/// A thread without its messages, as listed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ThreadSummary {
+    pub id: String,
+    pub created_at: u64,
+    pub metadata: Value,
+
+    /// Number of messages of the thread.
+    pub message_count: usize,
+}
+
+/// Storage of threads.
+///
+/// Every operation is scoped by `owner`: a thread of another owner is treated as missing.
+pub trait ThreadStore: Send + Sync + Debug {
+    /// Store the new `thread`.
+    fn insert(&self, thread: &Thread) -> Result<(), String>;
+
+    /// Thread `id` of `owner`, if any.
+    fn get(&self, id: &str, owner: Option<&str>) -> Result<Option<Thread>, String>;
+
+    /// Append `messages` to the thread `id` of `owner`, as a whole.
+    ///
+    /// Given `expected_len`, fails without appending unless the thread still holds that
+    /// many messages, so that answers computed concurrently can't interleave.
+    fn append(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+        expected_len: Option<usize>,
+        messages: &[Message],
+    ) -> Result<(), String>;
+
+    /// At most `limit` threads of `owner`, the most recent first.
+    fn list(&self, owner: Option<&str>, limit: usize) -> Result<Vec<ThreadSummary>, String>;
+
+    /// Delete the thread `id` of `owner`, returning whether it existed.
+    fn delete(&self, id: &str, owner: Option<&str>) -> Result<bool, String>;
+
+    /// Create and store a thread of `owner` holding `messages`.
+    fn create(
+        &self,
+        owner: Option<String>,
+        metadata: Value,
+        messages: Vec<Message>,
+    ) -> Result<Thread, String> {
+        let thread = Thread {
+            id: new_thread_id()?,
+            owner,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            metadata,
+            messages,
+        };
+        self.insert(&thread)?;
+        Ok(thread)
+    }
+}
+
+/// Build the store described by `POLYTHEUS_THREADS` ("sqlite", or unset for memory)
+/// and `POLYTHEUS_THREADS_DB`; a database that can't be opened falls back to memory.
+///
+/// The memory store is bounded by `POLYTHEUS_THREADS_CAPACITY` threads and
+/// `POLYTHEUS_THREADS_MAX_BYTES` bytes.
+pub fn store_from_env() -> Box<dyn ThreadStore> {
+    let memory = || {
+        let limit = |name: &str, default: usize| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Box::new(MemoryThreadStore::new(
+            limit("POLYTHEUS_THREADS_CAPACITY", DEFAULT_CAPACITY),
+            limit("POLYTHEUS_THREADS_MAX_BYTES", DEFAULT_MAX_BYTES),
+        ))
+    };
+    if env::var("POLYTHEUS_THREADS").as_deref() != Ok("sqlite") {
+        return memory();
+    }
+    let path = env::var("POLYTHEUS_THREADS_DB").unwrap_or_else(|_| DEFAULT_DB_PATH.to_string());
+    match SqliteThreadStore::open(&path) {
+        Ok(store) => Box::new(store),
+        Err(e) => {
+            warn!(error = %e, path, "thread database unavailable, keeping threads in memory");
+            memory()
+        }
+    }
+}
+
+fn not_found(id: &str) -> String {
+    format!("Thread '{}' not found", id)
+}
+
+fn changed(id: &str) -> String {
+    format!("Thread '{}' changed while it was answered, try again", id)
+}
+
+/// Identifier of a new thread: 128 bits from the OS random generator.
+fn new_thread_id() -> Result<String, String> {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).map_err(|e| format!("Failed to generate a thread id: {}", e))?;
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(format!("thread_{}", hex))
+}
+
+fn full() -> String {
+    "The thread store is full, delete threads first".to_string()
+}
+
+/// Threads in the memory of the process: on Lambda, they only live as long as the
+/// warm instance.
+///
+/// Threads are never evicted: past `capacity` threads or `max_bytes` bytes, creating
+/// and appending fail until threads are deleted.
+#[derive(Debug)]
+pub struct MemoryThreadStore {
+    capacity: usize,
+    max_bytes: usize,
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    threads: HashMap<String, Thread>,
+
+    /// Sum of the sizes of `threads`.
+    bytes: usize,
+}
+
+impl MemoryThreadStore {
+    /// Store keeping at most `capacity` threads, holding at most `max_bytes` bytes.
+    pub fn new(capacity: usize, max_bytes: usize) -> MemoryThreadStore {
+        MemoryThreadStore {
+            capacity,
+            max_bytes,
+            state: Mutex::new(MemoryState::default()),
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, MemoryState>, String> {
+        self.state
+            .lock()
+            .map_err(|_| "the thread store is poisoned".to_string())
+    }
+}
+
+impl Default for MemoryThreadStore {
+    fn default() -> MemoryThreadStore {
+        MemoryThreadStore::new(DEFAULT_CAPACITY, DEFAULT_MAX_BYTES)
+    }
+}
+
+impl ThreadStore for MemoryThreadStore {
+    fn insert(&self, thread: &Thread) -> Result<(), String> {
+        let mut state = self.lock()?;
+        let size = thread.size();
+        if state.threads.len() >= self.capacity || state.bytes + size > self.max_bytes {
+            return Err(full());
+        }
+        state.bytes += size;
+        if let Some(replaced) = state.threads.insert(thread.id.clone(), thread.clone()) {
+            state.bytes -= replaced.size();
+        }
+        Ok(())
+    }
+
+    fn get(&self, id: &str, owner: Option<&str>) -> Result<Option<Thread>, String> {
+        Ok(self
+            .lock()?
+            .threads
+            .get(id)
+            .filter(|thread| thread.owner.as_deref() == owner)
+            .cloned())
+    }
+
+    fn append(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+        expected_len: Option<usize>,
+        messages: &[Message],
+    ) -> Result<(), String> {
+        let mut state = self.lock()?;
+        let state = &mut *state;
+        let thread = state
+            .threads
+            .get_mut(id)
+            .filter(|thread| thread.owner.as_deref() == owner)
+            .ok_or_else(|| not_found(id))?;
+        if expected_len.is_some_and(|len| len != thread.messages.len()) {
+            return Err(changed(id));
+        }
+        let size = messages_size(messages);
+        if state.bytes + size > self.max_bytes {
+            return Err(full());
+        }
+        state.bytes += size;
+        thread.messages.extend_from_slice(messages);
+        Ok(())
+    }
+
+    fn list(&self, owner: Option<&str>, limit: usize) -> Result<Vec<ThreadSummary>, String> {
+        let state = self.lock()?;
+        let mut summaries: Vec<ThreadSummary> = state
+            .threads
+            .values()
+            .filter(|thread| thread.owner.as_deref() == owner)
+            .map(|thread| ThreadSummary {
+                id: thread.id.clone(),
+                created_at: thread.created_at,
+                metadata: thread.metadata.clone(),
+                message_count: thread.messages.len(),
+            })
+            .collect();
+        summaries.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+        summaries.truncate(limit);
+        Ok(summaries)
+    }
+
+    fn delete(&self, id: &str, owner: Option<&str>) -> Result<bool, String> {
+        let mut state = self.lock()?;
+        let owned = state
+            .threads
+            .get(id)
+            .is_some_and(|thread| thread.owner.as_deref() == owner);
+        if !owned {
+            return Ok(false);
+        }
+        match state.threads.remove(id) {
+            Some(removed) => {
+                state.bytes -= removed.size();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Threads in a SQLite database, one JSON row per message.
+#[derive(Debug)]
+pub struct SqliteThreadStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteThreadStore {
+    /// Store in the database at `path`, created if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<SqliteThreadStore, String> {
+        let connection = Connection::open(path.as_ref())
+            .map_err(|e| format!("Failed to open thread database {:?}: {}", path.as_ref(), e))?;
+        SqliteThreadStore::with_connection(connection)
+    }
+
+    /// Store in a private in-memory database.
+    pub fn in_memory() -> Result<SqliteThreadStore, String> {
+        let connection = Connection::open_in_memory()
+            .map_err(|e| format!("Failed to open thread database: {}", e))?;
+        SqliteThreadStore::with_connection(connection)
+    }
+
+    fn with_connection(connection: Connection) -> Result<SqliteThreadStore, String> {
+        connection
+            .execute_batch(
+                "PRAGMA foreign_keys = ON;
+                 CREATE TABLE IF NOT EXISTS threads (
+                     id TEXT PRIMARY KEY,
+                     owner TEXT,
+                     created_at INTEGER NOT NULL,
+                     metadata TEXT NOT NULL
+                 );
+                 CREATE TABLE IF NOT EXISTS thread_messages (
+                     thread_id TEXT NOT NULL REFERENCES threads(id) ON DELETE CASCADE,
+                     position INTEGER NOT NULL,
+                     message TEXT NOT NULL,
+                     PRIMARY KEY (thread_id, position)
+                 );",
+            )
+            .map_err(|e| format!("Failed to create the thread tables: {}", e))?;
+        Ok(SqliteThreadStore {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>, String> {
+        self.connection
+            .lock()
+            .map_err(|_| "the thread store is poisoned".to_string())
+    }
+}
+
+/// Insert `messages` into the thread `id` from `position` on.
+fn insert_messages(
+    connection: &Connection,
+    id: &str,
+    position: i64,
+    messages: &[Message],
+) -> rusqlite::Result<()> {
+    let mut statement = connection.prepare_cached(
+        "INSERT INTO thread_messages (thread_id, position, message) VALUES (?1, ?2, ?3)",
+    )?;
+    for (offset, message) in messages.iter().enumerate() {
+        let json = serde_json::to_string(message)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        statement.execute(params![id, position + offset as i64, json])?;
+    }
+    Ok(())
+}
+
+impl ThreadStore for SqliteThreadStore {
+    fn insert(&self, thread: &Thread) -> Result<(), String> {
+        let mut connection = self.lock()?;
+        let transaction = connection
+            .transaction()
+            .map_err(|e| format!("Failed to store the thread: {}", e))?;
+        transaction
+            .execute(
+                "INSERT INTO threads (id, owner, created_at, metadata) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    thread.id,
+                    thread.owner,
+                    thread.created_at,
+                    thread.metadata.to_string()
+                ],
+            )
+            .and_then(|_| insert_messages(&transaction, &thread.id, 0, &thread.messages))
+            .and_then(|_| transaction.commit())
+            .map_err(|e| format!("Failed to store the thread: {}", e))
+    }
+
+    fn get(&self, id: &str, owner: Option<&str>) -> Result<Option<Thread>, String> {
+        let connection = self.lock()?;
+        let row = connection
+            .query_row(
+                "SELECT created_at, metadata FROM threads WHERE id = ?1 AND owner IS ?2",
+                params![id, owner],
+                |row| Ok((row.get::<_, u64>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()
+            .map_err(|e| format!("Failed to read the thread: {}", e))?;
+        let Some((created_at, metadata)) = row else {
+            return Ok(None);
+        };
+        let mut statement = connection
+            .prepare_cached(
+                "SELECT message FROM thread_messages WHERE thread_id = ?1 ORDER BY position",
+            )
+            .map_err(|e| format!("Failed to read the thread: {}", e))?;
+        let messages = statement
+            .query_map(params![id], |row| row.get::<_, String>(0))
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<String>>>())
+            .map_err(|e| format!("Failed to read the thread: {}", e))?
+            .iter()
+            .map(|json| serde_json::from_str(json))
+            .collect::<Result<Vec<Message>, _>>()
+            .map_err(|e| format!("Invalid message in thread '{}': {}", id, e))?;
+        Ok(Some(Thread {
+            id: id.to_string(),
+            owner: owner.map(str::to_string),
+            created_at,
+            metadata: serde_json::from_str(&metadata).unwrap_or(Value::Null),
+            messages,
+        }))
+    }
+
+    fn append(
+        &self,
+        id: &str,
+        owner: Option<&str>,
+        expected_len: Option<usize>,
+        messages: &[Message],
+    ) -> Result<(), String> {
+        let mut connection = self.lock()?;
+        // immediate: the length checked below can't change before the commit, even from
+        // another process sharing the database
+        let transaction = connection
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(|e| format!("Failed to append to the thread: {}", e))?;
+        let exists = transaction
+            .query_row(
+                "SELECT 1 FROM threads WHERE id = ?1 AND owner IS ?2",
+                params![id, owner],
+                |_| Ok(()),
+            )
+            .optional()
+            .map_err(|e| format!("Failed to append to the thread: {}", e))?;
+        if exists.is_none() {
+            return Err(not_found(id));
+        }
+        let next: i64 = transaction
+            .query_row(
+                "SELECT COALESCE(MAX(position) + 1, 0) FROM thread_messages WHERE thread_id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to append to the thread: {}", e))?;
+        if expected_len.is_some_and(|len| len as i64 != next) {
+            return Err(changed(id));
+        }
+        insert_messages(&transaction, id, next, messages)
+            .and_then(|_| transaction.commit())
+            .map_err(|e| format!("Failed to append to the thread: {}", e))
+    }
+
+    fn list(&self, owner: Option<&str>, limit: usize) -> Result<Vec<ThreadSummary>, String> {
+        let connection = self.lock()?;
+        let mut statement = connection
+            .prepare_cached(
+                "SELECT t.id, t.created_at, t.metadata,
+                        (SELECT COUNT(*) FROM thread_messages m WHERE m.thread_id = t.id)
+                 FROM threads t WHERE t.owner IS ?1
+                 ORDER BY t.created_at DESC, t.id LIMIT ?2",
+            )
+            .map_err(|e| format!("Failed to list the threads: {}", e))?;
+        let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+        statement
+            .query_map(params![owner, limit], |row| {
+                Ok(ThreadSummary {
+                    id: row.get(0)?,
+                    created_at: row.get(1)?,
+                    metadata: serde_json::from_str(&row.get::<_, String>(2)?)
+                        .unwrap_or(Value::Null),
+                    message_count: row.get(3)?,
+                })
+            })
+            .and_then(|rows| rows.collect())
+            .map_err(|e| format!("Failed to list the threads: {}", e))
+    }
+
+    fn delete(&self, id: &str, owner: Option<&str>) -> Result<bool, String> {
+        self.lock()?
+            .execute(
+                "DELETE FROM threads WHERE id = ?1 AND owner IS ?2",
+                params![id, owner],
+            )
+            .map(|deleted| deleted > 0)
+            .map_err(|e| format!("Failed to delete the thread: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod threads_tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Instant;
+
+    fn message(role: &str, text: &str) -> Message {
+        Message {
+            role: role.to_string(),
+            input_text: text.to_string(),
+            input_image: None,
+            input_audio: None,
+            input_audio_format: None,
+            input_video: None,
+            input_file: None,
+        }
+    }
+
+    /// Create, append, list and delete through `store`.
+    fn exercise(store: &dyn ThreadStore) {
+        let acme = Some("acme");
+        let thread = store
+            .create(
+                acme.map(str::to_string),
+                json!({ "agent": "planner" }),
+                vec![message("user", "Plan a trip")],
+            )
+            .unwrap();
+        assert!(thread.id.starts_with("thread_"));
+        assert_eq!(thread.id.len(), "thread_".len() + 32);
+        store
+            .append(
+                &thread.id,
+                acme,
+                Some(1),
+                &[message("assistant", "Where to?"), message("user", "Rome")],
+            )
+            .unwrap();
+        assert_eq!(
+            store.append(&thread.id, acme, Some(1), &[message("user", "Late")]),
+            Err(format!(
+                "Thread '{}' changed while it was answered, try again",
+                thread.id
+            ))
+        );
+
+        let stored = store.get(&thread.id, acme).unwrap().unwrap();
+        let texts: Vec<&str> = stored
+            .messages
+            .iter()
+            .map(|message| message.input_text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["Plan a trip", "Where to?", "Rome"]);
+        assert_eq!(stored.metadata, json!({ "agent": "planner" }));
+
+        let listed = store.list(acme, 10).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].message_count, 3);
+
+        // other tenants and anonymous callers don't see the thread
+        for owner in [Some("globex"), None] {
+            assert_eq!(store.get(&thread.id, owner), Ok(None));
+            assert_eq!(store.list(owner, 10), Ok(vec![]));
+            assert_eq!(
+                store.append(&thread.id, owner, None, &[message("user", "hi")]),
+                Err(format!("Thread '{}' not found", thread.id))
+            );
+            assert_eq!(store.delete(&thread.id, owner), Ok(false));
+        }
+
+        assert_eq!(
+            store.append("thread_nope", acme, None, &[message("user", "hi")]),
+            Err("Thread 'thread_nope' not found".to_string())
+        );
+        assert_eq!(store.delete(&thread.id, acme), Ok(true));
+        assert_eq!(store.get(&thread.id, acme), Ok(None));
+        assert_eq!(store.delete(&thread.id, acme), Ok(false));
+    }
+
+    #[test]
+    fn test_memory_thread_store() {
+        let start = Instant::now();
+
+        exercise(&MemoryThreadStore::default());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_memory_thread_store took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_memory_thread_store_is_bounded() {
+        let start = Instant::now();
+
+        let store = MemoryThreadStore::new(2, 20);
+        let first = store
+            .create(None, Value::Null, vec![message("user", "hi")])
+            .unwrap();
+        store
+            .create(None, Value::Null, vec![message("user", "hi")])
+            .unwrap();
+        let full = "The thread store is full, delete threads first".to_string();
+        assert_eq!(
+            store
+                .create(None, Value::Null, vec![])
+                .map(|thread| thread.id),
+            Err(full.clone())
+        );
+
+        // "null" + "hi" + "null" + "hi" = 12 bytes, 8 left
+        assert_eq!(
+            store.append(&first.id, None, None, &[message("assistant", "123456789")]),
+            Err(full.clone())
+        );
+        assert_eq!(
+            store.get(&first.id, None).unwrap().unwrap().messages.len(),
+            1
+        );
+        store
+            .append(&first.id, None, None, &[message("assistant", "12345678")])
+            .unwrap();
+
+        // deleting frees room again
+        assert_eq!(store.delete(&first.id, None), Ok(true));
+        store
+            .create(None, Value::Null, vec![message("user", "hello")])
+            .unwrap();
+
+        let duration = Instant::now() - start;
+        eprintln!("test_memory_thread_store_is_bounded took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_sqlite_thread_store() {
+        let start = Instant::now();
+
+        exercise(&SqliteThreadStore::in_memory().unwrap());
+
+        let duration = Instant::now() - start;
+        eprintln!("test_sqlite_thread_store took: {:?}", duration);
+    }
+}
@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::model::Price;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+/// Token usage and cost of a model run, as reported by the provider.
+pub struct Usage {
+    /// Tokens of the prompt, when the provider reports them.
+    pub prompt_tokens: Option<u64>,
+
+    /// Tokens of the answer, when the provider reports them.
+    pub completion_tokens: Option<u64>,
+
+    /// Cost in USD, computed from the model's price.
+    pub cost: Option<f64>,
+
+    /// Tokens of the answer spent reasoning, when the provider reports them.
+    #[serde(default)]
+    pub reasoning_tokens: Option<u64>,
+}
+
+impl Usage {
+    /// Read the `usage` object of an OpenAI-like (OpenRouter) response.
+    pub fn from_openrouter(response: &Value) -> Usage {
+        let usage = &response["usage"];
+        Usage {
+            prompt_tokens: usage["prompt_tokens"].as_u64(),
+            completion_tokens: usage["completion_tokens"].as_u64(),
+            cost: None,
+            reasoning_tokens: usage["completion_tokens_details"]["reasoning_tokens"].as_u64(),
+        }
+    }
+
+    /// Read the `metrics` object of a Replicate prediction.
+    pub fn from_replicate(prediction: &Value) -> Usage {
+        let metrics = &prediction["metrics"];
+        Usage {
+            prompt_tokens: metrics["input_token_count"].as_u64(),
+            completion_tokens: metrics["output_token_count"].as_u64(),
+            cost: None,
+            reasoning_tokens: None,
+        }
+    }
+
+    /// Fill `cost` from `price`.
+    ///
+    /// Token prices need both token counts; a per-run price doesn't need any.
+    pub fn priced(mut self, price: &Price) -> Usage {
+        self.cost = match (
+            price.run_price(),
+            self.prompt_tokens,
+            self.completion_tokens,
+        ) {
+            (Some(run_price), _, _) => Some(*run_price),
+            (None, Some(prompt), Some(completion)) => {
+                let input = price.input_price_per_million(prompt);
+                let output = price.output_price_per_million(completion);
+                match (input, output) {
+                    (Some(input), Some(output)) => {
+                        Some((prompt as f64 * input + completion as f64 * output) / 1_000_000.0)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        self
+    }
+}
+
+#[cfg(test)]
+mod usage_tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Instant;
+
+    #[test]
+    fn test_usage_from_providers_and_cost() {
+        let start = Instant::now();
+
+        let openrouter = json!({ "usage": {
+            "prompt_tokens": 1_000_000,
+            "completion_tokens": 500_000,
+            "completion_tokens_details": { "reasoning_tokens": 200_000 }
+        }});
+        let usage = Usage::from_openrouter(&openrouter).priced(&Price::PerIoFlat {
+            input_price: 2.0,
+            output_price: 10.0,
+        });
+        assert_eq!(usage.prompt_tokens, Some(1_000_000));
+        assert_eq!(usage.reasoning_tokens, Some(200_000));
+        assert_eq!(usage.cost, Some(7.0));
+
+        let replicate = json!({ "metrics": { "input_token_count": 10, "output_token_count": 4 } });
+        let usage = Usage::from_replicate(&replicate);
+        assert_eq!(usage.completion_tokens, Some(4));
+
+        let unknown = Usage::default().priced(&Price::PerIoFlat {
+            input_price: 2.0,
+            output_price: 10.0,
+        });
+        assert_eq!(unknown.cost, None);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_usage_from_providers_and_cost took: {:?}", duration);
+    }
+}
@@ -0,0 +1,210 @@
+use ::image::codecs::gif::GifDecoder;
+use ::image::{AnimationDecoder, DynamicImage, ImageFormat};
+use base64::prelude::*;
+use std::io::Cursor;
+
+/// Frames sampled from a video sent to a model without video input.
+pub const KEYFRAMES: usize = 8;
+
+/// Pixels (width × height × frames) of a GIF decoded at most: larger GIFs are refused
+/// before decoding a single frame.
+const MAX_GIF_PIXELS: u64 = 250_000_000;
+
+/// Whether `keyframes` can sample the video `bytes`: only GIFs can be decoded without
+/// ffmpeg, which isn't part of the Lambda runtime.
+pub fn can_sample(bytes: &[u8]) -> bool {
+    ::image::guess_format(bytes).ok() == Some(ImageFormat::Gif)
+}
+
+/// Keyframes of the video `bytes`: `count` frames spread evenly over it, as image
+/// `data:` URLs.
+///
+/// Only GIFs are decoded (see `can_sample`): other containers (MP4, WebM...) are
+/// refused, and routed by `Polytheus` to a model taking video instead.
+pub fn keyframes(bytes: &[u8], count: usize) -> Result<Vec<String>, String> {
+    if !can_sample(bytes) {
+        return Err("only GIF videos can be sampled for models without video input".to_string());
+    }
+    let frames = gif_frames(bytes, count)?;
+    if frames.is_empty() {
+        return Err("the video has no frame".to_string());
+    }
+    Ok(frames
+        .into_iter()
+        .map(|frame| format!("data:image/png;base64,{}", BASE64_STANDARD.encode(frame)))
+        .collect())
+}
+
+/// Indices of `count` items spread evenly over `len` items: the middle one of each of
+/// `count` equal spans.
+fn picks(len: usize, count: usize) -> Vec<usize> {
+    if len <= count {
+        return (0..len).collect();
+    }
+    (0..count)
+        .map(|i| (2 * i + 1) * len / (2 * count))
+        .collect()
+}
+
+/// Keyframes of an animated GIF, as PNG bytes.
+///
+/// Frames are decoded one at a time and only the picked ones are kept, so memory holds
+/// `count` frames whatever the length of the GIF.
+fn gif_frames(bytes: &[u8], count: usize) -> Result<Vec<Vec<u8>>, String> {
+    let (width, height, frame_count) = gif_size(bytes)?;
+    let pixels = u64::from(width) * u64::from(height) * frame_count as u64;
+    if pixels > MAX_GIF_PIXELS {
+        return Err(format!(
+            "the GIF is too large to sample ({}x{} pixels, {} frames)",
+            width, height, frame_count
+        ));
+    }
+    let picks = picks(frame_count, count);
+    let decoder = GifDecoder::new(Cursor::new(bytes)).map_err(|e| format!("invalid GIF: {}", e))?;
+    let mut frames = vec![];
+    for (index, frame) in decoder.into_frames().enumerate() {
+        let frame = frame.map_err(|e| format!("invalid GIF: {}", e))?;
+        if !picks.contains(&index) {
+            continue;
+        }
+        let mut encoded = Cursor::new(vec![]);
+        DynamicImage::ImageRgba8(frame.into_buffer())
+            .write_to(&mut encoded, ImageFormat::Png)
+            .map_err(|e| format!("failed to encode a frame: {}", e))?;
+        frames.push(encoded.into_inner());
+        if frames.len() == picks.len() {
+            break;
+        }
+    }
+    Ok(frames)
+}
+
+/// Width, height and number of frames of a GIF, read from its block structure without
+/// decoding any image data.
+fn gif_size(bytes: &[u8]) -> Result<(u16, u16, usize), String> {
+    let truncated = || "invalid GIF: truncated".to_string();
+    let byte = |at: usize| bytes.get(at).copied().ok_or_else(truncated);
+    // the data sub-blocks starting at `at`, each prefixed by its length, up to an
+    // empty one: the position after them
+    let skip_sub_blocks = |mut at: usize| -> Result<usize, String> {
+        loop {
+            let len = usize::from(byte(at)?);
+            at += 1 + len;
+            if len == 0 {
+                return Ok(at);
+            }
+        }
+    };
+    // header (6 bytes) and logical screen descriptor (7 bytes)
+    let width = u16::from_le_bytes([byte(6)?, byte(7)?]);
+    let height = u16::from_le_bytes([byte(8)?, byte(9)?]);
+    let color_table_len = |flags: u8| match flags & 0x80 {
+        0 => 0,
+        _ => 3 << ((flags & 0x07) + 1),
+    };
+    let mut at = 13 + color_table_len(byte(10)?);
+    let mut frames = 0;
+    loop {
+        match byte(at)? {
+            // extension: label then sub-blocks
+            0x21 => at = skip_sub_blocks(at + 2)?,
+            // image descriptor (10 bytes), local color table, LZW code size, sub-blocks
+            0x2C => {
+                at += 10 + color_table_len(byte(at + 9)?);
+                at = skip_sub_blocks(at + 1)?;
+                frames += 1;
+            }
+            // trailer
+            0x3B => return Ok((width, height, frames)),
+            block => return Err(format!("invalid GIF: unknown block 0x{:02X}", block)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod video_tests {
+    use super::*;
+    use crate::polytheus::media;
+    use ::image::codecs::gif::GifEncoder;
+    use ::image::{Frame, Rgba, RgbaImage};
+    use std::time::Instant;
+
+    #[test]
+    fn test_picks() {
+        let start = Instant::now();
+
+        assert_eq!(picks(10, 3), vec![1, 5, 8]);
+        assert_eq!(picks(2, 8), vec![0, 1]);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_picks took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_gif_keyframes() {
+        let start = Instant::now();
+
+        let mut gif = vec![];
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            for shade in 0..20u8 {
+                let frame = RgbaImage::from_pixel(16, 16, Rgba([shade * 10, 0, 0, 255]));
+                encoder.encode_frame(Frame::new(frame)).unwrap();
+            }
+        }
+        let frames = keyframes(&gif, 4).unwrap();
+        assert_eq!(frames.len(), 4);
+        assert!(frames[0].starts_with("data:image/png;base64,"));
+        let first = media::decode_data_url(&frames[0]).unwrap();
+        let first = ::image::load_from_memory(&first).unwrap().to_rgba8();
+        assert_eq!(first.dimensions(), (16, 16));
+        // the third frame, give or take the GIF palette
+        assert!(first.get_pixel(0, 0)[0].abs_diff(20) <= 4);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_gif_keyframes took: {:?}", duration);
+    }
+
+    #[test]
+    fn test_large_gifs_are_refused_before_decoding() {
+        let start = Instant::now();
+
+        let mut gif = vec![];
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            for _ in 0..3 {
+                let frame = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+                encoder.encode_frame(Frame::new(frame)).unwrap();
+            }
+        }
+        assert_eq!(gif_size(&gif), Ok((4, 4, 3)));
+        // claim a 65535x65535 screen: far over the budget with only 3 frames
+        gif[6..10].copy_from_slice(&[0xFF; 4]);
+        assert_eq!(
+            keyframes(&gif, 2),
+            Err("the GIF is too large to sample (65535x65535 pixels, 3 frames)".to_string())
+        );
+        assert!(gif_size(&gif[..gif.len() - 1]).is_err());
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_large_gifs_are_refused_before_decoding took: {:?}",
+            duration
+        );
+    }
+
+    #[test]
+    fn test_other_videos_are_refused() {
+        let start = Instant::now();
+
+        // start of an MP4 `ftyp` box
+        let mp4 = b"\x00\x00\x00\x18ftypmp42\x00\x00\x00\x00mp42isom";
+        assert_eq!(
+            keyframes(mp4, 4),
+            Err("only GIF videos can be sampled for models without video input".to_string())
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_other_videos_are_refused took: {:?}", duration);
+    }
+}
@@ -0,0 +1,193 @@
+//! Logging and telemetry setup shared by every hosting method.
+//!
+//! Logs are emitted with `tracing` and formatted as JSON by default so CloudWatch can
+//! index their fields. Prompts and attachments are redacted unless explicitly enabled.
+//! The same spans are exported as OpenTelemetry traces by `otel`.
+use lambda_http::tracing::subscriber::layer::SubscriberExt;
+use lambda_http::tracing::subscriber::util::SubscriberInitExt;
+use lambda_http::tracing::subscriber::{self, filter::EnvFilter, Layer};
+use serde_json::Value;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub mod otel;
+use otel::OtelConfig;
+
+/// Whether prompt and attachment content may appear in the logs.
+static LOG_CONTENT: AtomicBool = AtomicBool::new(false);
+
+/// JSON keys whose string values carry user content (prompts, attachments, answers).
+const CONTENT_KEYS: [&str; 16] = [
+    "text",
+    "content",
+    "url",
+    "data",
+    "prompt",
+    "system_prompt",
+    "input_text",
+    "input_image",
+    "input_audio",
+    "input_video",
+    "input_file",
+    "file_data",
+    "filename",
+    "image",
+    "audio",
+    "output",
+];
+
+/// Output format of the logs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogFormat {
+    /// One JSON object per line (CloudWatch friendly).
+    Json,
+
+    /// Human readable lines, for local development.
+    Text,
+}
+
+/// Logging configuration.
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Output format.
+    pub format: LogFormat,
+
+    /// Verbosity, as an `EnvFilter` directive (e.g. "info", "backend=debug").
+    pub level: String,
+
+    /// Log prompts and attachments instead of redacting them.
+    pub log_content: bool,
+}
+
+impl LogConfig {
+    /// Read the configuration from `POLYTHEUS_LOG_FORMAT` ("json" or "text"),
+    /// `POLYTHEUS_LOG_LEVEL` (falling back on `AWS_LAMBDA_LOG_LEVEL` then `RUST_LOG`)
+    /// and `POLYTHEUS_LOG_CONTENT`.
+    pub fn from_env() -> LogConfig {
+        let format = match env::var("POLYTHEUS_LOG_FORMAT") {
+            Ok(f) if f.eq_ignore_ascii_case("text") => LogFormat::Text,
+            _ => LogFormat::Json,
+        };
+        let level = env::var("POLYTHEUS_LOG_LEVEL")
+            .or_else(|_| env::var("AWS_LAMBDA_LOG_LEVEL"))
+            .or_else(|_| env::var("RUST_LOG"))
+            .unwrap_or_else(|_| "info".to_string());
+        let log_content = env::var("POLYTHEUS_LOG_CONTENT")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        LogConfig {
+            format,
+            level,
+            log_content,
+        }
+    }
+}
+
+/// Install the global `tracing` subscriber: logs described by `config`, and spans
+/// exported as described by `otel`.
+///
+/// Calling it more than once keeps the first subscriber.
+pub fn init(config: &LogConfig, otel: OtelConfig) {
+    LOG_CONTENT.store(config.log_content, Ordering::Relaxed);
+
+    let filter =
+        EnvFilter::try_new(config.level.to_lowercase()).unwrap_or_else(|_| EnvFilter::new("info"));
+    let fmt_layer = subscriber::fmt::layer()
+        .with_target(false)
+        // Lambda already timestamps every line
+        .without_time();
+    let fmt_layer = match config.format {
+        LogFormat::Json => fmt_layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .boxed(),
+        LogFormat::Text => fmt_layer.boxed(),
+    };
+
+    let _ = subscriber::registry()
+        .with(fmt_layer)
+        .with(otel::init(otel))
+        .with(filter)
+        .try_init();
+}
+
+/// Copy of `value` safe to log: user content is replaced by its size.
+///
+/// Returned unchanged when content logging was enabled in `init`.
+pub fn redact(value: &Value) -> Value {
+    if LOG_CONTENT.load(Ordering::Relaxed) {
+        return value.clone();
+    }
+    redact_value(value, false)
+}
+
+/// Redact `value`; `sensitive` is true when it is (an array) directly under a content key.
+fn redact_value(value: &Value, sensitive: bool) -> Value {
+    match value {
+        Value::String(s) if sensitive => Value::String(format!("[redacted: {} bytes]", s.len())),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| redact_value(item, sensitive))
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, v)| {
+                    let sensitive = CONTENT_KEYS.contains(&key.as_str());
+                    (key.clone(), redact_value(v, sensitive))
+                })
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod telemetry_tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Instant;
+
+    #[test]
+    fn test_redact_hides_content_but_keeps_structure() {
+        let start = Instant::now();
+
+        let body = json!({
+            "model": "gpt-4o",
+            "stream": false,
+            "input": { "messages": [{
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": "secret" },
+                    { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } },
+                    { "type": "file", "file": {
+                        "filename": "payslip.pdf",
+                        "file_data": "data:application/pdf;base64,JVBE"
+                    } }
+                ]
+            }]}
+        });
+        let redacted = redact(&body);
+
+        assert_eq!(redacted["model"], json!("gpt-4o"));
+        let message = &redacted["input"]["messages"][0];
+        assert_eq!(message["role"], json!("user"));
+        assert_eq!(message["content"][0]["type"], json!("text"));
+        assert_eq!(message["content"][0]["text"], json!("[redacted: 6 bytes]"));
+        assert_eq!(
+            message["content"][1]["image_url"]["url"],
+            json!("[redacted: 26 bytes]")
+        );
+        let file = &message["content"][2]["file"];
+        assert_eq!(file["filename"], json!("[redacted: 11 bytes]"));
+        assert_eq!(file["file_data"], json!("[redacted: 32 bytes]"));
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_redact_hides_content_but_keeps_structure took: {:?}",
+            duration
+        );
+    }
+}
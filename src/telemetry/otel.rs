@@ -0,0 +1,844 @@
+//! OpenTelemetry export of the request pipeline.
+//!
+//! `OtelLayer` turns closed `tracing` spans into OTLP spans, and `record_run` aggregates
+//! per provider/model metrics. Both are buffered in memory and sent by `flush`, which
+//! hosting methods call at the end of every request (a frozen Lambda can't export later).
+//! The payloads use the OTLP/HTTP JSON encoding, so no protobuf or SDK dependency is needed.
+use lambda_http::tracing::subscriber::layer::{Context, Layer};
+use lambda_http::tracing::subscriber::registry::LookupSpan;
+use reqwest::Client;
+use serde_json::{json, Map, Value};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::env;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+
+use crate::polytheus::Usage;
+
+/// Upper bounds (in milliseconds) of the latency histogram buckets.
+const LATENCY_BOUNDS_MS: [f64; 11] = [
+    50.0, 100.0, 250.0, 500.0, 1_000.0, 2_500.0, 5_000.0, 10_000.0, 30_000.0, 60_000.0, 120_000.0,
+];
+
+/// Timeout of an export when `OTEL_EXPORTER_OTLP_TIMEOUT` is not set: shorter than the
+/// OTLP default, as every response waits for the export.
+const DEFAULT_EXPORT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Pipeline installed by `init`, used by the free functions of this module.
+static PIPELINE: OnceLock<Pipeline> = OnceLock::new();
+
+/// Where the telemetry is sent.
+#[derive(Debug, Clone)]
+pub enum ExporterConfig {
+    /// Telemetry is dropped.
+    None,
+
+    /// Telemetry is kept in memory (tests, local debugging).
+    InMemory(InMemoryExporter),
+
+    /// Telemetry is posted to an OTLP/HTTP collector.
+    OtlpHttp {
+        /// Collector base URL (e.g. "http://localhost:4318").
+        endpoint: String,
+
+        /// Extra headers sent with every export (e.g. an API key).
+        headers: Vec<(String, String)>,
+
+        /// Time allowed to export traces and metrics at each flush.
+        timeout: Duration,
+    },
+}
+
+/// OpenTelemetry configuration.
+#[derive(Debug, Clone)]
+pub struct OtelConfig {
+    /// Value of the `service.name` resource attribute.
+    pub service_name: String,
+
+    /// Destination of the telemetry.
+    pub exporter: ExporterConfig,
+}
+
+impl OtelConfig {
+    /// Read the standard `OTEL_SERVICE_NAME`, `OTEL_EXPORTER_OTLP_ENDPOINT`,
+    /// `OTEL_EXPORTER_OTLP_HEADERS` and `OTEL_EXPORTER_OTLP_TIMEOUT` (milliseconds)
+    /// variables.
+    ///
+    /// `POLYTHEUS_OTEL_EXPORTER` ("otlp", "memory" or "none") picks the exporter; it
+    /// defaults to "otlp" when an endpoint is set and to "none" otherwise.
+    pub fn from_env() -> OtelConfig {
+        let endpoint = env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok();
+        let kind = env::var("POLYTHEUS_OTEL_EXPORTER")
+            .unwrap_or_else(|_| if endpoint.is_some() { "otlp" } else { "none" }.to_string());
+        let exporter = match kind.to_lowercase().as_str() {
+            "memory" => ExporterConfig::InMemory(InMemoryExporter::default()),
+            "otlp" => ExporterConfig::OtlpHttp {
+                endpoint: endpoint.unwrap_or_else(|| "http://localhost:4318".to_string()),
+                headers: env::var("OTEL_EXPORTER_OTLP_HEADERS")
+                    .map(|h| parse_headers(&h))
+                    .unwrap_or_default(),
+                timeout: env::var("OTEL_EXPORTER_OTLP_TIMEOUT")
+                    .ok()
+                    .and_then(|ms| ms.trim().parse::<u64>().ok())
+                    .filter(|ms| *ms > 0)
+                    .map(Duration::from_millis)
+                    .unwrap_or(DEFAULT_EXPORT_TIMEOUT),
+            },
+            _ => ExporterConfig::None,
+        };
+        OtelConfig {
+            service_name: env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "polytheus".to_string()),
+            exporter,
+        }
+    }
+}
+
+/// Parse the `key1=value1,key2=value2` format of `OTEL_EXPORTER_OTLP_HEADERS`.
+fn parse_headers(headers: &str) -> Vec<(String, String)> {
+    headers
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect()
+}
+
+/// Exporter keeping every OTLP payload it receives.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryExporter {
+    traces: Arc<Mutex<Vec<Value>>>,
+    metrics: Arc<Mutex<Vec<Value>>>,
+}
+
+impl InMemoryExporter {
+    /// Every span exported so far, in OTLP JSON.
+    pub fn spans(&self) -> Vec<Value> {
+        let traces = self.traces.lock().map(|t| t.clone()).unwrap_or_default();
+        traces
+            .iter()
+            .flat_map(|payload| {
+                payload["resourceSpans"][0]["scopeSpans"][0]["spans"]
+                    .as_array()
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Last metrics payload exported, in OTLP JSON.
+    pub fn last_metrics(&self) -> Option<Value> {
+        self.metrics.lock().ok().and_then(|m| m.last().cloned())
+    }
+}
+
+/// A closed span, ready to be exported.
+#[derive(Debug, Clone)]
+struct SpanData {
+    trace_id: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    name: &'static str,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Map<String, Value>,
+}
+
+/// A span still open, stored in the span's extensions.
+struct OpenSpan {
+    trace_id: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    start: SystemTime,
+    attributes: Map<String, Value>,
+}
+
+/// Aggregated metrics of the runs of one model.
+#[derive(Debug, Clone)]
+struct RunStats {
+    succeeded: u64,
+    failed: u64,
+    latency_sum_ms: f64,
+    latency_buckets: [u64; LATENCY_BOUNDS_MS.len() + 1],
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    cost: f64,
+}
+
+impl Default for RunStats {
+    fn default() -> RunStats {
+        RunStats {
+            succeeded: 0,
+            failed: 0,
+            latency_sum_ms: 0.0,
+            latency_buckets: [0; LATENCY_BOUNDS_MS.len() + 1],
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            cost: 0.0,
+        }
+    }
+}
+
+/// Buffers spans and metrics, and sends them to the configured exporter.
+#[derive(Debug)]
+pub struct Pipeline {
+    config: OtelConfig,
+    client: Client,
+    start: SystemTime,
+    spans: Arc<Mutex<Vec<SpanData>>>,
+    /// Run statistics keyed by (provider, model).
+    runs: Mutex<HashMap<(String, String), RunStats>>,
+}
+
+impl Pipeline {
+    /// Build a pipeline exporting to `config.exporter`.
+    pub fn new(config: OtelConfig) -> Pipeline {
+        let timeout = match &config.exporter {
+            ExporterConfig::OtlpHttp { timeout, .. } => *timeout,
+            _ => DEFAULT_EXPORT_TIMEOUT,
+        };
+        Pipeline {
+            config,
+            client: Client::builder()
+                .timeout(timeout)
+                .build()
+                .unwrap_or_default(),
+            start: SystemTime::now(),
+            spans: Arc::new(Mutex::new(Vec::new())),
+            runs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `tracing` layer feeding this pipeline with the spans it closes.
+    pub fn layer(&self) -> OtelLayer {
+        OtelLayer {
+            spans: self.spans.clone(),
+        }
+    }
+
+    /// Record the outcome of one model run.
+    pub fn record_run(&self, provider: &str, model: &str, latency_ms: u64, usage: Option<&Usage>) {
+        let Ok(mut runs) = self.runs.lock() else {
+            return;
+        };
+        let stats = runs
+            .entry((provider.to_string(), model.to_string()))
+            .or_default();
+
+        let latency = latency_ms as f64;
+        stats.latency_sum_ms += latency;
+        let bucket = LATENCY_BOUNDS_MS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(LATENCY_BOUNDS_MS.len());
+        stats.latency_buckets[bucket] += 1;
+
+        match usage {
+            Some(usage) => {
+                stats.succeeded += 1;
+                stats.prompt_tokens += usage.prompt_tokens.unwrap_or(0);
+                stats.completion_tokens += usage.completion_tokens.unwrap_or(0);
+                stats.cost += usage.cost.unwrap_or(0.0);
+            }
+            None => stats.failed += 1,
+        }
+    }
+
+    /// Send the buffered spans and the current metrics to the exporter.
+    ///
+    /// Metrics are sent even when the traces can't be, and an OTLP export gives up after
+    /// its timeout.
+    pub async fn flush(&self) -> Result<(), String> {
+        let spans = self
+            .spans
+            .lock()
+            .map(|mut spans| std::mem::take(&mut *spans))
+            .unwrap_or_default();
+        let traces = (!spans.is_empty()).then(|| self.encode_spans(&spans));
+        let metrics = self.encode_metrics();
+
+        match &self.config.exporter {
+            ExporterConfig::None => Ok(()),
+            ExporterConfig::InMemory(exporter) => {
+                if let (Some(traces), Ok(mut exported)) = (traces, exporter.traces.lock()) {
+                    exported.push(traces);
+                }
+                if let (Some(metrics), Ok(mut exported)) = (metrics, exporter.metrics.lock()) {
+                    exported.push(metrics);
+                }
+                Ok(())
+            }
+            ExporterConfig::OtlpHttp {
+                endpoint,
+                headers,
+                timeout,
+            } => {
+                let endpoint = endpoint.trim_end_matches('/');
+                let export = async {
+                    let mut errors = vec![];
+                    if let Some(traces) = traces {
+                        let url = format!("{}/v1/traces", endpoint);
+                        if let Err(e) = self.post(&url, headers, &traces).await {
+                            errors.push(e);
+                        }
+                    }
+                    if let Some(metrics) = metrics {
+                        let url = format!("{}/v1/metrics", endpoint);
+                        if let Err(e) = self.post(&url, headers, &metrics).await {
+                            errors.push(e);
+                        }
+                    }
+                    match errors.is_empty() {
+                        true => Ok(()),
+                        false => Err(errors.join("; ")),
+                    }
+                };
+                tokio::time::timeout(*timeout, export).await.map_err(|_| {
+                    format!(
+                        "Telemetry export to {} timed out after {:?}",
+                        endpoint, timeout
+                    )
+                })?
+            }
+        }
+    }
+
+    /// Post one OTLP/HTTP JSON payload.
+    async fn post(
+        &self,
+        url: &str,
+        headers: &[(String, String)],
+        payload: &Value,
+    ) -> Result<(), String> {
+        let mut request = self.client.post(url).json(payload);
+        for (key, value) in headers {
+            request = request.header(key, value);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Failed to export telemetry to {}: {}", url, e))?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "Telemetry export to {} failed with status: {}",
+                url,
+                response.status()
+            ));
+        }
+        Ok(())
+    }
+
+    /// `resource` object shared by traces and metrics.
+    fn resource(&self) -> Value {
+        json!({ "attributes": [attribute("service.name", &json!(self.config.service_name))] })
+    }
+
+    /// Encode spans as an OTLP `ExportTraceServiceRequest`.
+    fn encode_spans(&self, spans: &[SpanData]) -> Value {
+        let spans: Vec<Value> = spans
+            .iter()
+            .map(|span| {
+                let kind = match span.attributes.get("otel.kind").and_then(|k| k.as_str()) {
+                    Some("server") => 2,
+                    Some("client") => 3,
+                    _ => 1,
+                };
+                let status = match span.attributes.get("status").and_then(|s| s.as_str()) {
+                    Some("ok") => 1,
+                    Some("error") => 2,
+                    _ => 0,
+                };
+                let attributes: Vec<Value> = span
+                    .attributes
+                    .iter()
+                    .filter(|(key, _)| key.as_str() != "otel.kind")
+                    .map(|(key, value)| attribute(key, value))
+                    .collect();
+                let mut encoded = json!({
+                    "traceId": span.trace_id,
+                    "spanId": span.span_id,
+                    "name": span.name,
+                    "kind": kind,
+                    "startTimeUnixNano": unix_nanos(span.start).to_string(),
+                    "endTimeUnixNano": unix_nanos(span.end).to_string(),
+                    "attributes": attributes,
+                    "status": { "code": status }
+                });
+                if let Some(parent) = &span.parent_span_id {
+                    encoded["parentSpanId"] = json!(parent);
+                }
+                encoded
+            })
+            .collect();
+
+        json!({ "resourceSpans": [{
+            "resource": self.resource(),
+            "scopeSpans": [{ "scope": { "name": "polytheus" }, "spans": spans }]
+        }]})
+    }
+
+    /// Encode the cumulative run metrics as an OTLP `ExportMetricsServiceRequest`.
+    fn encode_metrics(&self) -> Option<Value> {
+        let runs = self.runs.lock().ok()?.clone();
+        if runs.is_empty() {
+            return None;
+        }
+        let start = unix_nanos(self.start).to_string();
+        let now = unix_nanos(SystemTime::now()).to_string();
+        let point = |attrs: Vec<Value>, value: Value| {
+            let mut point = json!({
+                "attributes": attrs,
+                "startTimeUnixNano": start,
+                "timeUnixNano": now
+            });
+            if let Some(map) = point.as_object_mut() {
+                if let Some(value_map) = value.as_object() {
+                    map.extend(value_map.clone());
+                }
+            }
+            point
+        };
+
+        let (mut requests, mut errors, mut durations, mut tokens, mut costs) =
+            (vec![], vec![], vec![], vec![], vec![]);
+        for ((provider, model), stats) in &runs {
+            let attrs = |extra: Option<(&str, &str)>| {
+                let mut attrs = vec![
+                    attribute("provider", &json!(provider)),
+                    attribute("model", &json!(model)),
+                ];
+                if let Some((key, value)) = extra {
+                    attrs.push(attribute(key, &json!(value)));
+                }
+                attrs
+            };
+            requests.push(point(
+                attrs(Some(("status", "ok"))),
+                json!({ "asInt": stats.succeeded.to_string() }),
+            ));
+            requests.push(point(
+                attrs(Some(("status", "error"))),
+                json!({ "asInt": stats.failed.to_string() }),
+            ));
+            errors.push(point(
+                attrs(None),
+                json!({ "asInt": stats.failed.to_string() }),
+            ));
+            durations.push(point(
+                attrs(None),
+                json!({
+                    "count": (stats.succeeded + stats.failed).to_string(),
+                    "sum": stats.latency_sum_ms,
+                    "bucketCounts": stats.latency_buckets.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+                    "explicitBounds": LATENCY_BOUNDS_MS
+                }),
+            ));
+            tokens.push(point(
+                attrs(Some(("type", "prompt"))),
+                json!({ "asInt": stats.prompt_tokens.to_string() }),
+            ));
+            tokens.push(point(
+                attrs(Some(("type", "completion"))),
+                json!({ "asInt": stats.completion_tokens.to_string() }),
+            ));
+            costs.push(point(attrs(None), json!({ "asDouble": stats.cost })));
+        }
+
+        let sum = |name: &str, unit: &str, points: Vec<Value>| {
+            json!({
+                "name": name,
+                "unit": unit,
+                "sum": { "aggregationTemporality": 2, "isMonotonic": true, "dataPoints": points }
+            })
+        };
+        let metrics = vec![
+            sum("polytheus.requests", "1", requests),
+            sum("polytheus.errors", "1", errors),
+            json!({
+                "name": "polytheus.request.duration",
+                "unit": "ms",
+                "histogram": { "aggregationTemporality": 2, "dataPoints": durations }
+            }),
+            sum("polytheus.tokens", "1", tokens),
+            sum("polytheus.cost", "USD", costs),
+        ];
+
+        Some(json!({ "resourceMetrics": [{
+            "resource": self.resource(),
+            "scopeMetrics": [{ "scope": { "name": "polytheus" }, "metrics": metrics }]
+        }]}))
+    }
+}
+
+/// Encode a key/value pair as an OTLP `KeyValue`.
+fn attribute(key: &str, value: &Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) => json!({ "doubleValue": n.as_f64() }),
+        Value::String(s) => json!({ "stringValue": s }),
+        other => json!({ "stringValue": other.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+/// Nanoseconds since the Unix epoch.
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+/// Random identifier of `bytes` bytes, hex encoded.
+pub fn random_id(bytes: usize) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut id = String::with_capacity(bytes * 2);
+    while id.len() < bytes * 2 {
+        // RandomState is seeded randomly per process: no need for a rand dependency.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(unix_nanos(SystemTime::now()));
+        id.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    id.truncate(bytes * 2);
+    id
+}
+
+/// Collects span fields into a JSON map.
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+}
+
+/// `tracing` layer turning spans into OTLP spans.
+pub struct OtelLayer {
+    spans: Arc<Mutex<Vec<SpanData>>>,
+}
+
+impl<S> Layer<S> for OtelLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent_ids = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<OpenSpan>()
+                .map(|open| (open.trace_id.clone(), open.span_id.clone()))
+        });
+        let (trace_id, parent_span_id) = match parent_ids {
+            Some((trace_id, parent_id)) => (trace_id, Some(parent_id)),
+            None => (random_id(16), None),
+        };
+
+        let mut attributes = Map::new();
+        attrs.record(&mut JsonVisitor(&mut attributes));
+        span.extensions_mut().insert(OpenSpan {
+            trace_id,
+            span_id: random_id(8),
+            parent_span_id,
+            start: SystemTime::now(),
+            attributes,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(open) = span.extensions_mut().get_mut::<OpenSpan>() {
+                values.record(&mut JsonVisitor(&mut open.attributes));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(open) = span.extensions_mut().remove::<OpenSpan>() else {
+            return;
+        };
+        if let Ok(mut spans) = self.spans.lock() {
+            spans.push(SpanData {
+                trace_id: open.trace_id,
+                span_id: open.span_id,
+                parent_span_id: open.parent_span_id,
+                name: span.name(),
+                start: open.start,
+                end: SystemTime::now(),
+                attributes: open.attributes,
+            });
+        }
+    }
+}
+
+/// Install the process-wide pipeline and return the layer to add to the subscriber.
+///
+/// Returns `None` when a pipeline was already installed.
+pub fn init(config: OtelConfig) -> Option<OtelLayer> {
+    let mut layer = None;
+    PIPELINE.get_or_init(|| {
+        let pipeline = Pipeline::new(config);
+        layer = Some(pipeline.layer());
+        pipeline
+    });
+    layer
+}
+
+/// Record a model run on the process-wide pipeline, if any.
+///
+/// `usage` is `None` when the run failed.
+pub fn record_run(provider: &str, model: &str, latency_ms: u64, usage: Option<&Usage>) {
+    if let Some(pipeline) = PIPELINE.get() {
+        pipeline.record_run(provider, model, latency_ms, usage);
+    }
+}
+
+/// Export what the process-wide pipeline buffered, if any.
+pub async fn flush() -> Result<(), String> {
+    match PIPELINE.get() {
+        Some(pipeline) => pipeline.flush().await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod otel_tests {
+    use super::*;
+    use lambda_http::tracing::subscriber::layer::SubscriberExt;
+    use lambda_http::tracing::subscriber::registry;
+    use std::time::Instant;
+    use tracing::info_span;
+
+    fn memory_pipeline() -> (Pipeline, InMemoryExporter) {
+        let exporter = InMemoryExporter::default();
+        let pipeline = Pipeline::new(OtelConfig {
+            service_name: "test".to_string(),
+            exporter: ExporterConfig::InMemory(exporter.clone()),
+        });
+        (pipeline, exporter)
+    }
+
+    #[tokio::test]
+    async fn test_spans_are_exported_with_parents() {
+        let start = Instant::now();
+
+        let (pipeline, exporter) = memory_pipeline();
+        let subscriber = registry().with(pipeline.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            let root = info_span!(
+                "api.router",
+                otel.kind = "server",
+                path = "/v1/chat/completions"
+            );
+            root.in_scope(|| {
+                let run = info_span!(
+                    "polytheus.run",
+                    model = "gpt-4o",
+                    status = tracing::field::Empty
+                );
+                run.record("status", "error");
+                run.in_scope(|| {});
+            });
+        });
+        pipeline.flush().await.unwrap();
+
+        let spans = exporter.spans();
+        assert_eq!(spans.len(), 2);
+        let run = spans.iter().find(|s| s["name"] == "polytheus.run").unwrap();
+        let root = spans.iter().find(|s| s["name"] == "api.router").unwrap();
+        assert_eq!(run["traceId"], root["traceId"]);
+        assert_eq!(run["parentSpanId"], root["spanId"]);
+        assert_eq!(run["status"]["code"], json!(2));
+        assert_eq!(root["kind"], json!(2));
+        assert_eq!(run["traceId"].as_str().unwrap().len(), 32);
+
+        let duration = Instant::now() - start;
+        eprintln!("test_spans_are_exported_with_parents took: {:?}", duration);
+    }
+
+    #[tokio::test]
+    async fn test_run_metrics_are_aggregated() {
+        let start = Instant::now();
+
+        let (pipeline, exporter) = memory_pipeline();
+        let usage = Usage {
+            prompt_tokens: Some(10),
+            completion_tokens: Some(5),
+            cost: Some(0.5),
+            ..Usage::default()
+        };
+        pipeline.record_run("OpenRouter", "grok-4", 80, Some(&usage));
+        pipeline.record_run("OpenRouter", "grok-4", 3_000, None);
+        pipeline.flush().await.unwrap();
+
+        let payload = exporter.last_metrics().unwrap();
+        let metrics = payload["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap();
+        let find = |name: &str| metrics.iter().find(|m| m["name"] == name).unwrap().clone();
+
+        let errors = find("polytheus.errors");
+        assert_eq!(errors["sum"]["dataPoints"][0]["asInt"], json!("1"));
+        let histogram = find("polytheus.request.duration")["histogram"]["dataPoints"][0].clone();
+        assert_eq!(histogram["count"], json!("2"));
+        assert_eq!(histogram["bucketCounts"][1], json!("1"));
+        assert_eq!(histogram["bucketCounts"][6], json!("1"));
+        let cost = find("polytheus.cost");
+        assert_eq!(cost["sum"]["dataPoints"][0]["asDouble"], json!(0.5));
+
+        let duration = Instant::now() - start;
+        eprintln!("test_run_metrics_are_aggregated took: {:?}", duration);
+    }
+
+    /// OTLP collector on localhost answering `traces_status` to traces and 200 to
+    /// metrics, or never answering when `traces_status` is `None`.
+    ///
+    /// Returns its endpoint and the paths it was sent.
+    fn collector(traces_status: Option<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let paths = Arc::new(Mutex::new(vec![]));
+        let received = paths.clone();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().filter_map(Result::ok) {
+                let mut request = vec![];
+                let mut chunk = [0; 4096];
+                // headers, then a body of `content-length` bytes
+                let complete = |request: &[u8]| {
+                    let text = String::from_utf8_lossy(request);
+                    text.split_once("\r\n\r\n").is_some_and(|(head, body)| {
+                        let length = head
+                            .lines()
+                            .find_map(|line| {
+                                line.to_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|l| l.trim().to_string())
+                            })
+                            .and_then(|l| l.parse::<usize>().ok())
+                            .unwrap_or(0);
+                        body.len() >= length
+                    })
+                };
+                while !complete(&request) {
+                    match stream.read(&mut chunk) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&chunk[..n]),
+                    }
+                }
+                let path = String::from_utf8_lossy(&request)
+                    .split(' ')
+                    .nth(1)
+                    .unwrap_or_default()
+                    .to_string();
+                let status = match (path.ends_with("/v1/traces"), traces_status) {
+                    (_, None) => {
+                        std::thread::sleep(Duration::from_secs(5));
+                        continue;
+                    }
+                    (true, Some(status)) => status,
+                    (false, Some(_)) => 200,
+                };
+                received.lock().unwrap().push(path);
+                let _ = stream.write_all(
+                    format!(
+                        "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        status
+                    )
+                    .as_bytes(),
+                );
+            }
+        });
+        (endpoint, paths)
+    }
+
+    fn otlp_pipeline(endpoint: String, timeout: Duration) -> Pipeline {
+        let pipeline = Pipeline::new(OtelConfig {
+            service_name: "test".to_string(),
+            exporter: ExporterConfig::OtlpHttp {
+                endpoint,
+                headers: vec![],
+                timeout,
+            },
+        });
+        let subscriber = registry().with(pipeline.layer());
+        tracing::subscriber::with_default(subscriber, || info_span!("api.router").in_scope(|| {}));
+        pipeline.record_run("OpenRouter", "grok-4", 80, None);
+        pipeline
+    }
+
+    #[tokio::test]
+    async fn test_otlp_export_is_bounded_and_sends_metrics() {
+        let start = Instant::now();
+
+        let (endpoint, paths) = collector(Some(500));
+        let pipeline = otlp_pipeline(endpoint, Duration::from_secs(2));
+        let error = pipeline.flush().await.unwrap_err();
+        assert!(error.contains("failed with status: 500"), "{}", error);
+        assert_eq!(
+            *paths.lock().unwrap(),
+            vec!["/v1/traces".to_string(), "/v1/metrics".to_string()]
+        );
+
+        let (endpoint, _) = collector(None);
+        let pipeline = otlp_pipeline(endpoint, Duration::from_millis(200));
+        let flushed = Instant::now();
+        let error = pipeline.flush().await.unwrap_err();
+        assert!(flushed.elapsed() < Duration::from_secs(2));
+        assert!(error.contains("timed out"), "{}", error);
+
+        let duration = Instant::now() - start;
+        eprintln!(
+            "test_otlp_export_is_bounded_and_sends_metrics took: {:?}",
+            duration
+        );
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let start = Instant::now();
+
+        assert_eq!(
+            parse_headers("api-key=abc, x-team = polytheus"),
+            vec![
+                ("api-key".to_string(), "abc".to_string()),
+                ("x-team".to_string(), "polytheus".to_string())
+            ]
+        );
+
+        let duration = Instant::now() - start;
+        eprintln!("test_parse_headers took: {:?}", duration);
+    }
+}
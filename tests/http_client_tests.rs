@@ -0,0 +1,56 @@
+use backend::polytheus::HttpConfig;
+use reqwest::Client;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+/// Number of sequential requests sent by each side of the benchmark.
+const REQUESTS: u32 = 5;
+
+/// Public endpoint that doesn't need an API key.
+const URL: &str = "https://openrouter.ai/api/v1/models";
+
+/// Send `REQUESTS` sequential GETs, building a client with `make_client` before each one,
+/// and return the median time of a request.
+async fn median_request_time(mut make_client: impl FnMut() -> Client) -> Duration {
+    let mut times = Vec::with_capacity(REQUESTS as usize);
+    for _ in 0..REQUESTS {
+        let start = Instant::now();
+        let client = make_client();
+        let response = client.get(URL).send().await.expect("request failed");
+        assert!(response.status().is_success(), "{}", response.status());
+        let body: Value = response.json().await.expect("invalid JSON body");
+        assert!(body["data"]
+            .as_array()
+            .is_some_and(|models| !models.is_empty()));
+        times.push(Instant::now() - start);
+    }
+    times.sort();
+    times[times.len() / 2]
+}
+
+/// Compare a new client per request (the old `Client::new()` in `Polytheus::run`) with a
+/// shared client, as reused across warm Lambda invocations.
+#[tokio::test]
+#[ignore = "needs network access to openrouter.ai"]
+async fn test_shared_client_latency() {
+    let start = Instant::now();
+
+    let shared = HttpConfig::default().build_client().unwrap();
+    // warm the shared pool, like a previous Lambda invocation would have done
+    median_request_time(|| shared.clone()).await;
+
+    let fresh = median_request_time(Client::new).await;
+    eprintln!("median request with a new client: {:?}", fresh);
+    let reused = median_request_time(|| shared.clone()).await;
+    eprintln!("median request with a shared client: {:?}", reused);
+    // the shared client skips the TCP and TLS handshakes of every request
+    assert!(
+        reused < fresh,
+        "shared client ({:?}) not faster than a new client ({:?})",
+        reused,
+        fresh
+    );
+
+    let duration = Instant::now() - start;
+    eprintln!("test_shared_client_latency took: {:?}", duration);
+}
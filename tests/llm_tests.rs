@@ -16,11 +16,12 @@ async fn test(model_name: String) {
         input_audio: None,
         input_audio_format: None,
         input_video: None,
+        input_file: None,
     }];
 
     println!("Testing Replicate gpt-4o-mini...");
     match poly.run(&model_name, messages, None).await {
-        Ok(res) => println!("Replicate Success: {}", res),
+        Ok(res) => println!("Replicate Success: {}", res.text),
         Err(e) => eprintln!("Replicate Error: {}", e),
     }
 }
@@ -47,12 +48,13 @@ async fn test_with_image(model_name: String) {
         input_audio: None,
         input_audio_format: None,
         input_video: None,
+        input_file: None,
     }];
 
     println!("Testing Image Input...");
     // Use a vision capable model
     match poly.run(&model_name, messages, None).await {
-        Ok(res) => println!("Image Test Success: {}", res),
+        Ok(res) => println!("Image Test Success: {}", res.text),
         Err(e) => eprintln!("Image Test Error: {}", e),
     }
 }
@@ -70,6 +72,7 @@ async fn test_with_multiple_messages(model_name: String) {
             input_audio: None,
             input_audio_format: None,
             input_video: None,
+            input_file: None,
         },
         Message {
             role: "user".to_string(),
@@ -78,13 +81,14 @@ async fn test_with_multiple_messages(model_name: String) {
             input_audio: None,
             input_audio_format: None,
             input_video: None,
+            input_file: None,
         },
     ];
 
     println!("Testing Image Input...");
     // Use a vision capable model
     match poly.run(&model_name, messages, None).await {
-        Ok(res) => println!("Image Test Success: {}", res),
+        Ok(res) => println!("Image Test Success: {}", res.text),
         Err(e) => eprintln!("Image Test Error: {}", e),
     }
 }